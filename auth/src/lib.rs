@@ -1,4 +1,5 @@
 use std::env;
+use std::sync::OnceLock;
 
 use actix_identity::{Identity, IdentityPolicy, IdentityService};
 use actix_web::{
@@ -12,10 +13,42 @@ use serde::{Deserialize, Serialize};
 
 use errors::Error;
 
+/// How tokens are signed, and how long they're valid for
+#[derive(Clone, Debug)]
+pub struct JwtSettings {
+    pub key: String,
+    pub token_lifetime: Duration,
+}
+
+static JWT_SETTINGS: OnceLock<JwtSettings> = OnceLock::new();
+
+const DEFAULT_TOKEN_LIFETIME_HOURS: i64 = 3;
+
+/// Sets how tokens are signed for the rest of the process, and only has an effect the first time
+/// it's called. Without it, the key is read from `JWT_KEY` when it's first needed.
+pub fn configure(settings: JwtSettings) {
+    let _ = JWT_SETTINGS.set(settings);
+}
+
+fn jwt_settings() -> Result<&'static JwtSettings, Error> {
+    if let Some(settings) = JWT_SETTINGS.get() {
+        return Ok(settings);
+    }
+
+    let key = env::var("JWT_KEY")
+        .map_err(|_| Error::InternalServerError("JWT_KEY must be set".to_string()))?;
+    Ok(JWT_SETTINGS.get_or_init(|| JwtSettings {
+        key,
+        token_lifetime: Duration::hours(DEFAULT_TOKEN_LIFETIME_HOURS),
+    }))
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum Role {
     Player,
     Owner,
+    /// Read-only access to a game, such as a TV showing the scoreboard. Has no `users` row.
+    Spectator,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -29,12 +62,17 @@ pub struct PrivateClaim {
 
 impl PrivateClaim {
     pub fn new(id: i32, user_name: String, game_id: i32, role: Role) -> Self {
+        let token_lifetime = JWT_SETTINGS
+            .get()
+            .map_or(Duration::hours(DEFAULT_TOKEN_LIFETIME_HOURS), |settings| {
+                settings.token_lifetime
+            });
         PrivateClaim {
             id,
             user_name,
             game_id,
             role,
-            exp: (Utc::now() + Duration::hours(3)).timestamp(),
+            exp: (Utc::now() + token_lifetime).timestamp(),
         }
     }
 
@@ -80,14 +118,13 @@ impl IdentityPolicy for AuthHeaderIdentityPolicy {
 }
 
 pub fn create_jwt(private_claim: PrivateClaim) -> Result<String, Error> {
-    let encoding_key = EncodingKey::from_secret(&env::var("JWT_KEY").unwrap().as_ref());
+    let encoding_key = EncodingKey::from_secret(jwt_settings()?.key.as_ref());
     encode(&Header::default(), &private_claim, &encoding_key)
         .map_err(|e| Error::CannotEncodeJwtToken(e.to_string()))
 }
 
 pub fn decode_jwt(token: &str) -> Result<PrivateClaim, Error> {
-    let jwt_key = env::var("JWT_KEY").unwrap();
-    let decoding_key = DecodingKey::from_secret(&jwt_key.as_ref());
+    let decoding_key = DecodingKey::from_secret(jwt_settings()?.key.as_ref());
     decode::<PrivateClaim>(token, &decoding_key, &Validation::default())
         .map(|data| data.claims)
         .map_err(|e| Error::CannotDecodeJwtToken(e.to_string()))
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE chat_messages (
+    id SERIAL PRIMARY KEY,
+    game_id INTEGER NOT NULL REFERENCES games(id),
+    -- null when sent by the game's host, who has no users row
+    user_id INTEGER REFERENCES users(id),
+    body TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX chat_messages_game_id_id_idx ON chat_messages (game_id, id);
+
+SELECT diesel_manage_updated_at('chat_messages');
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- standings of each player as of the end of a scored round
+CREATE TABLE leaderboard_snapshots (
+    id SERIAL PRIMARY KEY,
+    round_id INTEGER NOT NULL REFERENCES rounds(id),
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    score INTEGER NOT NULL,
+    round_score INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (round_id, user_id)
+);
+
+CREATE INDEX leaderboard_snapshots_user_id_idx ON leaderboard_snapshots (user_id);
+
+SELECT diesel_manage_updated_at('leaderboard_snapshots');
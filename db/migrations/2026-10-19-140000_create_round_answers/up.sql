@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- the correct answers a round was scored with
+CREATE TABLE round_answers (
+    id SERIAL PRIMARY KEY,
+    round_id INTEGER NOT NULL REFERENCES rounds(id),
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    answer TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (round_id, question_id)
+);
+
+SELECT diesel_manage_updated_at('round_answers');
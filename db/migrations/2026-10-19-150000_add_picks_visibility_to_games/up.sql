@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE games
+    ADD COLUMN picks_visibility TEXT NOT NULL DEFAULT 'host_only'
+    CHECK (picks_visibility IN ('host_only', 'after_lock', 'after_scoring'));
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX rounds_one_open_round_per_game_idx;
+DROP INDEX user_questions_round_id_user_id_question_id_idx;
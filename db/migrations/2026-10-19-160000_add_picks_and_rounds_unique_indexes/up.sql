@@ -0,0 +1,21 @@
+-- Your SQL goes here
+-- drop duplicate picks left behind by racing requests, keeping the first one
+DELETE FROM user_questions a
+    USING user_questions b
+    WHERE a.id > b.id
+    AND a.round_id = b.round_id
+    AND a.user_id = b.user_id
+    AND a.question_id = b.question_id;
+
+CREATE UNIQUE INDEX user_questions_round_id_user_id_question_id_idx
+    ON user_questions (round_id, user_id, question_id);
+
+-- lock all but the latest open round of each game
+UPDATE rounds SET locked = TRUE
+    WHERE NOT locked
+    AND id NOT IN (SELECT MAX(id) FROM rounds WHERE NOT locked GROUP BY game_id);
+
+-- a game can only have one round open for picks at a time
+CREATE UNIQUE INDEX rounds_one_open_round_per_game_idx
+    ON rounds (game_id)
+    WHERE NOT locked;
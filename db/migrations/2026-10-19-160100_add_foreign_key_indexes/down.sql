@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX game_questions_game_id_idx;
+DROP INDEX rounds_game_id_idx;
+DROP INDEX users_game_id_idx;
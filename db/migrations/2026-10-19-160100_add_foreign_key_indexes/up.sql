@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- user_questions.round_id and chat_messages.game_id are already covered
+-- by the leading column of existing indexes
+CREATE INDEX users_game_id_idx ON users (game_id);
+CREATE INDEX rounds_game_id_idx ON rounds (game_id);
+CREATE INDEX game_questions_game_id_idx ON game_questions (game_id);
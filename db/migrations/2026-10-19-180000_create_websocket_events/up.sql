@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- websocket events too large for a NOTIFY payload, which every instance
+-- reads by the id it's notified of instead
+CREATE TABLE websocket_events (
+    id BIGSERIAL PRIMARY KEY,
+    payload TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
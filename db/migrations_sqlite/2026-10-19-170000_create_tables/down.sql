@@ -0,0 +1,9 @@
+DROP TABLE round_answers;
+DROP TABLE leaderboard_snapshots;
+DROP TABLE chat_messages;
+DROP TABLE user_questions;
+DROP TABLE rounds;
+DROP TABLE users;
+DROP TABLE game_questions;
+DROP TABLE games;
+DROP TABLE questions;
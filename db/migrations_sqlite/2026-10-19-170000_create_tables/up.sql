@@ -0,0 +1,166 @@
+-- The same schema the postgres migrations build up to. Timestamps are stored as text in the
+-- format diesel writes them, so they sort and compare correctly.
+CREATE TABLE questions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+CREATE TABLE games (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug VARCHAR(10),
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    creator TEXT,
+    picks_visibility TEXT NOT NULL DEFAULT 'host_only'
+        CHECK (picks_visibility IN ('host_only', 'after_lock', 'after_scoring'))
+);
+
+CREATE TABLE game_questions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    game_id INTEGER NOT NULL REFERENCES games(id),
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX game_questions_game_id_idx ON game_questions (game_id);
+
+-- ids are never reused, as players' tokens are tied to them
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_name VARCHAR(100) NOT NULL,
+    game_id INTEGER NOT NULL REFERENCES games(id),
+    session_id TEXT,
+    score INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX users_game_id_idx ON users (game_id);
+
+CREATE TABLE rounds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    player_one VARCHAR NOT NULL,
+    player_two VARCHAR NOT NULL,
+    game_id INTEGER NOT NULL REFERENCES games(id),
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    locked BOOLEAN NOT NULL DEFAULT FALSE,
+    finished BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX rounds_game_id_idx ON rounds (game_id);
+
+-- a game can only have one round open for picks at a time
+CREATE UNIQUE INDEX rounds_one_open_round_per_game_idx
+    ON rounds (game_id)
+    WHERE NOT locked;
+
+CREATE TABLE user_questions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    round_id INTEGER NOT NULL REFERENCES rounds(id),
+    answer VARCHAR(255) NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+CREATE UNIQUE INDEX user_questions_round_id_user_id_question_id_idx
+    ON user_questions (round_id, user_id, question_id);
+
+CREATE TABLE chat_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    game_id INTEGER NOT NULL REFERENCES games(id),
+    -- null when sent by the game's host, who has no users row
+    user_id INTEGER REFERENCES users(id),
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX chat_messages_game_id_id_idx ON chat_messages (game_id, id);
+
+-- standings of each player as of the end of a scored round
+CREATE TABLE leaderboard_snapshots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    round_id INTEGER NOT NULL REFERENCES rounds(id),
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    score INTEGER NOT NULL,
+    round_score INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    UNIQUE (round_id, user_id)
+);
+
+CREATE INDEX leaderboard_snapshots_user_id_idx ON leaderboard_snapshots (user_id);
+
+-- the correct answers a round was scored with
+CREATE TABLE round_answers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    round_id INTEGER NOT NULL REFERENCES rounds(id),
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    answer TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    UNIQUE (round_id, question_id)
+);
+
+-- what diesel_manage_updated_at does for the postgres tables
+
+CREATE TRIGGER questions_set_updated_at AFTER UPDATE ON questions
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE questions SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER games_set_updated_at AFTER UPDATE ON games
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE games SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER game_questions_set_updated_at AFTER UPDATE ON game_questions
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE game_questions SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER users_set_updated_at AFTER UPDATE ON users
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE users SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER rounds_set_updated_at AFTER UPDATE ON rounds
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE rounds SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER user_questions_set_updated_at AFTER UPDATE ON user_questions
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE user_questions SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER chat_messages_set_updated_at AFTER UPDATE ON chat_messages
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE chat_messages SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER leaderboard_snapshots_set_updated_at AFTER UPDATE ON leaderboard_snapshots
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE leaderboard_snapshots SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER round_answers_set_updated_at AFTER UPDATE ON round_answers
+    FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE round_answers SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+    END;
@@ -1,32 +1,83 @@
 #[macro_use]
 extern crate diesel;
 #[macro_use]
+extern crate diesel_migrations;
+#[macro_use]
 extern crate log;
 
 use std::env;
 
-use diesel::pg::PgConnection;
-use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
-use r2d2::Error;
+use diesel_async::pooled_connection::deadpool::{Object, Pool, PoolError};
+use diesel_async::pooled_connection::AsyncDieselConnectionManager;
 
-pub type PgPool = Pool<ConnectionManager<PgConnection>>;
-pub type Connection = PooledConnection<ConnectionManager<PgConnection>>;
+pub mod migrations;
 pub mod models;
+pub mod repository;
 pub mod schema;
+pub mod sql_types;
 mod utils;
 
-pub fn get_conn(pool: &PgPool) -> Result<Connection, Error> {
-    pool.get().map_err(|err| {
+#[cfg(not(feature = "sqlite"))]
+pub type DbConnection = diesel_async::AsyncPgConnection;
+/// SQLite connections are blocking, so queries run on a background thread
+#[cfg(feature = "sqlite")]
+pub type DbConnection =
+    diesel_async::sync_connection_wrapper::SyncConnectionWrapper<diesel::SqliteConnection>;
+
+pub type DbPool = Pool<DbConnection>;
+pub type Connection = Object<DbConnection>;
+
+pub async fn get_conn(pool: &DbPool) -> Result<Connection, PoolError> {
+    pool.get().await.inspect_err(|err| {
         error!("Failed to get connection - {}", err.to_string());
-        err.into()
     })
 }
 
-pub fn new_pool() -> PgPool {
+#[cfg(not(feature = "sqlite"))]
+fn connection_manager(database_url: String) -> AsyncDieselConnectionManager<DbConnection> {
+    AsyncDieselConnectionManager::<DbConnection>::new(database_url)
+}
+
+/// SQLite doesn't enforce foreign keys unless asked to, and fails right away when another
+/// connection is writing, rather than waiting for it
+#[cfg(feature = "sqlite")]
+fn connection_manager(database_url: String) -> AsyncDieselConnectionManager<DbConnection> {
+    use diesel_async::pooled_connection::ManagerConfig;
+    use diesel_async::{AsyncConnection, SimpleAsyncConnection};
+
+    let mut config = ManagerConfig::default();
+    config.custom_setup = Box::new(|database_url| {
+        Box::pin(async move {
+            let mut connection = DbConnection::establish(database_url).await?;
+            connection
+                .batch_execute(
+                    "PRAGMA foreign_keys = ON; \
+                     PRAGMA busy_timeout = 5000; \
+                     PRAGMA journal_mode = WAL;",
+                )
+                .await
+                .map_err(diesel::ConnectionError::CouldntSetupConfiguration)?;
+
+            Ok(connection)
+        })
+    });
+
+    AsyncDieselConnectionManager::<DbConnection>::new_with_config(database_url, config)
+}
+
+/// Creates a pool for the database at `DATABASE_URL`, with the default number of connections
+pub fn new_pool() -> DbPool {
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
 
-    Pool::builder()
-        .build(manager)
+    Pool::builder(connection_manager(database_url))
+        .build()
+        .expect("failed to create db pool")
+}
+
+/// Creates a pool for the database at `database_url`, holding up to `max_size` connections
+pub fn create_pool(database_url: String, max_size: usize) -> DbPool {
+    Pool::builder(connection_manager(database_url))
+        .max_size(max_size)
+        .build()
         .expect("failed to create db pool")
 }
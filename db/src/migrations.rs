@@ -0,0 +1,120 @@
+use diesel::migration::MigrationSource;
+use diesel::sql_types::Bool;
+use diesel::{Connection, ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use serde::Serialize;
+
+use errors::Error;
+
+use crate::DbConnection;
+
+#[cfg(not(feature = "sqlite"))]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+#[cfg(feature = "sqlite")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+type Backend = <DbConnection as AsyncConnection>::Backend;
+
+/// Migrations are run on a blocking connection, as that's what diesel_migrations works with
+#[cfg(not(feature = "sqlite"))]
+type MigrationConnection =
+    diesel_async::async_connection_wrapper::AsyncConnectionWrapper<DbConnection>;
+#[cfg(feature = "sqlite")]
+type MigrationConnection = diesel::SqliteConnection;
+
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+const MIGRATIONS_TABLE_EXISTS: &str =
+    "SELECT to_regclass('__diesel_schema_migrations') IS NOT NULL AS present";
+#[cfg(feature = "sqlite")]
+const MIGRATIONS_TABLE_EXISTS: &str = "SELECT EXISTS (SELECT 1 FROM sqlite_master \
+     WHERE type = 'table' AND name = '__diesel_schema_migrations') AS present";
+
+#[derive(QueryableByName)]
+struct TableExists {
+    #[diesel(sql_type = Bool)]
+    present: bool,
+}
+
+/// Where the database's schema is at, compared to the migrations built into the app
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SchemaStatus {
+    /// The newest migration that has been run, if any
+    pub version: Option<String>,
+    /// Versions of the migrations that haven't been run yet, oldest first
+    pub pending: Vec<String>,
+}
+
+impl SchemaStatus {
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+fn migration_error<E: ToString>(error: E) -> Error {
+    Error::InternalServerError(format!("Migration failed - {}", error.to_string()))
+}
+
+/// Versions of every migration built into the app, oldest first
+pub fn migration_versions() -> Result<Vec<String>, Error> {
+    let mut versions: Vec<String> = MigrationSource::<Backend>::migrations(&MIGRATIONS)
+        .map_err(migration_error)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+    versions.sort();
+
+    Ok(versions)
+}
+
+/// Only reads from the database, so it's fine to call from health checks, and with a read-only
+/// role. A database without diesel's table of migrations hasn't had any run.
+pub async fn schema_status(conn: &mut DbConnection) -> Result<SchemaStatus, Error> {
+    use self::__diesel_schema_migrations::dsl::{__diesel_schema_migrations, version};
+
+    let table = diesel::sql_query(MIGRATIONS_TABLE_EXISTS)
+        .get_result::<TableExists>(conn)
+        .await?;
+    let applied = if table.present {
+        __diesel_schema_migrations
+            .select(version)
+            .order(version.asc())
+            .load::<String>(conn)
+            .await?
+    } else {
+        Vec::new()
+    };
+    let pending = migration_versions()?
+        .into_iter()
+        .filter(|migration| !applied.contains(migration))
+        .collect();
+
+    Ok(SchemaStatus {
+        version: applied.last().cloned(),
+        pending,
+    })
+}
+
+/// Runs any migrations that haven't been run yet, returning the versions that were
+pub async fn run_pending_migrations(database_url: String) -> Result<Vec<String>, Error> {
+    tokio::task::spawn_blocking(move || {
+        let mut connection = <MigrationConnection as Connection>::establish(&database_url)
+            .map_err(|err| Error::PoolError(err.to_string()))?;
+        let versions = connection
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(migration_error)?
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        Ok(versions)
+    })
+    .await
+    .map_err(migration_error)?
+}
@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use diesel::{self, ExpressionMethods, NullableExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+
+use errors::Error;
+
+use crate::models::Game;
+use crate::schema::{chat_messages, users};
+use crate::DbConnection;
+
+#[derive(Associations, Debug, Deserialize, Identifiable, Queryable, Serialize)]
+#[diesel(belongs_to(Game))]
+pub struct ChatMessage {
+    pub id: i32,
+    pub game_id: i32,
+    pub user_id: Option<i32>,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = chat_messages)]
+pub struct NewChatMessage {
+    pub game_id: i32,
+    pub user_id: Option<i32>,
+    pub body: String,
+}
+
+/// A chat message with the name of the player that sent it. Messages from the host have no
+/// `user_id` or `user_name`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Queryable, Serialize)]
+pub struct ChatMessageDetails {
+    pub id: i32,
+    pub game_id: i32,
+    pub user_id: Option<i32>,
+    pub user_name: Option<String>,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ChatMessage {
+    pub async fn create(
+        conn: &mut DbConnection,
+        game_id: i32,
+        user_id: Option<i32>,
+        body: String,
+    ) -> Result<ChatMessageDetails, Error> {
+        let message: ChatMessage = diesel::insert_into(chat_messages::table)
+            .values(NewChatMessage {
+                game_id,
+                user_id,
+                body,
+            })
+            .get_result(conn)
+            .await?;
+
+        let user_name = match message.user_id {
+            Some(user_id) => Some(
+                users::table
+                    .find(user_id)
+                    .select(users::user_name)
+                    .get_result::<String>(conn)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        Ok(ChatMessageDetails {
+            id: message.id,
+            game_id: message.game_id,
+            user_id: message.user_id,
+            user_name,
+            body: message.body,
+            created_at: message.created_at,
+        })
+    }
+
+    /// The newest `limit` messages sent before the message with id `before`, oldest first
+    pub async fn find_page_by_game_id(
+        conn: &mut DbConnection,
+        game_id: i32,
+        before: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessageDetails>, Error> {
+        use chat_messages::dsl::{
+            body, chat_messages as chat_messages_table, created_at, game_id as game_id_field, id,
+            user_id,
+        };
+
+        let mut query = chat_messages_table
+            .left_join(users::table)
+            .select((
+                id,
+                game_id_field,
+                user_id,
+                users::user_name.nullable(),
+                body,
+                created_at,
+            ))
+            .filter(game_id_field.eq(game_id))
+            .order(id.desc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(before) = before {
+            query = query.filter(id.lt(before));
+        }
+
+        let mut messages = query.get_results::<ChatMessageDetails>(conn).await?;
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    /// How many messages the player, or the host when `user_id` is None, has sent since `since`
+    pub async fn count_sent_since(
+        conn: &mut DbConnection,
+        game_id: i32,
+        user_id: Option<i32>,
+        since: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        use chat_messages::dsl::{
+            chat_messages as chat_messages_table, created_at, game_id as game_id_field,
+            user_id as user_id_field,
+        };
+
+        let query = chat_messages_table
+            .filter(game_id_field.eq(game_id))
+            .filter(created_at.gt(since))
+            .into_boxed();
+        let query = match user_id {
+            Some(user_id) => query.filter(user_id_field.eq(user_id)),
+            None => query.filter(user_id_field.is_null()),
+        };
+
+        let count = query.count().get_result(conn).await?;
+
+        Ok(count)
+    }
+
+    pub async fn delete(
+        conn: &mut DbConnection,
+        game_id: i32,
+        message_id: i32,
+    ) -> Result<(), Error> {
+        use chat_messages::dsl::{chat_messages as chat_messages_table, game_id as game_id_field};
+
+        let deleted = diesel::delete(
+            chat_messages_table
+                .find(message_id)
+                .filter(game_id_field.eq(game_id)),
+        )
+        .execute(conn)
+        .await?;
+
+        if deleted == 0 {
+            return Err(Error::NotFound("Chat message not found".to_string()));
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,13 @@
 use chrono::{DateTime, Utc};
-use diesel::{self, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{
+    self,
+    backend::Backend,
+    deserialize::{self, FromSql},
+    serialize::{self, Output, ToSql},
+    sql_types::Text,
+    ExpressionMethods, QueryDsl,
+};
+use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
 
 use auth::{create_jwt, PrivateClaim, Role};
@@ -7,23 +15,79 @@ use errors::Error;
 
 use crate::schema::games;
 use crate::utils::create_slug_from_id;
+use crate::DbConnection;
 
-#[derive(Debug, Identifiable, Serialize, Deserialize, Queryable)]
+/// Who can see everyone's picks for a round. The host always can.
+#[derive(
+    AsExpression, Clone, Copy, Debug, Default, Deserialize, FromSqlRow, PartialEq, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[diesel(sql_type = Text)]
+pub enum PicksVisibility {
+    #[default]
+    HostOnly,
+    /// Once the round is locked and picks can't change
+    AfterLock,
+    /// Once the round has been scored
+    AfterScoring,
+}
+
+impl PicksVisibility {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PicksVisibility::HostOnly => "host_only",
+            PicksVisibility::AfterLock => "after_lock",
+            PicksVisibility::AfterScoring => "after_scoring",
+        }
+    }
+}
+
+impl<DB> ToSql<Text, DB> for PicksVisibility
+where
+    DB: Backend,
+    str: ToSql<Text, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        ToSql::<Text, DB>::to_sql(self.as_str(), out)
+    }
+}
+
+impl<DB> FromSql<Text, DB> for PicksVisibility
+where
+    DB: Backend,
+    String: FromSql<Text, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, DB>>::from_sql(bytes)?.as_str() {
+            "host_only" => Ok(PicksVisibility::HostOnly),
+            "after_lock" => Ok(PicksVisibility::AfterLock),
+            "after_scoring" => Ok(PicksVisibility::AfterScoring),
+            value => Err(format!("Unknown picks visibility: {}", value).into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Identifiable, Serialize, Deserialize, Queryable)]
 pub struct Game {
     pub id: i32,
     pub slug: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub creator: Option<String>,
+    pub picks_visibility: PicksVisibility,
 }
 
 impl Game {
-    pub fn create(conn: &PgConnection) -> Result<Game, Error> {
+    pub async fn create(
+        conn: &mut DbConnection,
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error> {
         use games::{dsl, table};
 
         let game: Game = diesel::insert_into(table)
-            .default_values()
-            .get_result(conn)?;
+            .values(dsl::picks_visibility.eq(picks_visibility))
+            .get_result(conn)
+            .await?;
         let new_slug = create_slug_from_id(game.id);
         let jwt = create_jwt(PrivateClaim::new(
             game.id,
@@ -33,23 +97,42 @@ impl Game {
         ))?;
         let updated_game = diesel::update(dsl::games.find(game.id))
             .set((dsl::slug.eq(new_slug), dsl::creator.eq(jwt)))
-            .get_result::<Game>(conn)?;
+            .get_result::<Game>(conn)
+            .await?;
 
         Ok(updated_game)
     }
 
-    pub fn find_by_id(conn: &PgConnection, id: i32) -> Result<Game, Error> {
+    pub async fn find_by_id(conn: &mut DbConnection, id: i32) -> Result<Game, Error> {
         use crate::schema::games::dsl::games;
 
-        let game = games.find(id).first(conn)?;
+        let game = games.find(id).first(conn).await?;
 
         Ok(game)
     }
 
-    pub fn find_by_slug(conn: &PgConnection, slug_value: &str) -> Result<Game, Error> {
+    pub async fn find_by_slug(conn: &mut DbConnection, slug_value: &str) -> Result<Game, Error> {
         use crate::schema::games::dsl::{games, slug};
 
-        let game = games.filter(slug.eq(slug_value)).first::<Game>(conn)?;
+        let game = games
+            .filter(slug.eq(slug_value))
+            .first::<Game>(conn)
+            .await?;
+
+        Ok(game)
+    }
+
+    pub async fn set_picks_visibility(
+        conn: &mut DbConnection,
+        id: i32,
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error> {
+        use crate::schema::games::dsl::{games, picks_visibility as picks_visibility_field};
+
+        let game = diesel::update(games.find(id))
+            .set(picks_visibility_field.eq(picks_visibility))
+            .get_result(conn)
+            .await?;
 
         Ok(game)
     }
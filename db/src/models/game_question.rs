@@ -1,15 +1,17 @@
 use chrono::{DateTime, Utc};
-use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
 
 use errors::Error;
 
 use crate::models::{Game, Question, QuestionDetails};
 use crate::schema::game_questions;
+use crate::DbConnection;
 
 #[derive(Associations, Debug, Identifiable, Serialize, Deserialize, Queryable)]
-#[belongs_to(Game)]
-#[belongs_to(Question)]
+#[diesel(belongs_to(Game))]
+#[diesel(belongs_to(Question))]
 pub struct GameQuestion {
     pub id: i32,
     pub game_id: i32,
@@ -19,15 +21,15 @@ pub struct GameQuestion {
 }
 
 #[derive(Insertable)]
-#[table_name = "game_questions"]
+#[diesel(table_name = game_questions)]
 pub struct NewGameQuestion {
     pub game_id: i32,
     pub question_id: i32,
 }
 
 impl GameQuestion {
-    pub fn create(
-        conn: &PgConnection,
+    pub async fn create(
+        conn: &mut DbConnection,
         game_id: i32,
         question_id: i32,
     ) -> Result<GameQuestion, Error> {
@@ -37,13 +39,14 @@ impl GameQuestion {
                 game_id,
                 question_id,
             })
-            .get_result(conn)?;
+            .get_result(conn)
+            .await?;
 
         Ok(game_question)
     }
 
-    pub fn get_questions_by_game_id(
-        conn: &PgConnection,
+    pub async fn get_questions_by_game_id(
+        conn: &mut DbConnection,
         game_id: i32,
     ) -> Result<Vec<QuestionDetails>, Error> {
         use crate::schema::questions;
@@ -52,7 +55,8 @@ impl GameQuestion {
             .inner_join(questions::dsl::questions)
             .filter(game_questions::dsl::game_id.eq(game_id))
             .select((questions::dsl::id, questions::dsl::body))
-            .get_results::<QuestionDetails>(conn)?;
+            .get_results::<QuestionDetails>(conn)
+            .await?;
 
         Ok(question_results)
     }
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+
+use errors::Error;
+
+use crate::models::{Round, User};
+use crate::schema::{leaderboard_snapshots, rounds};
+use crate::DbConnection;
+
+/// A player's standing as of the end of a scored round
+#[derive(Associations, Clone, Debug, Deserialize, Identifiable, Queryable, Serialize)]
+#[diesel(belongs_to(Round))]
+#[diesel(belongs_to(User))]
+pub struct LeaderboardSnapshot {
+    pub id: i32,
+    pub round_id: i32,
+    pub user_id: i32,
+    pub score: i32,
+    pub round_score: i32,
+    pub rank: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = leaderboard_snapshots)]
+pub struct NewLeaderboardSnapshot {
+    pub round_id: i32,
+    pub user_id: i32,
+    pub score: i32,
+    pub round_score: i32,
+    pub rank: i32,
+}
+
+impl LeaderboardSnapshot {
+    pub async fn create_all(
+        conn: &mut DbConnection,
+        snapshots: Vec<NewLeaderboardSnapshot>,
+    ) -> Result<usize, Error> {
+        let count = insert_all!(conn, leaderboard_snapshots::table, snapshots);
+
+        Ok(count)
+    }
+
+    /// Every snapshot taken for the game, ordered by round, then rank
+    pub async fn find_by_game_id(
+        conn: &mut DbConnection,
+        game_id: i32,
+    ) -> Result<Vec<LeaderboardSnapshot>, Error> {
+        use leaderboard_snapshots::dsl::{id, rank, round_id};
+
+        let snapshots = leaderboard_snapshots::table
+            .inner_join(rounds::table)
+            .filter(rounds::dsl::game_id.eq(game_id))
+            .select(leaderboard_snapshots::all_columns)
+            .order((round_id.asc(), rank.asc(), id.asc()))
+            .get_results(conn)
+            .await?;
+
+        Ok(snapshots)
+    }
+}
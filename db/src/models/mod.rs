@@ -1,13 +1,43 @@
+/// Inserts all of `rows` into `table` in a single statement. diesel-async can't build SQLite's
+/// batch inserts, so there it runs on the blocking connection underneath.
+macro_rules! insert_all {
+    ($conn:expr, $table:expr, $rows:expr) => {{
+        #[cfg(not(feature = "sqlite"))]
+        let count = diesel::insert_into($table)
+            .values($rows)
+            .execute($conn)
+            .await?;
+        #[cfg(feature = "sqlite")]
+        let count = {
+            let rows = $rows;
+            $conn
+                .spawn_blocking(move |conn| {
+                    diesel::RunQueryDsl::execute(diesel::insert_into($table).values(rows), conn)
+                })
+                .await?
+        };
+
+        count
+    }};
+}
+
+mod chat_message;
 mod game;
 mod game_question;
+mod leaderboard_snapshot;
 mod question;
 mod round;
+mod round_answer;
+mod unique_index;
 mod user;
 mod user_question;
 
+pub use self::chat_message::*;
 pub use self::game::*;
 pub use self::game_question::*;
+pub use self::leaderboard_snapshot::*;
 pub use self::question::*;
 pub use self::round::*;
+pub use self::round_answer::*;
 pub use self::user::*;
 pub use self::user_question::*;
@@ -1,12 +1,14 @@
 use chrono::{DateTime, Utc};
-use diesel::{PgConnection, QueryDsl, RunQueryDsl};
+use diesel::QueryDsl;
+use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
 
 use errors::Error;
 
 use crate::schema::questions;
+use crate::DbConnection;
 
-#[derive(Debug, Identifiable, Serialize, Deserialize, Queryable)]
+#[derive(Clone, Debug, Identifiable, Serialize, Deserialize, Queryable)]
 pub struct Question {
     pub id: i32,
     pub body: String,
@@ -14,17 +16,17 @@ pub struct Question {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize, Queryable, Serialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Queryable, Serialize, PartialEq)]
 pub struct QuestionDetails {
     pub id: i32,
     pub body: String,
 }
 
 impl Question {
-    pub fn get_all(conn: &PgConnection) -> Result<Vec<Question>, Error> {
+    pub async fn get_all(conn: &mut DbConnection) -> Result<Vec<Question>, Error> {
         use crate::schema::questions::dsl::{body, questions};
 
-        let all_questions = questions.order(body).load::<Question>(conn)?;
+        let all_questions = questions.order(body).load::<Question>(conn).await?;
 
         Ok(all_questions)
     }
@@ -1,14 +1,17 @@
 use chrono::{DateTime, Utc};
-use diesel::{self, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{self, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
 
 use errors::Error;
 
+use crate::models::unique_index::ONE_OPEN_ROUND_PER_GAME;
 use crate::models::Game;
 use crate::schema::rounds::{self, table};
+use crate::DbConnection;
 
-#[derive(Associations, Debug, Deserialize, Identifiable, Serialize, Queryable)]
-#[belongs_to(Game)]
+#[derive(Associations, Clone, Debug, Deserialize, Identifiable, Serialize, Queryable)]
+#[diesel(belongs_to(Game))]
 pub struct Round {
     pub id: i32,
     pub player_one: String,
@@ -21,7 +24,7 @@ pub struct Round {
 }
 
 #[derive(Insertable)]
-#[table_name = "rounds"]
+#[diesel(table_name = rounds)]
 pub struct NewRound {
     pub player_one: String,
     pub player_two: String,
@@ -29,8 +32,8 @@ pub struct NewRound {
 }
 
 impl Round {
-    pub fn create(
-        conn: &PgConnection,
+    pub async fn create(
+        conn: &mut DbConnection,
         game_id: i32,
         player_one: String,
         player_two: String,
@@ -41,35 +44,83 @@ impl Round {
                 player_two,
                 game_id,
             })
-            .get_result(conn)?;
+            .get_result(conn)
+            .await
+            .map_err(|err| ONE_OPEN_ROUND_PER_GAME.map_err(err))?;
 
         Ok(round)
     }
 
-    pub fn get_active_round_by_game_id(conn: &PgConnection, game_id: i32) -> Result<Round, Error> {
+    pub async fn get_active_round_by_game_id(
+        conn: &mut DbConnection,
+        game_id: i32,
+    ) -> Result<Round, Error> {
         use rounds::dsl::{game_id as game_id_field, locked, rounds as rounds_table};
 
         let round = rounds_table
             .filter(game_id_field.eq(game_id))
             .filter(locked.eq(false))
-            .first(conn)?;
+            .first(conn)
+            .await?;
+
+        Ok(round)
+    }
+
+    /// Same as `get_active_round_by_game_id`, holding a share lock on the round until the
+    /// transaction ends, so it can't be locked while picks are being saved. SQLite has no row
+    /// locks, but only lets one transaction write at a time, which keeps them apart all the same.
+    pub async fn get_active_round_by_game_id_for_share(
+        conn: &mut DbConnection,
+        game_id: i32,
+    ) -> Result<Round, Error> {
+        use rounds::dsl::{game_id as game_id_field, locked, rounds as rounds_table};
+
+        let query = rounds_table
+            .filter(game_id_field.eq(game_id))
+            .filter(locked.eq(false));
+        #[cfg(not(feature = "sqlite"))]
+        let query = query.for_share();
+        let round = query.first(conn).await?;
+
+        Ok(round)
+    }
+
+    /// Same as `get_active_round_by_game_id`, holding a row lock on the round until the
+    /// transaction ends
+    pub async fn get_active_round_by_game_id_for_update(
+        conn: &mut DbConnection,
+        game_id: i32,
+    ) -> Result<Round, Error> {
+        use rounds::dsl::{game_id as game_id_field, locked, rounds as rounds_table};
+
+        let query = rounds_table
+            .filter(game_id_field.eq(game_id))
+            .filter(locked.eq(false));
+        #[cfg(not(feature = "sqlite"))]
+        let query = query.for_update();
+        let round = query.first(conn).await?;
 
         Ok(round)
     }
 
-    pub fn get_latest_round_by_game_id(conn: &PgConnection, game_id: i32) -> Result<Round, Error> {
-        use rounds::dsl::{created_at, game_id as game_id_field, rounds as rounds_table};
+    pub async fn get_latest_round_by_game_id(
+        conn: &mut DbConnection,
+        game_id: i32,
+    ) -> Result<Round, Error> {
+        use rounds::dsl::{created_at, game_id as game_id_field, id, rounds as rounds_table};
 
+        // SQLite's timestamps only go down to milliseconds, so rounds can be created at the same time
         let round = rounds_table
             .filter(game_id_field.eq(game_id))
-            .order(created_at.desc())
-            .get_result::<Round>(conn)?;
+            .order((created_at.desc(), id.desc()))
+            .get_result::<Round>(conn)
+            .await?;
 
         Ok(round)
     }
 
-    pub fn get_unfinished_round_by_game_id(
-        conn: &PgConnection,
+    pub async fn get_unfinished_round_by_game_id(
+        conn: &mut DbConnection,
         game_id: i32,
     ) -> Result<Round, Error> {
         use rounds::dsl::{finished, game_id as game_id_field, locked, rounds as rounds_table};
@@ -78,27 +129,49 @@ impl Round {
             .filter(game_id_field.eq(game_id))
             .filter(locked.eq(true))
             .filter(finished.eq(false))
-            .first(conn)?;
+            .first(conn)
+            .await?;
+
+        Ok(round)
+    }
+
+    /// Same as `get_unfinished_round_by_game_id`, holding a row lock on the round until the
+    /// transaction ends
+    pub async fn get_unfinished_round_by_game_id_for_update(
+        conn: &mut DbConnection,
+        game_id: i32,
+    ) -> Result<Round, Error> {
+        use rounds::dsl::{finished, game_id as game_id_field, locked, rounds as rounds_table};
+
+        let query = rounds_table
+            .filter(game_id_field.eq(game_id))
+            .filter(locked.eq(true))
+            .filter(finished.eq(false));
+        #[cfg(not(feature = "sqlite"))]
+        let query = query.for_update();
+        let round = query.first(conn).await?;
 
         Ok(round)
     }
 
-    pub fn lock(conn: &PgConnection, round_id: i32) -> Result<(), Error> {
+    pub async fn lock(conn: &mut DbConnection, round_id: i32) -> Result<(), Error> {
         use rounds::dsl::{locked, rounds as rounds_table};
 
         diesel::update(rounds_table.find(round_id))
             .set(locked.eq(true))
-            .execute(conn)?;
+            .execute(conn)
+            .await?;
 
         Ok(())
     }
 
-    pub fn finish(conn: &PgConnection, round_id: i32) -> Result<(), Error> {
+    pub async fn finish(conn: &mut DbConnection, round_id: i32) -> Result<(), Error> {
         use rounds::dsl::{finished, rounds as rounds_table};
 
         diesel::update(rounds_table.find(round_id))
             .set(finished.eq(true))
-            .execute(conn)?;
+            .execute(conn)
+            .await?;
 
         Ok(())
     }
@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+
+use errors::Error;
+
+use crate::models::{Question, Round};
+use crate::schema::round_answers;
+use crate::DbConnection;
+
+/// The correct answer to a question, saved when the round is scored
+#[derive(Associations, Clone, Debug, Deserialize, Identifiable, Queryable, Serialize)]
+#[diesel(belongs_to(Round))]
+#[diesel(belongs_to(Question))]
+pub struct RoundAnswer {
+    pub id: i32,
+    pub round_id: i32,
+    pub question_id: i32,
+    pub answer: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = round_answers)]
+pub struct NewRoundAnswer {
+    pub round_id: i32,
+    pub question_id: i32,
+    pub answer: String,
+}
+
+impl RoundAnswer {
+    pub async fn create_all(
+        conn: &mut DbConnection,
+        answers: Vec<NewRoundAnswer>,
+    ) -> Result<usize, Error> {
+        let count = insert_all!(conn, round_answers::table, answers);
+
+        Ok(count)
+    }
+
+    pub async fn find_by_round(
+        conn: &mut DbConnection,
+        round_id: i32,
+    ) -> Result<Vec<RoundAnswer>, Error> {
+        use round_answers::dsl::{question_id, round_id as round_id_field};
+
+        let answers = round_answers::table
+            .filter(round_id_field.eq(round_id))
+            .order(question_id.asc())
+            .get_results(conn)
+            .await?;
+
+        Ok(answers)
+    }
+}
@@ -0,0 +1,47 @@
+use diesel::result::{DatabaseErrorKind, Error as DBError};
+
+use errors::Error;
+
+/// A unique index, and what to tell the client when a write would break it
+pub(crate) struct UniqueIndex {
+    pub name: &'static str,
+    /// SQLite doesn't name the index it failed on, only the columns in it
+    pub columns: &'static str,
+    pub message: &'static str,
+}
+
+pub(crate) const ONE_PICK_PER_QUESTION: UniqueIndex = UniqueIndex {
+    name: "user_questions_round_id_user_id_question_id_idx",
+    columns: "user_questions.round_id, user_questions.user_id, user_questions.question_id",
+    message: "User has already chosen picks for this round",
+};
+
+pub(crate) const ONE_OPEN_ROUND_PER_GAME: UniqueIndex = UniqueIndex {
+    name: "rounds_one_open_round_per_game_idx",
+    columns: "rounds.game_id",
+    message: "The game already has an open round",
+};
+
+impl UniqueIndex {
+    fn is_violated_by(&self, error: &DBError) -> bool {
+        match error {
+            DBError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                match info.constraint_name() {
+                    Some(name) => name == self.name,
+                    None => info.message() == format!("UNIQUE constraint failed: {}", self.columns),
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Turns a violation of this index into a conflict explaining it, and anything else into the
+    /// usual error
+    pub fn map_err(&self, error: DBError) -> Error {
+        if self.is_violated_by(&error) {
+            return Error::Conflict(self.message.to_string());
+        }
+
+        error.into()
+    }
+}
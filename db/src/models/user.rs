@@ -1,13 +1,16 @@
 use chrono::{DateTime, Utc};
-use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{sql_types::Integer, ExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 
 use auth::{create_jwt, PrivateClaim, Role};
 use errors::Error;
 
 use crate::schema::users;
+use crate::DbConnection;
 
-#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize)]
+#[derive(Clone, Debug, Queryable, Identifiable, Serialize, Deserialize)]
 pub struct User {
     pub id: i32,
     pub user_name: String,
@@ -19,14 +22,34 @@ pub struct User {
 }
 
 #[derive(Insertable)]
-#[table_name = "users"]
+#[diesel(table_name = users)]
 pub struct NewUser {
     pub user_name: String,
     pub game_id: i32,
 }
 
+/// Points a player got in a round, from `User::add_round_scores`
+#[derive(Debug, PartialEq, QueryableByName)]
+pub struct RoundScore {
+    #[diesel(sql_type = Integer)]
+    pub user_id: i32,
+    #[diesel(sql_type = Integer)]
+    pub round_score: i32,
+}
+
+/// Points each player's picks in the round `$1` earned, matched against its saved answers
+const ROUND_SCORES_QUERY: &str =
+    "SELECT user_questions.user_id, CAST(COUNT(*) AS INTEGER) AS round_score \
+     FROM user_questions \
+     INNER JOIN round_answers \
+         ON round_answers.round_id = user_questions.round_id \
+         AND round_answers.question_id = user_questions.question_id \
+         AND round_answers.answer = user_questions.answer \
+     WHERE user_questions.round_id = $1 \
+     GROUP BY user_questions.user_id";
+
 #[derive(Deserialize, Identifiable, Queryable, Serialize)]
-#[table_name = "users"]
+#[diesel(table_name = users)]
 pub struct UserDetails {
     pub id: i32,
     pub user_name: String,
@@ -35,8 +58,8 @@ pub struct UserDetails {
 }
 
 impl User {
-    pub fn create(
-        connection: &PgConnection,
+    pub async fn create(
+        connection: &mut DbConnection,
         user_name: String,
         game_id: i32,
     ) -> Result<User, Error> {
@@ -44,7 +67,8 @@ impl User {
 
         let result: User = diesel::insert_into(table)
             .values(NewUser { user_name, game_id })
-            .get_result(connection)?;
+            .get_result(connection)
+            .await?;
 
         let jwt = create_jwt(PrivateClaim::new(
             result.id,
@@ -52,15 +76,16 @@ impl User {
             game_id,
             Role::Player,
         ))?;
-        let result: User = diesel::update(table)
+        let result: User = diesel::update(table.filter(dsl::id.eq(result.id)))
             .set(dsl::session_id.eq(jwt))
-            .get_result(connection)?;
+            .get_result(connection)
+            .await?;
 
         Ok(result)
     }
 
-    pub fn find_all_by_game_id(
-        connection: &PgConnection,
+    pub async fn find_all_by_game_id(
+        connection: &mut DbConnection,
         game_id: i32,
     ) -> Result<Vec<UserDetails>, Error> {
         use crate::schema::users::dsl::{game_id as game_id_field, id, score, user_name, users};
@@ -68,38 +93,155 @@ impl User {
         let results = users
             .select((id, user_name, game_id_field, score))
             .filter(game_id_field.eq(game_id))
-            .get_results::<UserDetails>(connection)?;
+            .get_results::<UserDetails>(connection)
+            .await?;
 
         Ok(results)
     }
 
-    pub fn find_by_game_id_and_name(
-        connection: &PgConnection,
+    pub async fn find_by_game_id_and_name(
+        connection: &mut DbConnection,
         game_id: i32,
-        user_name: &String,
+        user_name: &str,
     ) -> Result<User, Error> {
         use crate::schema::users::dsl::{game_id as gi, user_name as un, users};
 
         let user: User = users
             .filter(un.eq(user_name))
             .filter(gi.eq(game_id))
-            .first::<User>(connection)?;
+            .first::<User>(connection)
+            .await?;
 
         Ok(user)
     }
 
-    pub fn add_score(connection: &PgConnection, user_id: i32, amount: i32) -> Result<User, Error> {
-        use crate::schema::users::dsl::{id, score as score_field, users as users_table};
+    pub async fn find_by_game_id_and_id(
+        connection: &mut DbConnection,
+        game_id: i32,
+        user_id: i32,
+    ) -> Result<User, Error> {
+        use crate::schema::users::dsl::{game_id as gi, id, users};
 
-        let score = users_table
-            .select(score_field)
+        let user: User = users
             .filter(id.eq(user_id))
-            .get_result::<i32>(connection)?;
+            .filter(gi.eq(game_id))
+            .first::<User>(connection)
+            .await?;
+
+        Ok(user)
+    }
 
-        let user = diesel::update(users_table.filter(id.eq(user_id)))
-            .set(score_field.eq(score + amount))
-            .get_result(connection)?;
+    /// Gives each player a point for every pick in the round that matches its saved answers, in
+    /// a single statement. Returns the points each player got, leaving out players that got none.
+    #[cfg(not(feature = "sqlite"))]
+    pub async fn add_round_scores(
+        connection: &mut DbConnection,
+        round_id: i32,
+    ) -> Result<Vec<RoundScore>, Error> {
+        let scores = diesel::sql_query(format!(
+            "UPDATE users SET score = users.score + round_scores.round_score \
+             FROM ({}) AS round_scores \
+             WHERE users.id = round_scores.user_id \
+             RETURNING users.id AS user_id, round_scores.round_score",
+            ROUND_SCORES_QUERY
+        ))
+        .bind::<Integer, _>(round_id)
+        .get_results::<RoundScore>(connection)
+        .await?;
+
+        Ok(scores)
+    }
+
+    /// Same as the Postgres version, though SQLite can't return columns from an update's `FROM`
+    /// clause, so the points are read before they're added. Should be run in a transaction.
+    #[cfg(feature = "sqlite")]
+    pub async fn add_round_scores(
+        connection: &mut DbConnection,
+        round_id: i32,
+    ) -> Result<Vec<RoundScore>, Error> {
+        let scores = diesel::sql_query(ROUND_SCORES_QUERY)
+            .bind::<Integer, _>(round_id)
+            .get_results::<RoundScore>(connection)
+            .await?;
+
+        diesel::sql_query(format!(
+            "UPDATE users SET score = users.score + round_scores.round_score \
+             FROM ({}) AS round_scores \
+             WHERE users.id = round_scores.user_id",
+            ROUND_SCORES_QUERY
+        ))
+        .bind::<Integer, _>(round_id)
+        .execute(connection)
+        .await?;
+
+        Ok(scores)
+    }
+
+    pub async fn rename(
+        connection: &mut DbConnection,
+        game_id: i32,
+        user_id: i32,
+        user_name: String,
+    ) -> Result<User, Error> {
+        use crate::schema::users::dsl::{
+            game_id as game_id_field, id, user_name as user_name_field, users as users_table,
+        };
+
+        let user = diesel::update(
+            users_table
+                .filter(id.eq(user_id))
+                .filter(game_id_field.eq(game_id)),
+        )
+        .set(user_name_field.eq(user_name))
+        .get_result(connection)
+        .await?;
 
         Ok(user)
     }
+
+    /// Removes a player from the game, along with their picks, chat messages and leaderboard history
+    pub async fn delete(
+        connection: &mut DbConnection,
+        game_id: i32,
+        user_id: i32,
+    ) -> Result<(), Error> {
+        use crate::schema::users::dsl::{game_id as game_id_field, id, users as users_table};
+        use crate::schema::{chat_messages, leaderboard_snapshots, user_questions};
+
+        connection
+            .transaction::<_, Error, _>(|connection| {
+                async move {
+                    let user = users_table
+                        .filter(id.eq(user_id))
+                        .filter(game_id_field.eq(game_id))
+                        .select(id)
+                        .first::<i32>(connection)
+                        .await?;
+
+                    diesel::delete(
+                        user_questions::table.filter(user_questions::dsl::user_id.eq(user)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        chat_messages::table.filter(chat_messages::dsl::user_id.eq(user)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        leaderboard_snapshots::table
+                            .filter(leaderboard_snapshots::dsl::user_id.eq(user)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(users_table.filter(id.eq(user)))
+                        .execute(connection)
+                        .await?;
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
 }
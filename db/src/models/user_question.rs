@@ -1,17 +1,20 @@
 use chrono::{DateTime, Utc};
-use diesel::{self, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{self, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
 use serde::{Deserialize, Serialize};
 
 use errors::Error;
 
+use crate::models::unique_index::ONE_PICK_PER_QUESTION;
 use crate::models::{Question, Round, User};
 use crate::schema::{user_questions, users};
+use crate::DbConnection;
 
-#[derive(Associations, Deserialize, Queryable, Identifiable, Serialize)]
-#[table_name = "user_questions"]
-#[belongs_to(Round)]
-#[belongs_to(User)]
-#[belongs_to(Question)]
+#[derive(Associations, Clone, Deserialize, Queryable, Identifiable, Serialize)]
+#[diesel(table_name = user_questions)]
+#[diesel(belongs_to(Round))]
+#[diesel(belongs_to(User))]
+#[diesel(belongs_to(Question))]
 pub struct UserQuestion {
     pub id: i32,
     pub user_id: i32,
@@ -23,7 +26,7 @@ pub struct UserQuestion {
 }
 
 #[derive(Insertable, Serialize)]
-#[table_name = "user_questions"]
+#[diesel(table_name = user_questions)]
 pub struct NewUserQuestion {
     pub user_id: i32,
     pub question_id: i32,
@@ -32,7 +35,7 @@ pub struct NewUserQuestion {
 }
 
 #[derive(Deserialize, Identifiable, PartialEq, Queryable, Serialize)]
-#[table_name = "user_questions"]
+#[diesel(table_name = user_questions)]
 pub struct UserAnswer {
     pub id: i32,
     pub question_id: i32,
@@ -42,26 +45,22 @@ pub struct UserAnswer {
 }
 
 impl UserQuestion {
-    pub fn create(
-        conn: &PgConnection,
-        user_id: i32,
-        question_id: i32,
-        round_id: i32,
-        answer: String,
-    ) -> Result<UserQuestion, Error> {
-        let user_question = diesel::insert_into(user_questions::table)
-            .values(NewUserQuestion {
-                user_id,
-                question_id,
-                round_id,
-                answer,
-            })
-            .get_result(conn)?;
-
-        Ok(user_question)
+    /// Saves a batch of picks in a single insert
+    pub async fn create_all(
+        conn: &mut DbConnection,
+        picks: Vec<NewUserQuestion>,
+    ) -> Result<usize, Error> {
+        let insert = async { Ok(insert_all!(conn, user_questions::table, picks)) };
+
+        insert
+            .await
+            .map_err(|err| ONE_PICK_PER_QUESTION.map_err(err))
     }
 
-    pub fn find_by_round(conn: &PgConnection, round_id: i32) -> Result<Vec<UserAnswer>, Error> {
+    pub async fn find_by_round(
+        conn: &mut DbConnection,
+        round_id: i32,
+    ) -> Result<Vec<UserAnswer>, Error> {
         use user_questions::dsl::{
             answer, id, question_id, round_id as round_id_dsl, user_id,
             user_questions as user_questions_table,
@@ -71,13 +70,15 @@ impl UserQuestion {
             .inner_join(users::table)
             .select((id, question_id, user_id, answer, users::user_name))
             .filter(round_id_dsl.eq(round_id))
-            .get_results::<UserAnswer>(conn)?;
+            .order(id.asc())
+            .get_results::<UserAnswer>(conn)
+            .await?;
 
         Ok(user_answers)
     }
 
-    pub fn find_by_round_and_user(
-        conn: &PgConnection,
+    pub async fn find_by_round_and_user(
+        conn: &mut DbConnection,
         round_id: i32,
         user_id: i32,
     ) -> Result<Vec<UserQuestion>, Error> {
@@ -89,8 +90,30 @@ impl UserQuestion {
         let results = user_questions_table
             .filter(round_id_dsl.eq(round_id))
             .filter(user_id_dsl.eq(user_id))
-            .get_results(conn)?;
+            .get_results(conn)
+            .await?;
 
         Ok(results)
     }
+
+    pub async fn delete_by_round_and_user(
+        conn: &mut DbConnection,
+        round_id: i32,
+        user_id: i32,
+    ) -> Result<usize, Error> {
+        use user_questions::dsl::{
+            round_id as round_id_dsl, user_id as user_id_dsl,
+            user_questions as user_questions_table,
+        };
+
+        let deleted = diesel::delete(
+            user_questions_table
+                .filter(round_id_dsl.eq(round_id))
+                .filter(user_id_dsl.eq(user_id)),
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(deleted)
+    }
 }
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use diesel::BelongingToDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+
+use errors::Error;
+
+use super::{
+    rank_users, ChatRepository, GameRepository, LeaderboardRepository, Pick, PickRepository,
+    QuestionRepository, RoundRepository, UserRepository,
+};
+use crate::models::{
+    ChatMessage, ChatMessageDetails, Game, GameQuestion, LeaderboardSnapshot,
+    NewLeaderboardSnapshot, NewRoundAnswer, NewUserQuestion, PicksVisibility, Question,
+    QuestionDetails, Round, RoundAnswer, User, UserAnswer, UserDetails, UserQuestion,
+};
+use crate::{get_conn, DbPool};
+
+/// Runs the repositories against the database, through the model functions
+#[derive(Clone)]
+pub struct DbRepository {
+    pool: DbPool,
+}
+
+impl DbRepository {
+    pub fn new(pool: DbPool) -> Self {
+        DbRepository { pool }
+    }
+
+    /// Saves the picks for the game's open round, holding a share lock on it so it can't be locked
+    /// halfway through
+    async fn save_picks(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        picks: Vec<Pick>,
+        replace: bool,
+    ) -> Result<(), Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        connection
+            .transaction::<_, Error, _>(|connection| {
+                async move {
+                    let round =
+                        Round::get_active_round_by_game_id_for_share(connection, game_id).await?;
+                    if replace {
+                        UserQuestion::delete_by_round_and_user(connection, round.id, user_id)
+                            .await?;
+                    }
+
+                    let picks = picks
+                        .into_iter()
+                        .map(|pick| NewUserQuestion {
+                            user_id,
+                            question_id: pick.question_id,
+                            round_id: round.id,
+                            answer: pick.answer,
+                        })
+                        .collect();
+                    UserQuestion::create_all(connection, picks).await?;
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl GameRepository for DbRepository {
+    async fn create_game(
+        &self,
+        question_ids: &[i32],
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        connection
+            .transaction::<_, Error, _>(|connection| {
+                async move {
+                    let game = Game::create(connection, picks_visibility).await?;
+                    for question_id in question_ids {
+                        GameQuestion::create(connection, game.id, *question_id).await?;
+                    }
+
+                    Ok(game)
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn find_game(&self, id: i32) -> Result<Game, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        Game::find_by_id(&mut connection, id).await
+    }
+
+    async fn find_game_by_slug(&self, slug: &str) -> Result<Game, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        Game::find_by_slug(&mut connection, slug).await
+    }
+
+    async fn set_picks_visibility(
+        &self,
+        id: i32,
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        Game::set_picks_visibility(&mut connection, id, picks_visibility).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for DbRepository {
+    async fn create_user(&self, game_id: i32, user_name: String) -> Result<User, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        User::create(&mut connection, user_name, game_id).await
+    }
+
+    async fn find_users_by_game(&self, game_id: i32) -> Result<Vec<UserDetails>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        User::find_all_by_game_id(&mut connection, game_id).await
+    }
+
+    async fn find_user(&self, game_id: i32, user_id: i32) -> Result<User, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        User::find_by_game_id_and_id(&mut connection, game_id, user_id).await
+    }
+
+    async fn find_user_by_name(&self, game_id: i32, user_name: &str) -> Result<User, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        User::find_by_game_id_and_name(&mut connection, game_id, user_name).await
+    }
+
+    async fn rename_user(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        user_name: String,
+    ) -> Result<User, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        User::rename(&mut connection, game_id, user_id, user_name).await
+    }
+
+    async fn delete_user(&self, game_id: i32, user_id: i32) -> Result<(), Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        User::delete(&mut connection, game_id, user_id).await
+    }
+}
+
+#[async_trait]
+impl RoundRepository for DbRepository {
+    async fn create_round(
+        &self,
+        game_id: i32,
+        player_one: String,
+        player_two: String,
+    ) -> Result<Round, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        Round::create(&mut connection, game_id, player_one, player_two).await
+    }
+
+    async fn find_rounds_by_game(&self, game_id: i32) -> Result<Vec<Round>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        let game = Game::find_by_id(&mut connection, game_id).await?;
+        let rounds = Round::belonging_to(&game)
+            .load::<Round>(&mut connection)
+            .await?;
+
+        Ok(rounds)
+    }
+
+    async fn find_active_round(&self, game_id: i32) -> Result<Round, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        Round::get_active_round_by_game_id(&mut connection, game_id).await
+    }
+
+    async fn find_latest_round(&self, game_id: i32) -> Result<Round, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        Round::get_latest_round_by_game_id(&mut connection, game_id).await
+    }
+
+    async fn lock_active_round(&self, game_id: i32) -> Result<Round, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        // picks being saved hold a share lock on the round, so this waits for them to finish
+        connection
+            .transaction::<_, Error, _>(|connection| {
+                async move {
+                    let round =
+                        Round::get_active_round_by_game_id_for_update(connection, game_id).await?;
+                    Round::lock(connection, round.id).await?;
+
+                    Ok(Round {
+                        locked: true,
+                        ..round
+                    })
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn find_round_answers(&self, round_id: i32) -> Result<Vec<RoundAnswer>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        RoundAnswer::find_by_round(&mut connection, round_id).await
+    }
+
+    async fn score_round(&self, game_id: i32, answers: Vec<Pick>) -> Result<bool, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        connection
+            .transaction::<_, Error, _>(|connection| {
+                async move {
+                    let round = match Round::get_unfinished_round_by_game_id_for_update(
+                        connection, game_id,
+                    )
+                    .await
+                    {
+                        Ok(round) => round,
+                        Err(err @ Error::NotFound(_)) => {
+                            // already scored, likely a retried request, so leave it as is
+                            let latest =
+                                Round::get_latest_round_by_game_id(connection, game_id).await?;
+                            if latest.finished {
+                                return Ok(false);
+                            }
+                            return Err(err);
+                        }
+                        Err(err) => return Err(err),
+                    };
+
+                    let round_answers = answers
+                        .into_iter()
+                        .map(|answer| NewRoundAnswer {
+                            round_id: round.id,
+                            question_id: answer.question_id,
+                            answer: answer.answer,
+                        })
+                        .collect();
+                    RoundAnswer::create_all(connection, round_answers).await?;
+
+                    // picks are scored against the answers just saved
+                    let scores: HashMap<i32, i32> = User::add_round_scores(connection, round.id)
+                        .await?
+                        .into_iter()
+                        .map(|score| (score.user_id, score.round_score))
+                        .collect();
+
+                    Round::finish(connection, round.id).await?;
+
+                    // the standings as of this round, for the leaderboard's history
+                    let users = User::find_all_by_game_id(connection, game_id).await?;
+                    let snapshots = rank_users(users)
+                        .into_iter()
+                        .map(|(rank, user)| NewLeaderboardSnapshot {
+                            round_id: round.id,
+                            user_id: user.id,
+                            score: user.score,
+                            round_score: *scores.get(&user.id).unwrap_or(&0),
+                            rank,
+                        })
+                        .collect();
+                    LeaderboardSnapshot::create_all(connection, snapshots).await?;
+
+                    Ok(true)
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl QuestionRepository for DbRepository {
+    async fn find_all_questions(&self) -> Result<Vec<Question>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        Question::get_all(&mut connection).await
+    }
+
+    async fn find_questions_by_game(&self, game_id: i32) -> Result<Vec<QuestionDetails>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        GameQuestion::get_questions_by_game_id(&mut connection, game_id).await
+    }
+}
+
+#[async_trait]
+impl PickRepository for DbRepository {
+    async fn find_picks_by_round(&self, round_id: i32) -> Result<Vec<UserAnswer>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        UserQuestion::find_by_round(&mut connection, round_id).await
+    }
+
+    async fn find_picks_by_round_and_user(
+        &self,
+        round_id: i32,
+        user_id: i32,
+    ) -> Result<Vec<UserQuestion>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        UserQuestion::find_by_round_and_user(&mut connection, round_id, user_id).await
+    }
+
+    async fn create_picks(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        picks: Vec<Pick>,
+    ) -> Result<(), Error> {
+        self.save_picks(game_id, user_id, picks, false).await
+    }
+
+    async fn replace_picks(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        picks: Vec<Pick>,
+    ) -> Result<(), Error> {
+        self.save_picks(game_id, user_id, picks, true).await
+    }
+}
+
+#[async_trait]
+impl LeaderboardRepository for DbRepository {
+    async fn find_leaderboard_snapshots(
+        &self,
+        game_id: i32,
+    ) -> Result<Vec<LeaderboardSnapshot>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        LeaderboardSnapshot::find_by_game_id(&mut connection, game_id).await
+    }
+}
+
+#[async_trait]
+impl ChatRepository for DbRepository {
+    async fn create_chat_message(
+        &self,
+        game_id: i32,
+        user_id: Option<i32>,
+        body: String,
+    ) -> Result<ChatMessageDetails, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        ChatMessage::create(&mut connection, game_id, user_id, body).await
+    }
+
+    async fn find_chat_messages(
+        &self,
+        game_id: i32,
+        before: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessageDetails>, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        ChatMessage::find_page_by_game_id(&mut connection, game_id, before, limit).await
+    }
+
+    async fn count_chat_messages_since(
+        &self,
+        game_id: i32,
+        user_id: Option<i32>,
+        since: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        ChatMessage::count_sent_since(&mut connection, game_id, user_id, since).await
+    }
+
+    async fn delete_chat_message(&self, game_id: i32, message_id: i32) -> Result<(), Error> {
+        let mut connection = get_conn(&self.pool).await?;
+        ChatMessage::delete(&mut connection, game_id, message_id).await
+    }
+}
@@ -0,0 +1,683 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use auth::{create_jwt, PrivateClaim, Role};
+use errors::Error;
+
+use super::{
+    rank_users, ChatRepository, GameRepository, LeaderboardRepository, Pick, PickRepository,
+    QuestionRepository, RoundRepository, UserRepository,
+};
+use crate::models::{
+    ChatMessage, ChatMessageDetails, Game, LeaderboardSnapshot, PicksVisibility, Question,
+    QuestionDetails, Round, RoundAnswer, User, UserAnswer, UserDetails, UserQuestion,
+};
+use crate::utils::create_slug_from_id;
+
+#[derive(Default)]
+struct MemoryState {
+    last_id: i32,
+    chat_messages: Vec<ChatMessage>,
+    games: Vec<Game>,
+    // (game id, question id)
+    game_questions: Vec<(i32, i32)>,
+    leaderboard_snapshots: Vec<LeaderboardSnapshot>,
+    picks: Vec<UserQuestion>,
+    questions: Vec<Question>,
+    round_answers: Vec<RoundAnswer>,
+    rounds: Vec<Round>,
+    users: Vec<User>,
+}
+
+impl MemoryState {
+    /// Ids are shared across tables, which is fine as nothing compares them between tables
+    fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn game(&self, id: i32) -> Result<&Game, Error> {
+        self.games
+            .iter()
+            .find(|game| game.id == id)
+            .ok_or_else(not_found)
+    }
+
+    fn game_mut(&mut self, id: i32) -> Result<&mut Game, Error> {
+        self.games
+            .iter_mut()
+            .find(|game| game.id == id)
+            .ok_or_else(not_found)
+    }
+
+    fn active_round(&self, game_id: i32) -> Result<&Round, Error> {
+        self.rounds
+            .iter()
+            .find(|round| round.game_id == game_id && !round.locked)
+            .ok_or_else(not_found)
+    }
+
+    fn user_details(&self, game_id: i32) -> Vec<UserDetails> {
+        self.users
+            .iter()
+            .filter(|user| user.game_id == game_id)
+            .map(|user| UserDetails {
+                id: user.id,
+                user_name: user.user_name.clone(),
+                game_id: user.game_id,
+                score: user.score,
+            })
+            .collect()
+    }
+
+    fn chat_message_details(&self, message: &ChatMessage) -> ChatMessageDetails {
+        let user_name = message.user_id.and_then(|user_id| {
+            self.users
+                .iter()
+                .find(|user| user.id == user_id)
+                .map(|user| user.user_name.clone())
+        });
+
+        ChatMessageDetails {
+            id: message.id,
+            game_id: message.game_id,
+            user_id: message.user_id,
+            user_name,
+            body: message.body.clone(),
+            created_at: message.created_at,
+        }
+    }
+}
+
+/// Keeps everything in memory, so handlers can be tested without a database. Mirrors the
+/// constraints the database schema enforces, returning the same errors.
+#[derive(Default)]
+pub struct MemoryRepository {
+    state: Mutex<MemoryState>,
+}
+
+fn not_found() -> Error {
+    Error::NotFound("Record not found".into())
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        MemoryRepository::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().expect("memory repository lock poisoned")
+    }
+
+    /// Questions are seeded rather than created through the app
+    pub fn add_question(&self, body: &str) -> Question {
+        let mut state = self.state();
+        let now = Utc::now();
+        let question = Question {
+            id: state.next_id(),
+            body: body.to_string(),
+            created_at: now,
+            updated_at: now,
+        };
+        state.questions.push(question.clone());
+
+        question
+    }
+
+    fn save_picks(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        picks: Vec<Pick>,
+        replace: bool,
+    ) -> Result<(), Error> {
+        let conflict =
+            || Error::Conflict("User has already chosen picks for this round".to_string());
+        // checked before replacing anything, as the unique index rolls back the whole batch
+        let mut question_ids = HashSet::new();
+        if !picks
+            .iter()
+            .all(|pick| question_ids.insert(pick.question_id))
+        {
+            return Err(conflict());
+        }
+
+        let mut state = self.state();
+        let round_id = state.active_round(game_id)?.id;
+        if replace {
+            state
+                .picks
+                .retain(|pick| pick.round_id != round_id || pick.user_id != user_id);
+        }
+
+        let already_picked = state.picks.iter().any(|saved| {
+            saved.round_id == round_id
+                && saved.user_id == user_id
+                && picks
+                    .iter()
+                    .any(|pick| pick.question_id == saved.question_id)
+        });
+        if already_picked {
+            return Err(conflict());
+        }
+
+        let now = Utc::now();
+        for pick in picks {
+            let id = state.next_id();
+            state.picks.push(UserQuestion {
+                id,
+                user_id,
+                question_id: pick.question_id,
+                round_id,
+                answer: pick.answer,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl GameRepository for MemoryRepository {
+    async fn create_game(
+        &self,
+        question_ids: &[i32],
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error> {
+        let mut state = self.state();
+        let id = state.next_id();
+        let slug = create_slug_from_id(id);
+        let creator = create_jwt(PrivateClaim::new(id, slug.clone(), id, Role::Owner))?;
+        let now = Utc::now();
+        let game = Game {
+            id,
+            slug: Some(slug),
+            created_at: now,
+            updated_at: now,
+            creator: Some(creator),
+            picks_visibility,
+        };
+
+        state.games.push(game.clone());
+        for question_id in question_ids {
+            state.game_questions.push((id, *question_id));
+        }
+
+        Ok(game)
+    }
+
+    async fn find_game(&self, id: i32) -> Result<Game, Error> {
+        Ok(self.state().game(id)?.clone())
+    }
+
+    async fn find_game_by_slug(&self, slug: &str) -> Result<Game, Error> {
+        self.state()
+            .games
+            .iter()
+            .find(|game| game.slug.as_deref() == Some(slug))
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn set_picks_visibility(
+        &self,
+        id: i32,
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error> {
+        let mut state = self.state();
+        let game = state.game_mut(id)?;
+        game.picks_visibility = picks_visibility;
+        game.updated_at = Utc::now();
+
+        Ok(game.clone())
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn create_user(&self, game_id: i32, user_name: String) -> Result<User, Error> {
+        let mut state = self.state();
+        let id = state.next_id();
+        let session_id = create_jwt(PrivateClaim::new(
+            id,
+            user_name.clone(),
+            game_id,
+            Role::Player,
+        ))?;
+        let now = Utc::now();
+        let user = User {
+            id,
+            user_name,
+            game_id,
+            session_id: Some(session_id),
+            score: 0,
+            created_at: now,
+            updated_at: now,
+        };
+        state.users.push(user.clone());
+
+        Ok(user)
+    }
+
+    async fn find_users_by_game(&self, game_id: i32) -> Result<Vec<UserDetails>, Error> {
+        Ok(self.state().user_details(game_id))
+    }
+
+    async fn find_user(&self, game_id: i32, user_id: i32) -> Result<User, Error> {
+        self.state()
+            .users
+            .iter()
+            .find(|user| user.game_id == game_id && user.id == user_id)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn find_user_by_name(&self, game_id: i32, user_name: &str) -> Result<User, Error> {
+        self.state()
+            .users
+            .iter()
+            .find(|user| user.game_id == game_id && user.user_name == user_name)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn rename_user(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        user_name: String,
+    ) -> Result<User, Error> {
+        let mut state = self.state();
+        let user = state
+            .users
+            .iter_mut()
+            .find(|user| user.id == user_id && user.game_id == game_id)
+            .ok_or_else(not_found)?;
+        user.user_name = user_name;
+        user.updated_at = Utc::now();
+
+        Ok(user.clone())
+    }
+
+    async fn delete_user(&self, game_id: i32, user_id: i32) -> Result<(), Error> {
+        let mut state = self.state();
+        if !state
+            .users
+            .iter()
+            .any(|user| user.id == user_id && user.game_id == game_id)
+        {
+            return Err(not_found());
+        }
+
+        state.picks.retain(|pick| pick.user_id != user_id);
+        state
+            .chat_messages
+            .retain(|message| message.user_id != Some(user_id));
+        state
+            .leaderboard_snapshots
+            .retain(|snapshot| snapshot.user_id != user_id);
+        state.users.retain(|user| user.id != user_id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RoundRepository for MemoryRepository {
+    async fn create_round(
+        &self,
+        game_id: i32,
+        player_one: String,
+        player_two: String,
+    ) -> Result<Round, Error> {
+        let mut state = self.state();
+        if state.active_round(game_id).is_ok() {
+            return Err(Error::Conflict(
+                "The game already has an open round".to_string(),
+            ));
+        }
+
+        let now = Utc::now();
+        let round = Round {
+            id: state.next_id(),
+            player_one,
+            player_two,
+            game_id,
+            created_at: now,
+            updated_at: now,
+            locked: false,
+            finished: false,
+        };
+        state.rounds.push(round.clone());
+
+        Ok(round)
+    }
+
+    async fn find_rounds_by_game(&self, game_id: i32) -> Result<Vec<Round>, Error> {
+        let state = self.state();
+        state.game(game_id)?;
+
+        Ok(state
+            .rounds
+            .iter()
+            .filter(|round| round.game_id == game_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_active_round(&self, game_id: i32) -> Result<Round, Error> {
+        Ok(self.state().active_round(game_id)?.clone())
+    }
+
+    async fn find_latest_round(&self, game_id: i32) -> Result<Round, Error> {
+        self.state()
+            .rounds
+            .iter()
+            .filter(|round| round.game_id == game_id)
+            .max_by_key(|round| (round.created_at, round.id))
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn lock_active_round(&self, game_id: i32) -> Result<Round, Error> {
+        let mut state = self.state();
+        let round = state
+            .rounds
+            .iter_mut()
+            .find(|round| round.game_id == game_id && !round.locked)
+            .ok_or_else(not_found)?;
+        round.locked = true;
+        round.updated_at = Utc::now();
+
+        Ok(round.clone())
+    }
+
+    async fn find_round_answers(&self, round_id: i32) -> Result<Vec<RoundAnswer>, Error> {
+        let mut answers: Vec<RoundAnswer> = self
+            .state()
+            .round_answers
+            .iter()
+            .filter(|answer| answer.round_id == round_id)
+            .cloned()
+            .collect();
+        answers.sort_by_key(|answer| answer.question_id);
+
+        Ok(answers)
+    }
+
+    async fn score_round(&self, game_id: i32, answers: Vec<Pick>) -> Result<bool, Error> {
+        let mut state = self.state();
+        let round_id = match state
+            .rounds
+            .iter()
+            .find(|round| round.game_id == game_id && round.locked && !round.finished)
+        {
+            Some(round) => round.id,
+            None => {
+                // already scored, likely a retried request, so leave it as is
+                let latest = state
+                    .rounds
+                    .iter()
+                    .filter(|round| round.game_id == game_id)
+                    .max_by_key(|round| (round.created_at, round.id))
+                    .ok_or_else(not_found)?;
+                if latest.finished {
+                    return Ok(false);
+                }
+                return Err(not_found());
+            }
+        };
+
+        let now = Utc::now();
+        for answer in answers {
+            let id = state.next_id();
+            state.round_answers.push(RoundAnswer {
+                id,
+                round_id,
+                question_id: answer.question_id,
+                answer: answer.answer,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+
+        // picks are scored against the answers just saved
+        let mut scores: HashMap<i32, i32> = HashMap::new();
+        for pick in state.picks.iter().filter(|pick| pick.round_id == round_id) {
+            let correct = state.round_answers.iter().any(|answer| {
+                answer.round_id == round_id
+                    && answer.question_id == pick.question_id
+                    && answer.answer == pick.answer
+            });
+            if correct {
+                *scores.entry(pick.user_id).or_insert(0) += 1;
+            }
+        }
+        for user in &mut state.users {
+            if let Some(round_score) = scores.get(&user.id) {
+                user.score += round_score;
+                user.updated_at = now;
+            }
+        }
+
+        if let Some(round) = state.rounds.iter_mut().find(|round| round.id == round_id) {
+            round.finished = true;
+            round.updated_at = now;
+        }
+
+        // the standings as of this round, for the leaderboard's history
+        for (rank, user) in rank_users(state.user_details(game_id)) {
+            let id = state.next_id();
+            state.leaderboard_snapshots.push(LeaderboardSnapshot {
+                id,
+                round_id,
+                user_id: user.id,
+                score: user.score,
+                round_score: *scores.get(&user.id).unwrap_or(&0),
+                rank,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl QuestionRepository for MemoryRepository {
+    async fn find_all_questions(&self) -> Result<Vec<Question>, Error> {
+        let mut questions = self.state().questions.clone();
+        questions.sort_by(|a, b| a.body.cmp(&b.body));
+
+        Ok(questions)
+    }
+
+    async fn find_questions_by_game(&self, game_id: i32) -> Result<Vec<QuestionDetails>, Error> {
+        let state = self.state();
+        let questions = state
+            .game_questions
+            .iter()
+            .filter(|(question_game_id, _)| *question_game_id == game_id)
+            .filter_map(|(_, question_id)| {
+                state
+                    .questions
+                    .iter()
+                    .find(|question| question.id == *question_id)
+            })
+            .map(|question| QuestionDetails {
+                id: question.id,
+                body: question.body.clone(),
+            })
+            .collect();
+
+        Ok(questions)
+    }
+}
+
+#[async_trait]
+impl PickRepository for MemoryRepository {
+    async fn find_picks_by_round(&self, round_id: i32) -> Result<Vec<UserAnswer>, Error> {
+        let state = self.state();
+        let picks = state
+            .picks
+            .iter()
+            .filter(|pick| pick.round_id == round_id)
+            .filter_map(|pick| {
+                state
+                    .users
+                    .iter()
+                    .find(|user| user.id == pick.user_id)
+                    .map(|user| UserAnswer {
+                        id: pick.id,
+                        question_id: pick.question_id,
+                        user_id: pick.user_id,
+                        answer: pick.answer.clone(),
+                        user_name: user.user_name.clone(),
+                    })
+            })
+            .collect();
+
+        Ok(picks)
+    }
+
+    async fn find_picks_by_round_and_user(
+        &self,
+        round_id: i32,
+        user_id: i32,
+    ) -> Result<Vec<UserQuestion>, Error> {
+        Ok(self
+            .state()
+            .picks
+            .iter()
+            .filter(|pick| pick.round_id == round_id && pick.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_picks(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        picks: Vec<Pick>,
+    ) -> Result<(), Error> {
+        self.save_picks(game_id, user_id, picks, false)
+    }
+
+    async fn replace_picks(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        picks: Vec<Pick>,
+    ) -> Result<(), Error> {
+        self.save_picks(game_id, user_id, picks, true)
+    }
+}
+
+#[async_trait]
+impl LeaderboardRepository for MemoryRepository {
+    async fn find_leaderboard_snapshots(
+        &self,
+        game_id: i32,
+    ) -> Result<Vec<LeaderboardSnapshot>, Error> {
+        let state = self.state();
+        let mut snapshots: Vec<LeaderboardSnapshot> = state
+            .leaderboard_snapshots
+            .iter()
+            .filter(|snapshot| {
+                state
+                    .rounds
+                    .iter()
+                    .any(|round| round.id == snapshot.round_id && round.game_id == game_id)
+            })
+            .cloned()
+            .collect();
+        snapshots.sort_by_key(|snapshot| (snapshot.round_id, snapshot.rank, snapshot.id));
+
+        Ok(snapshots)
+    }
+}
+
+#[async_trait]
+impl ChatRepository for MemoryRepository {
+    async fn create_chat_message(
+        &self,
+        game_id: i32,
+        user_id: Option<i32>,
+        body: String,
+    ) -> Result<ChatMessageDetails, Error> {
+        let mut state = self.state();
+        let now = Utc::now();
+        let message = ChatMessage {
+            id: state.next_id(),
+            game_id,
+            user_id,
+            body,
+            created_at: now,
+            updated_at: now,
+        };
+        let details = state.chat_message_details(&message);
+        state.chat_messages.push(message);
+
+        Ok(details)
+    }
+
+    async fn find_chat_messages(
+        &self,
+        game_id: i32,
+        before: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessageDetails>, Error> {
+        let state = self.state();
+        let mut messages: Vec<ChatMessageDetails> = state
+            .chat_messages
+            .iter()
+            .rev()
+            .filter(|message| message.game_id == game_id)
+            .filter(|message| before.is_none_or(|before| message.id < before))
+            .take(limit.max(0) as usize)
+            .map(|message| state.chat_message_details(message))
+            .collect();
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    async fn count_chat_messages_since(
+        &self,
+        game_id: i32,
+        user_id: Option<i32>,
+        since: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        let count = self
+            .state()
+            .chat_messages
+            .iter()
+            .filter(|message| {
+                message.game_id == game_id
+                    && message.user_id == user_id
+                    && message.created_at > since
+            })
+            .count();
+
+        Ok(count as i64)
+    }
+
+    async fn delete_chat_message(&self, game_id: i32, message_id: i32) -> Result<(), Error> {
+        let mut state = self.state();
+        let count = state.chat_messages.len();
+        state
+            .chat_messages
+            .retain(|message| message.id != message_id || message.game_id != game_id);
+        if state.chat_messages.len() == count {
+            return Err(Error::NotFound("Chat message not found".to_string()));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use errors::Error;
+
+use crate::models::{
+    ChatMessageDetails, Game, LeaderboardSnapshot, PicksVisibility, Question, QuestionDetails,
+    Round, RoundAnswer, User, UserAnswer, UserDetails, UserQuestion,
+};
+
+mod database;
+mod memory;
+
+pub use self::database::*;
+pub use self::memory::*;
+
+/// An answer to one of the game's questions, a player's pick or the round's correct answer, before
+/// it's saved to a round
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pick {
+    pub question_id: i32,
+    pub answer: String,
+}
+
+/// Sorts players by score, ties broken by name, and gives each their competition rank, so players
+/// on the same score share a rank and the next one is skipped
+pub fn rank_users(mut users: Vec<UserDetails>) -> Vec<(i32, UserDetails)> {
+    users.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| a.user_name.cmp(&b.user_name))
+    });
+
+    let mut ranked: Vec<(i32, UserDetails)> = Vec::with_capacity(users.len());
+    for (i, user) in users.into_iter().enumerate() {
+        let rank = match ranked.last() {
+            Some((rank, previous)) if previous.score == user.score => *rank,
+            _ => i as i32 + 1,
+        };
+        ranked.push((rank, user));
+    }
+
+    ranked
+}
+
+#[async_trait]
+pub trait GameRepository {
+    /// Creates the game along with its questions, giving it a slug and the host's token
+    async fn create_game(
+        &self,
+        question_ids: &[i32],
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error>;
+
+    async fn find_game(&self, id: i32) -> Result<Game, Error>;
+
+    async fn find_game_by_slug(&self, slug: &str) -> Result<Game, Error>;
+
+    async fn set_picks_visibility(
+        &self,
+        id: i32,
+        picks_visibility: PicksVisibility,
+    ) -> Result<Game, Error>;
+}
+
+#[async_trait]
+pub trait UserRepository {
+    /// Creates the player, giving them their session token
+    async fn create_user(&self, game_id: i32, user_name: String) -> Result<User, Error>;
+
+    async fn find_users_by_game(&self, game_id: i32) -> Result<Vec<UserDetails>, Error>;
+
+    /// Finds one of the game's players. Kicked players aren't found, though their token is still
+    /// valid.
+    async fn find_user(&self, game_id: i32, user_id: i32) -> Result<User, Error>;
+
+    async fn find_user_by_name(&self, game_id: i32, user_name: &str) -> Result<User, Error>;
+
+    async fn rename_user(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        user_name: String,
+    ) -> Result<User, Error>;
+
+    /// Removes a player from the game, along with everything they've done in it
+    async fn delete_user(&self, game_id: i32, user_id: i32) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait RoundRepository {
+    async fn create_round(
+        &self,
+        game_id: i32,
+        player_one: String,
+        player_two: String,
+    ) -> Result<Round, Error>;
+
+    async fn find_rounds_by_game(&self, game_id: i32) -> Result<Vec<Round>, Error>;
+
+    /// The round open for picks
+    async fn find_active_round(&self, game_id: i32) -> Result<Round, Error>;
+
+    async fn find_latest_round(&self, game_id: i32) -> Result<Round, Error>;
+
+    /// Locks the round open for picks, once any picks being saved for it are done
+    async fn lock_active_round(&self, game_id: i32) -> Result<Round, Error>;
+
+    /// The correct answers the round was scored with, empty until it's scored
+    async fn find_round_answers(&self, round_id: i32) -> Result<Vec<RoundAnswer>, Error>;
+
+    /// Saves the answers for the game's locked round, scores everyone's picks against them and
+    /// snapshots the standings. Returns false if the latest round was already scored, so a retried
+    /// request changes nothing.
+    async fn score_round(&self, game_id: i32, answers: Vec<Pick>) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub trait LeaderboardRepository {
+    /// Every snapshot taken for the game, ordered by round, then rank
+    async fn find_leaderboard_snapshots(
+        &self,
+        game_id: i32,
+    ) -> Result<Vec<LeaderboardSnapshot>, Error>;
+}
+
+#[async_trait]
+pub trait ChatRepository {
+    /// Messages from the host have no `user_id`
+    async fn create_chat_message(
+        &self,
+        game_id: i32,
+        user_id: Option<i32>,
+        body: String,
+    ) -> Result<ChatMessageDetails, Error>;
+
+    /// The newest `limit` messages sent before the message with id `before`, oldest first
+    async fn find_chat_messages(
+        &self,
+        game_id: i32,
+        before: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessageDetails>, Error>;
+
+    /// How many messages the player, or the host when `user_id` is None, has sent since `since`
+    async fn count_chat_messages_since(
+        &self,
+        game_id: i32,
+        user_id: Option<i32>,
+        since: DateTime<Utc>,
+    ) -> Result<i64, Error>;
+
+    async fn delete_chat_message(&self, game_id: i32, message_id: i32) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait QuestionRepository {
+    async fn find_all_questions(&self) -> Result<Vec<Question>, Error>;
+
+    async fn find_questions_by_game(&self, game_id: i32) -> Result<Vec<QuestionDetails>, Error>;
+}
+
+#[async_trait]
+pub trait PickRepository {
+    async fn find_picks_by_round(&self, round_id: i32) -> Result<Vec<UserAnswer>, Error>;
+
+    async fn find_picks_by_round_and_user(
+        &self,
+        round_id: i32,
+        user_id: i32,
+    ) -> Result<Vec<UserQuestion>, Error>;
+
+    /// Saves a player's picks for the game's open round. The round can't be locked until they're
+    /// saved.
+    async fn create_picks(&self, game_id: i32, user_id: i32, picks: Vec<Pick>)
+        -> Result<(), Error>;
+
+    /// Same as `create_picks`, replacing any picks the player already has for the round
+    async fn replace_picks(
+        &self,
+        game_id: i32,
+        user_id: i32,
+        picks: Vec<Pick>,
+    ) -> Result<(), Error>;
+}
+
+/// Everything the server reads and writes for a game, so handlers can run against the database or
+/// the in-memory store in tests
+pub trait Repository:
+    GameRepository
+    + UserRepository
+    + RoundRepository
+    + QuestionRepository
+    + PickRepository
+    + LeaderboardRepository
+    + ChatRepository
+    + Send
+    + Sync
+{
+}
+
+impl<T> Repository for T where
+    T: GameRepository
+        + UserRepository
+        + RoundRepository
+        + QuestionRepository
+        + PickRepository
+        + LeaderboardRepository
+        + ChatRepository
+        + Send
+        + Sync
+{
+}
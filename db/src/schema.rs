@@ -1,73 +1,153 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
+    chat_messages (id) {
+        id -> Int4,
+        game_id -> Int4,
+        user_id -> Nullable<Int4>,
+        body -> Text,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
     game_questions (id) {
         id -> Int4,
         game_id -> Int4,
         question_id -> Int4,
-        created_at -> Timestamptz,
-        updated_at -> Timestamptz,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
     games (id) {
         id -> Int4,
         slug -> Nullable<Varchar>,
-        created_at -> Timestamptz,
-        updated_at -> Timestamptz,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
         creator -> Nullable<Text>,
+        picks_visibility -> Text,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
+    leaderboard_snapshots (id) {
+        id -> Int4,
+        round_id -> Int4,
+        user_id -> Int4,
+        score -> Int4,
+        round_score -> Int4,
+        rank -> Int4,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
     questions (id) {
         id -> Int4,
         body -> Text,
-        created_at -> Timestamptz,
-        updated_at -> Timestamptz,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
+    round_answers (id) {
+        id -> Int4,
+        round_id -> Int4,
+        question_id -> Int4,
+        answer -> Text,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
     rounds (id) {
         id -> Int4,
         player_one -> Varchar,
         player_two -> Varchar,
         game_id -> Int4,
-        created_at -> Timestamptz,
-        updated_at -> Timestamptz,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
         locked -> Bool,
         finished -> Bool,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
     user_questions (id) {
         id -> Int4,
         user_id -> Int4,
         question_id -> Int4,
         round_id -> Int4,
         answer -> Varchar,
-        created_at -> Timestamptz,
-        updated_at -> Timestamptz,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
     users (id) {
         id -> Int4,
         user_name -> Varchar,
         game_id -> Int4,
         session_id -> Nullable<Text>,
         score -> Int4,
-        created_at -> Timestamptz,
-        updated_at -> Timestamptz,
+        created_at -> TimestampUtc,
+        updated_at -> TimestampUtc,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql_types::TimestampUtc;
+
+    websocket_events (id) {
+        id -> Int8,
+        payload -> Text,
+        created_at -> TimestampUtc,
     }
 }
 
+diesel::joinable!(chat_messages -> games (game_id));
+diesel::joinable!(chat_messages -> users (user_id));
 diesel::joinable!(game_questions -> games (game_id));
 diesel::joinable!(game_questions -> questions (question_id));
+diesel::joinable!(leaderboard_snapshots -> rounds (round_id));
+diesel::joinable!(leaderboard_snapshots -> users (user_id));
+diesel::joinable!(round_answers -> questions (question_id));
+diesel::joinable!(round_answers -> rounds (round_id));
 diesel::joinable!(rounds -> games (game_id));
 diesel::joinable!(user_questions -> questions (question_id));
 diesel::joinable!(user_questions -> rounds (round_id));
@@ -75,10 +155,14 @@ diesel::joinable!(user_questions -> users (user_id));
 diesel::joinable!(users -> games (game_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    chat_messages,
     game_questions,
     games,
+    leaderboard_snapshots,
     questions,
+    round_answers,
     rounds,
     user_questions,
     users,
+    websocket_events,
 );
@@ -0,0 +1,7 @@
+//! SQL types that differ between the Postgres and SQLite backends, imported into `schema`
+
+/// A timestamp with its time zone, read and written as `DateTime<Utc>`
+#[cfg(not(feature = "sqlite"))]
+pub type TimestampUtc = diesel::sql_types::Timestamptz;
+#[cfg(feature = "sqlite")]
+pub type TimestampUtc = diesel::sql_types::TimestamptzSqlite;
@@ -1,13 +1,11 @@
 #[macro_use]
 extern crate log;
 
-use actix_web::{
-    error::{BlockingError, ResponseError},
-    Error as ActixError, HttpResponse,
-};
+use actix::MailboxError;
+use actix_web::{error::ResponseError, Error as ActixError, HttpResponse};
 use derive_more::Display;
 use diesel::result::{DatabaseErrorKind, Error as DBError};
-use r2d2::Error as PoolError;
+use diesel_async::pooled_connection::deadpool::PoolError;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Display, PartialEq)]
@@ -16,6 +14,7 @@ pub enum Error {
     BadRequest(String),
     CannotDecodeJwtToken(String),
     CannotEncodeJwtToken(String),
+    Conflict(String),
     InternalServerError(String),
     Unauthorized,
     Forbidden,
@@ -24,7 +23,6 @@ pub enum Error {
     #[display(fmt = "")]
     ValidationError(Vec<String>),
     UnprocessableEntity(String),
-    BlockingError(String),
 }
 
 // User-friendly error messages
@@ -47,10 +45,18 @@ impl ResponseError for Error {
                 let error: ErrorResponse = message.into();
                 HttpResponse::NotFound().json(error)
             }
+            Error::Conflict(message) => {
+                let error: ErrorResponse = message.into();
+                HttpResponse::Conflict().json(error)
+            }
             Error::UnprocessableEntity(message) => {
                 let error: ErrorResponse = message.into();
                 HttpResponse::UnprocessableEntity().json(error)
             }
+            Error::Unauthorized => {
+                let error: ErrorResponse = "Unauthorized".into();
+                HttpResponse::Unauthorized().json(error)
+            }
             Error::Forbidden => {
                 let error: ErrorResponse = "Forbidden".into();
                 HttpResponse::Forbidden().json(error)
@@ -95,7 +101,7 @@ impl From<DBError> for Error {
             DBError::DatabaseError(kind, info) => {
                 if let DatabaseErrorKind::UniqueViolation = kind {
                     let message = info.details().unwrap_or_else(|| info.message()).to_string();
-                    return Error::BadRequest(message);
+                    return Error::Conflict(message);
                 }
                 Error::InternalServerError("Unknown database error".into())
             }
@@ -112,15 +118,15 @@ impl From<PoolError> for Error {
     }
 }
 
-impl From<BlockingError> for Error {
-    fn from(error: BlockingError) -> Error {
-        error!("Thread blocking error {:?}", error);
-        Error::BlockingError("Thread blocking error".into())
-    }
-}
-
 impl From<ActixError> for Error {
     fn from(error: ActixError) -> Error {
         Error::InternalServerError(error.to_string())
     }
 }
+
+impl From<MailboxError> for Error {
+    fn from(error: MailboxError) -> Error {
+        error!("Actor mailbox error {:?}", error);
+        Error::InternalServerError("Actor mailbox error".into())
+    }
+}
@@ -1,13 +1,15 @@
-use diesel::{self, ExpressionMethods, RunQueryDsl};
+use diesel::{self, ExpressionMethods};
+use diesel_async::RunQueryDsl;
 use dotenv::dotenv;
 
 use db::{get_conn, new_pool, schema::questions};
 
-fn main() {
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
     dotenv().ok();
 
     let pool = new_pool();
-    let conn = get_conn(&pool).unwrap();
+    let mut conn = get_conn(&pool).await.unwrap();
 
     for body in &[
         "First to expand",
@@ -17,7 +19,8 @@ fn main() {
     ] {
         diesel::insert_into(questions::table)
             .values(questions::dsl::body.eq(body))
-            .execute(&conn)
+            .execute(&mut conn)
+            .await
             .unwrap();
     }
 }
@@ -0,0 +1,420 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What to do about migrations that haven't been run when the server starts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Migrations {
+    /// Refuse to start, so the server never runs against a schema it doesn't match
+    Check,
+    Run,
+    Skip,
+}
+
+impl FromStr for Migrations {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "check" => Ok(Migrations::Check),
+            "run" => Ok(Migrations::Run),
+            "skip" => Ok(Migrations::Skip),
+            _ => Err(format!("expected check, run or skip, got {}", value)),
+        }
+    }
+}
+
+/// How websocket messages reach sessions connected to other instances
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Broadcast {
+    /// Only this instance's sessions are sent messages
+    InProcess,
+    /// Messages are shared with other instances using the same database
+    #[cfg(feature = "postgres_broadcast")]
+    Postgres,
+}
+
+impl FromStr for Broadcast {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "in_process" => Ok(Broadcast::InProcess),
+            #[cfg(feature = "postgres_broadcast")]
+            "postgres" => Ok(Broadcast::Postgres),
+            #[cfg(not(feature = "postgres_broadcast"))]
+            "postgres" => Err("postgres isn't available in this build".to_string()),
+            _ => Err(format!("expected in_process or postgres, got {}", value)),
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, including the fields of the spans it was logged in, such as the
+    /// request id, game_id and user_id
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("expected text or json, got {}", value)),
+        }
+    }
+}
+
+/// Timings and limits for websocket and event stream sessions
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebsocketConfig {
+    /// How often sessions are pinged
+    pub heartbeat_interval: Duration,
+    /// Sessions that haven't responded to a ping for this long are disconnected
+    pub client_timeout: Duration,
+    /// How often event streams are sent a comment, so proxies don't close them when idle
+    pub keep_alive_interval: Duration,
+    /// Largest frame a client can send, in bytes
+    pub max_frame_size: usize,
+    /// Reactions sent by a session more often than this are rejected
+    pub reaction_throttle: Duration,
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> Self {
+        WebsocketConfig {
+            heartbeat_interval: Duration::from_secs(5),
+            client_timeout: Duration::from_secs(30),
+            keep_alive_interval: Duration::from_secs(15),
+            max_frame_size: 64 * 1024,
+            reaction_throttle: Duration::from_millis(250),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub bind_address: String,
+    pub database_url: String,
+    pub database_pool_size: usize,
+    /// Origins the client is served from, which are allowed to call the API
+    pub cors_origins: Vec<String>,
+    pub jwt_key: String,
+    /// How long tokens given to hosts and players are valid for
+    pub token_lifetime: Duration,
+    pub migrations: Migrations,
+    pub websocket_broadcast: Broadcast,
+    pub websocket: WebsocketConfig,
+    pub log_format: LogFormat,
+    /// Bearer token `/metrics` requires. Anyone that can reach the server can read them without it.
+    pub metrics_token: Option<String>,
+}
+
+/// Every setting that was missing or invalid, so they can be fixed in one go
+#[derive(Debug, PartialEq)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid configuration, set these in the environment, or in lowercase in CONFIG_FILE:"
+        )?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Looks up settings by their environment variable name, falling back to the same name in
+/// lowercase in the config file. Problems are collected rather than returned, so they can all be
+/// reported at once.
+struct Settings<F> {
+    env: F,
+    file: toml::Table,
+    problems: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Settings<F> {
+    fn raw(&self, name: &str) -> Option<String> {
+        if let Some(value) = (self.env)(name) {
+            return Some(value);
+        }
+
+        self.file
+            .get(&name.to_lowercase())
+            .map(|value| match value {
+                toml::Value::String(value) => value.clone(),
+                // lists are read the same way as comma separated values in the environment
+                toml::Value::Array(values) => values
+                    .iter()
+                    .map(|value| match value {
+                        toml::Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                value => value.to_string(),
+            })
+    }
+
+    fn optional<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.raw(name)?;
+        match value.trim().parse() {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.problems.push(format!("{} is invalid - {}", name, err));
+                None
+            }
+        }
+    }
+
+    fn or_default<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.optional(name).unwrap_or(default)
+    }
+
+    fn required<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        if self.raw(name).is_none() {
+            self.problems.push(format!("{} must be set", name));
+            return None;
+        }
+
+        self.optional(name)
+    }
+
+    fn secs(&mut self, name: &str, default: Duration) -> Duration {
+        self.optional(name).map_or(default, Duration::from_secs)
+    }
+
+    fn list(&mut self, name: &str) -> Option<Vec<String>> {
+        self.raw(name).map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    }
+}
+
+impl Config {
+    /// Loads the config from the environment, and the TOML file at `CONFIG_FILE` if it's set.
+    /// Values in the environment take precedence over the file.
+    pub fn load() -> Result<Config, ConfigError> {
+        let file = match env::var("CONFIG_FILE") {
+            Ok(path) => fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|contents| {
+                    contents
+                        .parse::<toml::Table>()
+                        .map_err(|err| err.to_string())
+                })
+                .map_err(|err| ConfigError(vec![format!("CONFIG_FILE {} - {}", path, err)]))?,
+            Err(_) => toml::Table::new(),
+        };
+
+        Config::from_sources(|name| env::var(name).ok(), file)
+    }
+
+    fn from_sources<F>(env: F, file: toml::Table) -> Result<Config, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut settings = Settings {
+            env,
+            file,
+            problems: Vec::new(),
+        };
+        let defaults = WebsocketConfig::default();
+
+        let bind_address = settings.or_default("BIND_ADDRESS", "0.0.0.0:8080".to_string());
+        let database_url = settings.required::<String>("DATABASE_URL");
+        let database_pool_size = settings.or_default("DATABASE_POOL_SIZE", 10usize);
+        if database_pool_size == 0 {
+            settings
+                .problems
+                .push("DATABASE_POOL_SIZE must be at least 1".to_string());
+        }
+        // CLIENT_HOST is the single origin older setups were configured with
+        let cors_origins = settings
+            .list("CORS_ORIGINS")
+            .or_else(|| settings.list("CLIENT_HOST"))
+            .filter(|origins| !origins.is_empty());
+        if cors_origins.is_none() {
+            settings
+                .problems
+                .push("CORS_ORIGINS must be set".to_string());
+        }
+        let jwt_key = settings.required::<String>("JWT_KEY");
+        let token_lifetime = settings.secs("TOKEN_LIFETIME_SECS", Duration::from_secs(3 * 60 * 60));
+        let migrations = settings.or_default("MIGRATIONS", Migrations::Check);
+        let websocket_broadcast = settings.or_default("WEBSOCKET_BROADCAST", Broadcast::InProcess);
+        let websocket = WebsocketConfig {
+            heartbeat_interval: settings
+                .secs("WS_HEARTBEAT_INTERVAL_SECS", defaults.heartbeat_interval),
+            client_timeout: settings.secs("WS_CLIENT_TIMEOUT_SECS", defaults.client_timeout),
+            keep_alive_interval: settings
+                .secs("WS_KEEP_ALIVE_INTERVAL_SECS", defaults.keep_alive_interval),
+            max_frame_size: settings.or_default("WS_MAX_FRAME_SIZE", defaults.max_frame_size),
+            reaction_throttle: settings
+                .optional("WS_REACTION_THROTTLE_MS")
+                .map_or(defaults.reaction_throttle, Duration::from_millis),
+        };
+        let log_format = settings.or_default("LOG_FORMAT", LogFormat::Text);
+        let metrics_token = settings
+            .optional::<String>("METRICS_TOKEN")
+            .filter(|token| !token.is_empty());
+        if websocket.client_timeout <= websocket.heartbeat_interval {
+            settings.problems.push(
+                "WS_CLIENT_TIMEOUT_SECS must be longer than WS_HEARTBEAT_INTERVAL_SECS".to_string(),
+            );
+        }
+
+        match (database_url, cors_origins, jwt_key) {
+            (Some(database_url), Some(cors_origins), Some(jwt_key))
+                if settings.problems.is_empty() =>
+            {
+                Ok(Config {
+                    bind_address,
+                    database_url,
+                    database_pool_size,
+                    cors_origins,
+                    jwt_key,
+                    token_lifetime,
+                    migrations,
+                    websocket_broadcast,
+                    websocket,
+                    log_format,
+                    metrics_token,
+                })
+            }
+            _ => Err(ConfigError(settings.problems)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::{Broadcast, Config, ConfigError, LogFormat, Migrations, WebsocketConfig};
+
+    fn load(env: &[(&str, &str)], file: &str) -> Result<Config, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        Config::from_sources(|name| env.get(name).cloned(), file.parse().unwrap())
+    }
+
+    #[test]
+    fn test_loads_defaults_from_env() {
+        let config = load(
+            &[
+                ("DATABASE_URL", "postgres://localhost/test"),
+                ("CLIENT_HOST", "http://localhost:3000"),
+                ("JWT_KEY", "secret"),
+            ],
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                bind_address: "0.0.0.0:8080".to_string(),
+                database_url: "postgres://localhost/test".to_string(),
+                database_pool_size: 10,
+                cors_origins: vec!["http://localhost:3000".to_string()],
+                jwt_key: "secret".to_string(),
+                token_lifetime: Duration::from_secs(10800),
+                migrations: Migrations::Check,
+                websocket_broadcast: Broadcast::InProcess,
+                websocket: WebsocketConfig::default(),
+                log_format: LogFormat::Text,
+                metrics_token: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_env_takes_precedence_over_file() {
+        let config = load(
+            &[
+                ("CORS_ORIGINS", "http://a.test, http://b.test"),
+                ("WS_HEARTBEAT_INTERVAL_SECS", "10"),
+                ("LOG_FORMAT", "json"),
+            ],
+            r#"
+                database_url = "postgres://localhost/test"
+                database_pool_size = 4
+                cors_origins = ["http://c.test"]
+                jwt_key = "secret"
+                migrations = "run"
+                ws_heartbeat_interval_secs = 2
+                ws_reaction_throttle_ms = 100
+                metrics_token = "scrape"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.database_pool_size, 4);
+        assert_eq!(
+            config.cors_origins,
+            vec!["http://a.test".to_string(), "http://b.test".to_string()]
+        );
+        assert_eq!(config.migrations, Migrations::Run);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.metrics_token, Some("scrape".to_string()));
+        assert_eq!(config.websocket.heartbeat_interval, Duration::from_secs(10));
+        assert_eq!(
+            config.websocket.reaction_throttle,
+            Duration::from_millis(100)
+        );
+    }
+
+    #[test]
+    fn test_reports_every_problem_together() {
+        let err = load(
+            &[("DATABASE_POOL_SIZE", "lots"), ("MIGRATIONS", "later")],
+            "ws_client_timeout_secs = 1",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err,
+            ConfigError(vec![
+                "DATABASE_URL must be set".to_string(),
+                "DATABASE_POOL_SIZE is invalid - invalid digit found in string".to_string(),
+                "CORS_ORIGINS must be set".to_string(),
+                "JWT_KEY must be set".to_string(),
+                "MIGRATIONS is invalid - expected check, run or skip, got later".to_string(),
+                "WS_CLIENT_TIMEOUT_SECS must be longer than WS_HEARTBEAT_INTERVAL_SECS".to_string(),
+            ])
+        );
+    }
+}
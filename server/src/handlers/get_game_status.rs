@@ -1,10 +1,7 @@
-use actix_web::web::block;
-use diesel::pg::PgConnection;
-use diesel::r2d2::{ConnectionManager, PooledConnection};
-use diesel::{BelongingToDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 
-use db::models::{Game, Round};
+use db::models::{PicksVisibility, Round};
+use db::repository::Repository;
 use errors::Error;
 
 #[derive(Deserialize, Serialize)]
@@ -12,23 +9,19 @@ pub struct StatusResponse {
     pub slug: String,
     pub open_round: bool,
     pub unfinished_round: bool,
+    pub picks_visibility: PicksVisibility,
 }
 
 pub async fn get_game_status(
-    connection: PooledConnection<ConnectionManager<PgConnection>>,
+    repository: &dyn Repository,
     game_id: i32,
 ) -> Result<StatusResponse, Error> {
-    let data: Result<(Game, Vec<Round>), Error> = block(move || {
-        let game = Game::find_by_id(&connection, game_id)?;
-        let rounds = Round::belonging_to(&game).load::<Round>(&connection)?;
-        Ok((game, rounds))
-    })
-    .await?;
-
-    let (game, rounds) = data?;
+    let game = repository.find_game(game_id).await?;
+    let rounds = repository.find_rounds_by_game(game_id).await?;
 
     Ok(StatusResponse {
         slug: game.slug.unwrap_or_else(|| "".to_string()),
+        picks_visibility: game.picks_visibility,
         open_round: rounds.iter().fold(false, |result: bool, round: &Round| {
             // if there is a round that's not locked, we want to return true
             if !round.locked {
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use db::{
+    models::{LeaderboardSnapshot, UserDetails},
+    repository::{rank_users, Repository},
+};
+use errors::Error;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LeaderboardEntry {
+    /// Competition rank, so players on the same score share a rank and the next one is skipped
+    pub rank: i32,
+    pub user_id: i32,
+    pub user_name: String,
+    pub score: i32,
+    /// Points scored in the last finished round
+    pub round_score: i32,
+    /// Places moved up in the last finished round, negative when moving down. None for players
+    /// that weren't ranked in both it and the round before it.
+    pub movement: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Leaderboard {
+    /// The round `round_score` is for
+    pub round_id: Option<i32>,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Standing {
+    pub user_id: i32,
+    pub score: i32,
+    pub round_score: i32,
+    pub rank: i32,
+}
+
+/// The leaderboard as it was at the end of a round
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RoundStandings {
+    pub round_id: i32,
+    pub standings: Vec<Standing>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetLeaderboardResponse {
+    #[serde(flatten)]
+    pub leaderboard: Leaderboard,
+    /// Every scored round, oldest first, for charting scores over time
+    pub history: Vec<RoundStandings>,
+}
+
+/// Sorts players by score, ties broken by name, and ranks them. `round_scores` maps user ids to
+/// the points they got in the last round, anyone missing scored nothing.
+pub fn rank_players(
+    users: Vec<UserDetails>,
+    round_scores: &HashMap<i32, i32>,
+) -> Vec<LeaderboardEntry> {
+    rank_users(users)
+        .into_iter()
+        .map(|(rank, user)| LeaderboardEntry {
+            rank,
+            round_score: *round_scores.get(&user.id).unwrap_or(&0),
+            user_id: user.id,
+            user_name: user.user_name,
+            score: user.score,
+            movement: None,
+        })
+        .collect()
+}
+
+fn group_by_round(snapshots: Vec<LeaderboardSnapshot>) -> Vec<RoundStandings> {
+    let mut history: Vec<RoundStandings> = Vec::new();
+    for snapshot in snapshots {
+        let standing = Standing {
+            user_id: snapshot.user_id,
+            score: snapshot.score,
+            round_score: snapshot.round_score,
+            rank: snapshot.rank,
+        };
+        match history.last_mut() {
+            Some(round) if round.round_id == snapshot.round_id => round.standings.push(standing),
+            _ => history.push(RoundStandings {
+                round_id: snapshot.round_id,
+                standings: vec![standing],
+            }),
+        }
+    }
+
+    history
+}
+
+/// Ranks the game's players by their current scores, using the snapshots taken as rounds are
+/// scored for the last round's points and the movement since the round before it
+pub async fn get_leaderboard(
+    repository: &dyn Repository,
+    game_id: i32,
+) -> Result<GetLeaderboardResponse, Error> {
+    let users = repository.find_users_by_game(game_id).await?;
+    let history = group_by_round(repository.find_leaderboard_snapshots(game_id).await?);
+
+    let latest = history.last();
+    let round_scores: HashMap<i32, i32> = latest
+        .map(|round| {
+            round
+                .standings
+                .iter()
+                .map(|standing| (standing.user_id, standing.round_score))
+                .collect()
+        })
+        .unwrap_or_default();
+    let ranks = |round: Option<&RoundStandings>| -> HashMap<i32, i32> {
+        round
+            .map(|round| {
+                round
+                    .standings
+                    .iter()
+                    .map(|standing| (standing.user_id, standing.rank))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let latest_ranks = ranks(latest);
+    let previous_ranks = ranks(history.len().checked_sub(2).and_then(|i| history.get(i)));
+
+    let mut entries = rank_players(users, &round_scores);
+    for entry in &mut entries {
+        // between the two snapshots, so it isn't thrown off by players joining or leaving since
+        entry.movement = previous_ranks
+            .get(&entry.user_id)
+            .zip(latest_ranks.get(&entry.user_id))
+            .map(|(previous_rank, latest_rank)| previous_rank - latest_rank);
+    }
+
+    Ok(GetLeaderboardResponse {
+        leaderboard: Leaderboard {
+            round_id: latest.map(|round| round.round_id),
+            entries,
+        },
+        history,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use db::models::UserDetails;
+
+    use super::rank_players;
+
+    fn user(id: i32, user_name: &str, score: i32) -> UserDetails {
+        UserDetails {
+            id,
+            user_name: user_name.to_string(),
+            game_id: 1,
+            score,
+        }
+    }
+
+    #[test]
+    fn test_rank_players_shares_ranks_on_ties() {
+        let users = vec![
+            user(1, "zerg", 3),
+            user(2, "protoss", 5),
+            user(3, "terran", 3),
+            user(4, "random", 1),
+        ];
+        let mut round_scores = HashMap::new();
+        round_scores.insert(2, 2);
+        round_scores.insert(3, 1);
+
+        let entries = rank_players(users, &round_scores);
+        let ranks: Vec<(i32, &str, i32)> = entries
+            .iter()
+            .map(|e| (e.rank, e.user_name.as_str(), e.round_score))
+            .collect();
+        assert_eq!(
+            ranks,
+            vec![
+                (1, "protoss", 2),
+                (2, "terran", 1),
+                (2, "zerg", 0),
+                (4, "random", 0),
+            ]
+        );
+    }
+}
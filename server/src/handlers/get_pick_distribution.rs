@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use auth::Role;
+use db::models::{QuestionDetails, RoundAnswer, UserAnswer};
+use db::repository::Repository;
+use errors::Error;
+
+use super::picks_revealed;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AnswerCount {
+    pub answer: String,
+    pub count: usize,
+    /// Share of the players that answered the question, out of 100
+    pub percentage: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QuestionDistribution {
+    pub question_id: i32,
+    pub body: String,
+    /// How many players answered the question
+    pub total: usize,
+    /// Most picked first
+    pub answers: Vec<AnswerCount>,
+    /// Set once the round is scored
+    pub correct_answer: Option<String>,
+    pub correct_percentage: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PickDistribution {
+    pub round_id: i32,
+    pub locked: bool,
+    pub finished: bool,
+    pub questions: Vec<QuestionDistribution>,
+    /// Whether the game's picks visibility lets players and spectators see it yet
+    #[serde(skip)]
+    pub revealed: bool,
+}
+
+impl PickDistribution {
+    pub fn visible_to(&self, role: &Role) -> bool {
+        *role == Role::Owner || self.revealed
+    }
+}
+
+fn percentage(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    // one decimal place is plenty for showing to the room
+    (count as f64 * 1000.0 / total as f64).round() / 10.0
+}
+
+/// Counts the distinct answers picked for each question. `correct` is empty until the round is
+/// scored.
+pub fn tally_picks(
+    questions: Vec<QuestionDetails>,
+    picks: &[UserAnswer],
+    correct: &[RoundAnswer],
+) -> Vec<QuestionDistribution> {
+    questions
+        .into_iter()
+        .map(|question| {
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for pick in picks.iter().filter(|pick| pick.question_id == question.id) {
+                *counts.entry(pick.answer.as_str()).or_insert(0) += 1;
+            }
+            let total = counts.values().sum();
+
+            let mut answers: Vec<AnswerCount> = counts
+                .into_iter()
+                .map(|(answer, count)| AnswerCount {
+                    answer: answer.to_string(),
+                    count,
+                    percentage: percentage(count, total),
+                })
+                .collect();
+            answers.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.answer.cmp(&b.answer)));
+
+            let correct_answer = correct
+                .iter()
+                .find(|answer| answer.question_id == question.id)
+                .map(|answer| answer.answer.clone());
+            let correct_percentage = correct_answer.as_ref().map(|correct_answer| {
+                let count = answers
+                    .iter()
+                    .find(|answer| &answer.answer == correct_answer)
+                    .map(|answer| answer.count)
+                    .unwrap_or(0);
+                percentage(count, total)
+            });
+
+            QuestionDistribution {
+                question_id: question.id,
+                body: question.body,
+                total,
+                answers,
+                correct_answer,
+                correct_percentage,
+            }
+        })
+        .collect()
+}
+
+/// How the game's players picked in its latest round
+pub async fn get_pick_distribution(
+    repository: &dyn Repository,
+    game_id: i32,
+) -> Result<PickDistribution, Error> {
+    let game = repository.find_game(game_id).await?;
+    let round = repository.find_latest_round(game_id).await?;
+    let mut questions = repository.find_questions_by_game(game_id).await?;
+    questions.sort_by_key(|question| question.id);
+    let picks = repository.find_picks_by_round(round.id).await?;
+    let correct = repository.find_round_answers(round.id).await?;
+
+    Ok(PickDistribution {
+        round_id: round.id,
+        locked: round.locked,
+        finished: round.finished,
+        questions: tally_picks(questions, &picks, &correct),
+        revealed: picks_revealed(game.picks_visibility, &round),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use db::models::{QuestionDetails, RoundAnswer, UserAnswer};
+
+    use super::{tally_picks, AnswerCount};
+
+    fn pick(question_id: i32, user_id: i32, answer: &str) -> UserAnswer {
+        UserAnswer {
+            id: 0,
+            question_id,
+            user_id,
+            answer: answer.to_string(),
+            user_name: format!("player{}", user_id),
+        }
+    }
+
+    #[test]
+    fn test_tally_picks() {
+        let questions = vec![
+            QuestionDetails {
+                id: 1,
+                body: "Who wins?".to_string(),
+            },
+            QuestionDetails {
+                id: 2,
+                body: "Cheese?".to_string(),
+            },
+        ];
+        let picks = vec![
+            pick(1, 1, "Serral"),
+            pick(1, 2, "Serral"),
+            pick(1, 3, "Maru"),
+            pick(1, 4, "Serral"),
+            pick(1, 5, "Serral"),
+        ];
+        let now = Utc::now();
+        let correct = vec![RoundAnswer {
+            id: 1,
+            round_id: 1,
+            question_id: 1,
+            answer: "Maru".to_string(),
+            created_at: now,
+            updated_at: now,
+        }];
+
+        let distribution = tally_picks(questions, &picks, &correct);
+        assert_eq!(distribution[0].total, 5);
+        assert_eq!(
+            distribution[0].answers,
+            vec![
+                AnswerCount {
+                    answer: "Serral".to_string(),
+                    count: 4,
+                    percentage: 80.0,
+                },
+                AnswerCount {
+                    answer: "Maru".to_string(),
+                    count: 1,
+                    percentage: 20.0,
+                },
+            ]
+        );
+        assert_eq!(distribution[0].correct_answer, Some("Maru".to_string()));
+        assert_eq!(distribution[0].correct_percentage, Some(20.0));
+
+        // nobody answered, and it hasn't been scored
+        assert_eq!(distribution[1].total, 0);
+        assert!(distribution[1].answers.is_empty());
+        assert_eq!(distribution[1].correct_percentage, None);
+    }
+}
@@ -0,0 +1,37 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use db::models::UserDetails;
+use db::repository::Repository;
+use errors::Error;
+
+#[derive(Deserialize, Serialize)]
+pub struct PlayerDetails {
+    #[serde(flatten)]
+    pub user: UserDetails,
+    /// Whether the player has a websocket session open
+    pub online: bool,
+}
+
+impl PlayerDetails {
+    pub fn from_users(users: Vec<UserDetails>, online_ids: &HashSet<i32>) -> Vec<PlayerDetails> {
+        users
+            .into_iter()
+            .map(|user| PlayerDetails {
+                online: online_ids.contains(&user.id),
+                user,
+            })
+            .collect()
+    }
+}
+
+pub async fn get_players(
+    repository: &dyn Repository,
+    game_id: i32,
+    online_ids: HashSet<i32>,
+) -> Result<Vec<PlayerDetails>, Error> {
+    let users = repository.find_users_by_game(game_id).await?;
+
+    Ok(PlayerDetails::from_users(users, &online_ids))
+}
@@ -1,13 +1,13 @@
-use actix_web::web::block;
-use diesel::pg::PgConnection;
-use diesel::r2d2::{ConnectionManager, PooledConnection};
+use std::collections::HashSet;
+
 use serde::{Deserialize, Serialize};
 
 use auth::Role;
-use db::models::{GameQuestion, QuestionDetails, Round, UserQuestion};
+use db::models::QuestionDetails;
+use db::repository::Repository;
 use errors::Error;
 
-#[derive(Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
 pub struct RoundStatusRepsonse {
     pub player_names: Vec<String>,
     pub questions: Vec<QuestionDetails>,
@@ -18,26 +18,21 @@ pub struct RoundStatusRepsonse {
 }
 
 pub async fn get_round_status(
-    connection: PooledConnection<ConnectionManager<PgConnection>>,
+    repository: &dyn Repository,
     role: Role,
     user_id: i32,
     game_id: i32,
 ) -> Result<RoundStatusRepsonse, Error> {
-    let data: Result<(Round, Vec<QuestionDetails>, Vec<UserQuestion>), Error> = block(move || {
-        let round = Round::get_latest_round_by_game_id(&connection, game_id)?;
-        let questions = GameQuestion::get_questions_by_game_id(&connection, game_id)?;
-
-        let user_questions = if role == Role::Player {
-            UserQuestion::find_by_round_and_user(&connection, round.id, user_id)?
-        } else {
-            Vec::new()
-        };
+    let round = repository.find_latest_round(game_id).await?;
+    let questions = repository.find_questions_by_game(game_id).await?;
 
-        Ok((round, questions, user_questions))
-    })
-    .await?;
-
-    let (round, questions, user_questions) = data?;
+    let user_questions = if role == Role::Player {
+        repository
+            .find_picks_by_round_and_user(round.id, user_id)
+            .await?
+    } else {
+        Vec::new()
+    };
 
     Ok(RoundStatusRepsonse {
         player_names: vec![round.player_one, round.player_two],
@@ -48,3 +43,41 @@ pub async fn get_round_status(
         picks_chosen: user_questions.len() > 0,
     })
 }
+
+/// Each player's users id, paired with whether they have chosen picks
+pub type PlayersPicked = Vec<(i32, bool)>;
+
+/// Round status for everyone in a game. The response is the host's view, so `picks_chosen` is
+/// false, and the players are listed with whether they have chosen picks this round.
+pub async fn get_round_status_for_game(
+    repository: &dyn Repository,
+    game_id: i32,
+) -> Result<(RoundStatusRepsonse, PlayersPicked), Error> {
+    let round = repository.find_latest_round(game_id).await?;
+    let questions = repository.find_questions_by_game(game_id).await?;
+
+    let picked_user_ids: HashSet<i32> = repository
+        .find_picks_by_round(round.id)
+        .await?
+        .iter()
+        .map(|user_answer| user_answer.user_id)
+        .collect();
+    let players: PlayersPicked = repository
+        .find_users_by_game(game_id)
+        .await?
+        .iter()
+        .map(|user| (user.id, picked_user_ids.contains(&user.id)))
+        .collect();
+
+    Ok((
+        RoundStatusRepsonse {
+            player_names: vec![round.player_one, round.player_two],
+            questions,
+            round_id: round.id,
+            locked: round.locked,
+            finished: round.finished,
+            picks_chosen: false,
+        },
+        players,
+    ))
+}
@@ -1,9 +1,8 @@
-use actix_web::web::block;
-use diesel::pg::PgConnection;
-use diesel::r2d2::{ConnectionManager, PooledConnection};
 use serde::{Deserialize, Serialize};
 
-use db::models::{Round, UserAnswer, UserQuestion};
+use auth::Role;
+use db::models::{PicksVisibility, Round, UserAnswer};
+use db::repository::Repository;
 use errors::Error;
 
 #[derive(Deserialize, PartialEq, Serialize)]
@@ -12,22 +11,41 @@ pub struct GetRoundPicksResponse {
     pub locked: bool,
 }
 
-pub async fn get_round_picks(
-    connection: PooledConnection<ConnectionManager<PgConnection>>,
-    game_id: i32,
-) -> Result<GetRoundPicksResponse, Error> {
-    let data: Result<(Vec<UserAnswer>, bool), Error> = block(move || {
-        let round = Round::get_active_round_by_game_id(&connection, game_id)?;
+/// The picks for a game's latest round, and whether the game's policy lets players and
+/// spectators see them yet
+pub struct RoundPicks {
+    pub response: GetRoundPicksResponse,
+    pub revealed: bool,
+}
 
-        let user_questions = UserQuestion::find_by_round(&connection, round.id)?;
-        Ok((user_questions, round.locked))
-    })
-    .await?;
+impl RoundPicks {
+    pub fn visible_to(&self, role: &Role) -> bool {
+        *role == Role::Owner || self.revealed
+    }
+}
 
-    let (user_questions, locked) = data?;
+/// Whether everyone's picks can be shown to players and spectators, for a round in this state
+pub fn picks_revealed(visibility: PicksVisibility, round: &Round) -> bool {
+    match visibility {
+        PicksVisibility::HostOnly => false,
+        PicksVisibility::AfterLock => round.locked,
+        PicksVisibility::AfterScoring => round.finished,
+    }
+}
+
+pub async fn get_round_picks(
+    repository: &dyn Repository,
+    game_id: i32,
+) -> Result<RoundPicks, Error> {
+    let game = repository.find_game(game_id).await?;
+    let round = repository.find_latest_round(game_id).await?;
+    let user_questions = repository.find_picks_by_round(round.id).await?;
 
-    Ok(GetRoundPicksResponse {
-        data: user_questions,
-        locked,
+    Ok(RoundPicks {
+        revealed: picks_revealed(game.picks_visibility, &round),
+        response: GetRoundPicksResponse {
+            data: user_questions,
+            locked: round.locked,
+        },
     })
 }
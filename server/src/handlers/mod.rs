@@ -1,7 +1,19 @@
-mod get_game_status;
-mod get_round_details;
-mod get_round_picks;
-
-pub use self::get_game_status::*;
-pub use self::get_round_details::*;
-pub use self::get_round_picks::*;
+mod get_game_status;
+mod get_leaderboard;
+mod get_pick_distribution;
+mod get_players;
+mod get_round_details;
+mod get_round_picks;
+mod save_picks;
+mod send_chat_message;
+mod verify_claim;
+
+pub use self::get_game_status::*;
+pub use self::get_leaderboard::*;
+pub use self::get_pick_distribution::*;
+pub use self::get_players::*;
+pub use self::get_round_details::*;
+pub use self::get_round_picks::*;
+pub use self::save_picks::*;
+pub use self::send_chat_message::*;
+pub use self::verify_claim::*;
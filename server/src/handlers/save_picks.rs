@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use auth::PrivateClaim;
+use db::repository::{Pick, Repository};
+use errors::Error;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Answer {
+    pub id: i32,
+    pub value: String,
+}
+
+async fn validate_user_has_not_picked(
+    repository: &dyn Repository,
+    claim: &PrivateClaim,
+    round_id: i32,
+) -> Result<(), Error> {
+    let results = repository
+        .find_picks_by_round_and_user(round_id, claim.id)
+        .await?;
+    if !results.is_empty() {
+        return Err(Error::BadRequest(
+            "User has already chosen picks for this round".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn validate_selected_questions(
+    repository: &dyn Repository,
+    claim: &PrivateClaim,
+    answers: &[Answer],
+) -> Result<(), Error> {
+    let questions = repository.find_questions_by_game(claim.game_id).await?;
+
+    if questions.len() != answers.len() {
+        return Err(Error::BadRequest(format!(
+            "Received {} answers, expected {}",
+            answers.len(),
+            questions.len()
+        )));
+    }
+
+    let mut question_ids = HashSet::<i32>::new();
+    for question in &questions {
+        question_ids.insert(question.id);
+    }
+    // check if any answers map to questions not in this game
+    for answer in answers {
+        if !question_ids.contains(&answer.id) {
+            return Err(Error::BadRequest(format!(
+                "Invalid question id: {}",
+                answer.id
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn to_picks(answers: Vec<Answer>) -> Vec<Pick> {
+    answers
+        .into_iter()
+        .map(|answer| Pick {
+            question_id: answer.id,
+            answer: answer.value,
+        })
+        .collect()
+}
+
+/// Saves a player's picks for the game's open round
+pub async fn save_picks(
+    repository: &dyn Repository,
+    claim: PrivateClaim,
+    answers: Vec<Answer>,
+) -> Result<(), Error> {
+    let round = repository.find_active_round(claim.game_id).await?;
+    validate_user_has_not_picked(repository, &claim, round.id).await?;
+    validate_selected_questions(repository, &claim, &answers).await?;
+    repository
+        .create_picks(claim.game_id, claim.id, to_picks(answers))
+        .await
+}
+
+/// Replaces a player's picks for the game's open round, or saves them if they haven't picked yet
+pub async fn update_picks(
+    repository: &dyn Repository,
+    claim: PrivateClaim,
+    answers: Vec<Answer>,
+) -> Result<(), Error> {
+    repository.find_active_round(claim.game_id).await?;
+    validate_selected_questions(repository, &claim, &answers).await?;
+    repository
+        .replace_picks(claim.game_id, claim.id, to_picks(answers))
+        .await
+}
@@ -0,0 +1,55 @@
+use chrono::{Duration, Utc};
+use validator::Validate;
+
+use auth::{PrivateClaim, Role};
+use db::{models::ChatMessageDetails, repository::Repository};
+use errors::Error;
+
+use crate::validate::validate_params;
+
+/// Window that `MAX_RECENT_CHAT_MESSAGES` applies to
+const CHAT_RATE_LIMIT_SECONDS: i64 = 10;
+
+#[derive(Validate)]
+struct NewChatMessageParams {
+    #[validate(length(
+        min = "1",
+        max = "500",
+        message = "Message must be between 1 and 500 characters"
+    ))]
+    body: String,
+    /// Messages sent by the same person within the rate limit window
+    #[validate(range(
+        min = "0",
+        max = "4",
+        message = "Sending messages too quickly, try again shortly"
+    ))]
+    recent_messages: i64,
+}
+
+/// Saves a chat message from a player, or from the host. Spectators can only read the chat.
+pub async fn send_chat_message(
+    repository: &dyn Repository,
+    claim: PrivateClaim,
+    body: String,
+) -> Result<ChatMessageDetails, Error> {
+    let user_id = match claim.role {
+        Role::Player => Some(claim.id),
+        Role::Owner => None,
+        Role::Spectator => return Err(Error::Forbidden),
+    };
+
+    let since = Utc::now() - Duration::seconds(CHAT_RATE_LIMIT_SECONDS);
+    let recent_messages = repository
+        .count_chat_messages_since(claim.game_id, user_id, since)
+        .await?;
+    let body = body.trim().to_string();
+    validate_params(&NewChatMessageParams {
+        body: body.clone(),
+        recent_messages,
+    })?;
+
+    repository
+        .create_chat_message(claim.game_id, user_id, body)
+        .await
+}
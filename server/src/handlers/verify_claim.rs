@@ -0,0 +1,17 @@
+use auth::{PrivateClaim, Role};
+use db::repository::Repository;
+use errors::Error;
+
+/// Checks a player's claim still belongs to someone in the game. Tokens outlive a kick, so a
+/// kicked player's is refused here instead.
+pub async fn verify_claim(repository: &dyn Repository, claim: &PrivateClaim) -> Result<(), Error> {
+    if claim.role != Role::Player {
+        return Ok(());
+    }
+
+    match repository.find_user(claim.game_id, claim.id).await {
+        Ok(_) => Ok(()),
+        Err(Error::NotFound(_)) => Err(Error::Unauthorized),
+        Err(err) => Err(err),
+    }
+}
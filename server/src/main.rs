@@ -2,61 +2,171 @@
 #[macro_use]
 extern crate diesel;
 #[macro_use]
-extern crate log;
+extern crate tracing;
 #[macro_use]
 extern crate validator_derive;
 
-use std::env;
+#[cfg(all(feature = "sqlite", feature = "postgres_broadcast"))]
+compile_error!("postgres_broadcast needs Postgres, build with --no-default-features for SQLite");
+
+use std::io;
+use std::sync::Arc;
 
 use actix::Actor;
 use actix_cors::Cors;
 use actix_rt;
-use actix_web::{http, middleware::Logger, web, App, HttpResponse, HttpServer};
+use actix_web::{http, web, App, HttpResponse, HttpServer};
 use dotenv::dotenv;
-use env_logger;
+use tracing_subscriber::EnvFilter;
 
+mod config;
 mod handlers;
+mod metrics;
 mod middleware;
 mod routes;
 mod tests;
 mod validate;
 mod websocket;
 
+use crate::config::{Broadcast, Config, LogFormat, Migrations};
+use crate::metrics::Metrics;
+use crate::middleware::{RequestMetrics, RequestTracing, REQUEST_ID_HEADER};
 use crate::routes::routes;
-use db;
+use db::{
+    self, get_conn, migrations,
+    repository::{DbRepository, Repository},
+    DbPool,
+};
+
+/// Runs any pending migrations when started with `MIGRATIONS=run`. Otherwise fails if any are
+/// pending, so the server doesn't start against a schema it doesn't match, unless started with
+/// `MIGRATIONS=skip`.
+async fn prepare_schema(pool: &DbPool, config: &Config) -> Result<(), String> {
+    match config.migrations {
+        Migrations::Skip => return Ok(()),
+        Migrations::Run => {
+            let versions = migrations::run_pending_migrations(config.database_url.clone())
+                .await
+                .map_err(|err| err.to_string())?;
+            for version in versions {
+                info!("Ran migration {}", version);
+            }
+        }
+        Migrations::Check => {}
+    }
+
+    let mut connection = get_conn(pool).await.map_err(|err| err.to_string())?;
+    let status = migrations::schema_status(&mut connection)
+        .await
+        .map_err(|err| format!("Could not read the schema version - {}", err))?;
+    if !status.is_up_to_date() {
+        return Err(format!(
+            "Database schema is out of date, migrations {} have not been run. Start with \
+             MIGRATIONS=run to run them.",
+            status.pending.join(", ")
+        ));
+    }
+
+    info!(
+        "Database schema is at version {}",
+        status.version.unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Logs what `RUST_LOG` asks for, defaulting to debug. Records from crates still using `log` are
+/// picked up too, inside whichever span they were logged in.
+fn init_tracing(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("debug"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("debug"));
 
-    let pool = db::new_pool();
+    let config = Config::load();
+    // invalid config is still logged, just not in the format it asked for
+    init_tracing(
+        config
+            .as_ref()
+            .map_or(LogFormat::Text, |config| config.log_format),
+    );
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", err);
+            return Err(io::Error::other(err.to_string()));
+        }
+    };
+    auth::configure(auth::JwtSettings {
+        key: config.jwt_key.clone(),
+        token_lifetime: chrono::Duration::from_std(config.token_lifetime)
+            .map_err(io::Error::other)?,
+    });
+
+    let pool = db::create_pool(config.database_url.clone(), config.database_pool_size);
+    if let Err(err) = prepare_schema(&pool, &config).await {
+        error!("{}", err);
+        return Err(io::Error::other(err));
+    }
+
+    let repository: Arc<dyn Repository> = Arc::new(DbRepository::new(pool.clone()));
 
-    let server = websocket::Server::new(pool.clone()).start();
+    let server = match config.websocket_broadcast {
+        #[cfg(feature = "postgres_broadcast")]
+        Broadcast::Postgres => websocket::Server::with_backend(
+            repository.clone(),
+            Box::new(websocket::PostgresBackend::new(
+                config.database_url.clone(),
+                pool.clone(),
+            )),
+        ),
+        Broadcast::InProcess => websocket::Server::new(repository.clone()),
+    }
+    .start();
 
+    let metrics = web::Data::new(Metrics::new(config.metrics_token.clone()));
+    let bind_address = config.bind_address.clone();
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin(&env::var("CLIENT_HOST").unwrap())
+        let cors = config
+            .cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
             .allow_any_method()
             .allowed_headers(vec![
                 http::header::AUTHORIZATION,
                 http::header::ACCEPT,
                 http::header::CONTENT_TYPE,
+                http::header::HeaderName::from_static(REQUEST_ID_HEADER),
             ])
+            .expose_headers(vec![REQUEST_ID_HEADER])
             // .allow_any_header()
             .max_age(3600);
 
         App::new()
             .wrap(cors)
-            .wrap(Logger::default())
-            .wrap(Logger::new("%a %{User-Agent}i"))
             .wrap(auth::get_identity_service())
+            .wrap(RequestMetrics::new(metrics.clone()))
+            .wrap(RequestTracing)
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(repository.clone()))
             .app_data(web::Data::new(server.clone()))
+            .app_data(web::Data::new(config.websocket.clone()))
+            .app_data(metrics.clone())
             .configure(routes)
             .default_service(web::to(|| HttpResponse::NotFound()))
     })
-    .bind("0.0.0.0:8080")?
+    .bind(bind_address)?
     .run()
     .await
 }
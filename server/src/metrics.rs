@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use deadpool::Status;
+
+use crate::websocket::ServerStats;
+
+/// Upper bounds of the request latency histogram's buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Latency {
+    /// Requests in each of `LATENCY_BUCKETS`, not counting the ones in smaller buckets
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Requests {
+    // (method, route, status) -> requests
+    counts: BTreeMap<(String, String, u16), u64>,
+    // (method, route) -> latency
+    latencies: BTreeMap<(String, String), Latency>,
+}
+
+/// Collects metrics about the requests handled, to be served to Prometheus along with the pool's
+/// and the websocket server's
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<Requests>,
+    /// The bearer token needed to read the metrics. Anyone can when it isn't set.
+    token: Option<String>,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    pub fn new(token: Option<String>) -> Self {
+        Metrics {
+            requests: Mutex::default(),
+            token,
+        }
+    }
+
+    /// Whether a request with this `Authorization` header can read the metrics
+    pub fn authorized(&self, authorization: Option<&str>) -> bool {
+        match &self.token {
+            Some(token) => {
+                authorization.and_then(|value| value.strip_prefix("Bearer "))
+                    == Some(token.as_str())
+            }
+            None => true,
+        }
+    }
+
+    fn requests(&self) -> MutexGuard<'_, Requests> {
+        self.requests.lock().expect("metrics lock poisoned")
+    }
+
+    /// `route` is the pattern the request matched, such as `/api/games/{id}`, so each game doesn't
+    /// get its own series
+    pub fn record_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let mut requests = self.requests();
+        *requests
+            .counts
+            .entry((method.to_string(), route.to_string(), status))
+            .or_insert(0) += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        let latency = requests
+            .latencies
+            .entry((method.to_string(), route.to_string()))
+            .or_default();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            latency.buckets[bucket] += 1;
+        }
+        latency.sum += seconds;
+        latency.count += 1;
+    }
+
+    /// Formats every metric in Prometheus' text format
+    pub fn render(&self, pool: &Status, server: &ServerStats) -> String {
+        let mut out = String::new();
+
+        {
+            let requests = self.requests();
+            write_header(
+                &mut out,
+                "http_requests_total",
+                "counter",
+                "Requests handled, by route and status.",
+            );
+            for ((method, route, status), count) in &requests.counts {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                    method,
+                    escape(route),
+                    status,
+                    count
+                );
+            }
+
+            write_header(
+                &mut out,
+                "http_request_duration_seconds",
+                "histogram",
+                "How long requests took to handle, by route.",
+            );
+            for ((method, route), latency) in &requests.latencies {
+                let labels = format!("method=\"{}\",route=\"{}\"", method, escape(route));
+                let mut cumulative = 0;
+                for (le, count) in LATENCY_BUCKETS.iter().zip(latency.buckets.iter()) {
+                    cumulative += count;
+                    let _ = writeln!(
+                        out,
+                        "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                        labels, le, cumulative
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                    labels, latency.count
+                );
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_sum{{{}}} {}",
+                    labels, latency.sum
+                );
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_count{{{}}} {}",
+                    labels, latency.count
+                );
+            }
+        }
+
+        let gauges = [
+            (
+                "db_pool_max_connections",
+                "Connections the pool can hold.",
+                pool.max_size,
+            ),
+            (
+                "db_pool_connections",
+                "Connections the pool has open.",
+                pool.size,
+            ),
+            (
+                "db_pool_idle_connections",
+                "Open connections not in use.",
+                pool.available,
+            ),
+            (
+                "db_pool_waiting",
+                "Requests waiting for a connection.",
+                pool.waiting,
+            ),
+            (
+                "websocket_sessions",
+                "Open websocket and event stream sessions.",
+                server.sessions,
+            ),
+            (
+                "websocket_games",
+                "Games with sessions joined to them.",
+                server.games,
+            ),
+            (
+                "websocket_game_sessions",
+                "Sessions joined to a game.",
+                server.game_sessions,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            write_header(&mut out, name, "gauge", help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        write_header(
+            &mut out,
+            "websocket_messages_broadcast_total",
+            "counter",
+            "Messages sent to games' sessions.",
+        );
+        let _ = writeln!(
+            out,
+            "websocket_messages_broadcast_total {}",
+            server.messages_broadcast
+        );
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use deadpool::Status;
+
+    use super::Metrics;
+    use crate::websocket::ServerStats;
+
+    #[test]
+    fn test_renders_request_histogram() {
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/api/games/{id}", 200, Duration::from_millis(20));
+        metrics.record_request("GET", "/api/games/{id}", 404, Duration::from_secs(3));
+
+        let status = Status {
+            max_size: 10,
+            size: 2,
+            available: 1,
+            waiting: 0,
+        };
+        let stats = ServerStats {
+            sessions: 4,
+            games: 2,
+            game_sessions: 3,
+            messages_broadcast: 7,
+        };
+        let out = metrics.render(&status, &stats);
+
+        for line in [
+            "http_requests_total{method=\"GET\",route=\"/api/games/{id}\",status=\"200\"} 1",
+            "http_requests_total{method=\"GET\",route=\"/api/games/{id}\",status=\"404\"} 1",
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/games/{id}\",le=\"0.01\"} 0",
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/games/{id}\",le=\"0.025\"} 1",
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/games/{id}\",le=\"2.5\"} 1",
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/games/{id}\",le=\"5\"} 2",
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/games/{id}\",le=\"+Inf\"} 2",
+            "http_request_duration_seconds_count{method=\"GET\",route=\"/api/games/{id}\"} 2",
+            "db_pool_max_connections 10",
+            "db_pool_idle_connections 1",
+            "websocket_sessions 4",
+            "websocket_games 2",
+            "websocket_game_sessions 3",
+            "websocket_messages_broadcast_total 7",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {}", line);
+        }
+    }
+
+    #[test]
+    fn test_metrics_need_the_token_when_one_is_set() {
+        let metrics = Metrics::new(Some("secret".to_string()));
+        assert!(metrics.authorized(Some("Bearer secret")));
+        assert!(!metrics.authorized(Some("Bearer other")));
+        assert!(!metrics.authorized(Some("secret")));
+        assert!(!metrics.authorized(None));
+
+        assert!(Metrics::default().authorized(None));
+    }
+}
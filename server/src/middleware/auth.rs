@@ -1,4 +1,5 @@
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task::{Context, Poll};
 
 use actix_identity::RequestIdentity;
@@ -6,21 +7,27 @@ use actix_service::{Service, Transform};
 use actix_web::{
     body::BoxBody,
     dev::{ServiceRequest, ServiceResponse},
-    Error, HttpResponse,
+    web::Data,
+    Error, HttpResponse, ResponseError,
 };
 use futures::{
     future::{ok, Ready},
     Future,
 };
+use tracing::Span;
 
 use auth::{decode_jwt, PrivateClaim};
+use db::repository::Repository;
 use errors;
 
+use super::record_claim;
+use crate::handlers::verify_claim;
+
 pub struct Auth;
 
 impl<S> Transform<S, ServiceRequest> for Auth
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<BoxBody>;
@@ -30,17 +37,19 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(AuthMiddleware { service })
+        ok(AuthMiddleware {
+            service: Rc::new(service),
+        })
     }
 }
 
 pub struct AuthMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S> Service<ServiceRequest> for AuthMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<BoxBody>;
@@ -56,36 +65,55 @@ where
         let private_claim: Result<PrivateClaim, errors::Error> = decode_jwt(&identity);
 
         // decode uses default validation to ensure not expired, changed, etc.
-        if private_claim.is_ok() {
-            let fut = self.service.call(req);
+        if let Ok(private_claim) = private_claim {
+            record_claim(&Span::current(), &private_claim);
+            let repository = req.app_data::<Data<dyn Repository>>().cloned();
+            let service = self.service.clone();
             Box::pin(async move {
-                let res = fut.await?;
-                Ok(res)
+                if let Some(repository) = repository {
+                    match verify_claim(repository.get_ref(), &private_claim).await {
+                        Ok(_) => {}
+                        Err(errors::Error::Unauthorized) => return Ok(unauthorized(req)),
+                        Err(err) => return Ok(req.into_response(err.error_response())),
+                    }
+                }
+
+                service.call(req).await
             })
         } else {
-            Box::pin(async move {
-                let error: errors::ErrorResponse = "Unauthorized".into();
-                Ok(req.into_response(HttpResponse::Unauthorized().json(error)))
-            })
+            Box::pin(async move { Ok(unauthorized(req)) })
         }
     }
 }
 
+fn unauthorized(req: ServiceRequest) -> ServiceResponse<BoxBody> {
+    let error: errors::ErrorResponse = "Unauthorized".into();
+    req.into_response(HttpResponse::Unauthorized().json(error))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use chrono::{Duration, Utc};
 
     use auth::{PrivateClaim, Role};
+    use db::repository::MemoryRepository;
     use errors::ErrorResponse;
 
-    use crate::tests::helpers::tests::{get_auth_token, test_get};
+    use crate::tests::helpers::tests::{get_auth_token, test_get_in_memory};
 
     #[actix_rt::test]
     async fn test_expired_token_unauthorized() {
         let mut claim = PrivateClaim::new(1, "".to_string(), 1, Role::Owner);
         claim.set_exp((Utc::now() - Duration::minutes(1)).timestamp());
         let cookie = get_auth_token(claim);
-        let res = test_get(&format!("/api/games/{}/players", 1), Some(cookie)).await;
+        let res = test_get_in_memory(
+            Arc::new(MemoryRepository::new()),
+            &format!("/api/games/{}/players", 1),
+            Some(cookie),
+        )
+        .await;
         assert_eq!(res.0, 401);
 
         let body: ErrorResponse = res.1;
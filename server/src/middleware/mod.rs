@@ -1,3 +1,7 @@
 mod auth;
+mod metrics;
+mod request_tracing;
 
 pub use self::auth::*;
+pub use self::metrics::*;
+pub use self::request_tracing::*;
@@ -0,0 +1,157 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use tracing::{field, Instrument, Span};
+use uuid::Uuid;
+
+use auth::{PrivateClaim, Role};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Ids from a proxy in front of the server are kept, as long as they're reasonable to log
+fn incoming_request_id(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    if id.is_empty() || id.len() > 128 {
+        return None;
+    }
+
+    Some(id.to_string())
+}
+
+/// Adds who the claim belongs to to `span`, which needs empty game_id and user_id fields. Hosts
+/// and spectators aren't users, so only have a game_id.
+pub fn record_claim(span: &Span, claim: &PrivateClaim) {
+    span.record("game_id", &claim.game_id);
+    if claim.role == Role::Player {
+        span.record("user_id", &claim.id);
+    }
+}
+
+/// Runs each request in a span with its own id, which is sent back in the `X-Request-Id` header.
+/// Everything logged while handling the request, including by the `Auth` middleware, which adds
+/// the game_id and user_id to the span, is tagged with it.
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestTracingMiddleware { service })
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let request_id = incoming_request_id(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+            remote_addr = req.connection_info().realip_remote_addr().unwrap_or("-"),
+            game_id = field::Empty,
+            user_id = field::Empty,
+        );
+        // middleware further in reads the span while it's being called, not just when its
+        // future is polled
+        let fut = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                let mut res = fut.await?;
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                info!(
+                    status = res.status().as_u16(),
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "request finished"
+                );
+
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::test::{self, TestRequest};
+
+    use db::repository::MemoryRepository;
+
+    use super::REQUEST_ID_HEADER;
+    use crate::tests::helpers::tests::get_memory_service;
+
+    #[actix_rt::test]
+    async fn test_responds_with_a_request_id() {
+        let srv = get_memory_service(Arc::new(MemoryRepository::new())).await;
+
+        let first = test::call_service(&srv, TestRequest::get().uri("/healthz").to_request()).await;
+        let second =
+            test::call_service(&srv, TestRequest::get().uri("/healthz").to_request()).await;
+
+        let first = first.headers().get(REQUEST_ID_HEADER).unwrap();
+        let second = second.headers().get(REQUEST_ID_HEADER).unwrap();
+        assert_eq!(first.len(), 36);
+        assert_ne!(first, second);
+    }
+
+    #[actix_rt::test]
+    async fn test_keeps_an_incoming_request_id() {
+        let srv = get_memory_service(Arc::new(MemoryRepository::new())).await;
+
+        let req = TestRequest::get()
+            .uri("/healthz")
+            .insert_header((REQUEST_ID_HEADER, "from-the-proxy"))
+            .to_request();
+        let res = test::call_service(&srv, req).await;
+
+        assert_eq!(
+            res.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "from-the-proxy"
+        );
+    }
+}
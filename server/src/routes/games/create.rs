@@ -1,91 +1,68 @@
 use actix_web::{
-    web::{block, Data, Json},
+    web::{Data, Json},
     Result,
 };
 use serde::{Deserialize, Serialize};
 
 use db::{
-    get_conn,
-    models::{Game, GameQuestion},
-    PgPool,
+    models::{Game, PicksVisibility},
+    repository::Repository,
 };
 use errors::Error;
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct CreateGameRequest {
     question_ids: Vec<i32>,
-}
-
-fn create_db_records(pool: Data<PgPool>, params: Json<CreateGameRequest>) -> Result<Game, Error> {
-    use diesel::connection::Connection;
-    let connection = get_conn(&pool).unwrap();
-
-    connection.transaction::<Game, Error, _>(|| {
-        let game = Game::create(&connection)?;
-
-        for question_id in &params.question_ids {
-            GameQuestion::create(&connection, game.id, *question_id)?;
-        }
-
-        Ok(game)
-    })
+    /// Who can see everyone's picks, only the host unless set
+    #[serde(default)]
+    picks_visibility: PicksVisibility,
 }
 
 pub async fn create(
-    pool: Data<PgPool>,
+    repository: Data<dyn Repository>,
     params: Json<CreateGameRequest>,
 ) -> Result<Json<Game>, Error> {
-    let res: Result<Game, Error> = block(move || create_db_records(pool, params)).await?;
-    let game = res?;
+    let game = repository
+        .create_game(&params.question_ids, params.picks_visibility)
+        .await?;
 
     Ok(Json(game))
 }
 
 #[cfg(test)]
 mod tests {
-    use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+    use std::sync::Arc;
 
-    use crate::tests::helpers::tests::test_post;
+    use crate::tests::helpers::tests::test_post_in_memory;
     use db::{
-        get_conn,
-        models::{Game, Question},
-        new_pool,
-        schema::{game_questions, games, questions},
+        models::{Game, PicksVisibility},
+        repository::{MemoryRepository, QuestionRepository},
     };
 
     use super::CreateGameRequest;
 
     #[actix_rt::test]
     async fn test_create_game() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-        let question = diesel::insert_into(questions::table)
-            .values(questions::dsl::body.eq("This is the question"))
-            .get_result::<Question>(&conn)
-            .unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let question = repository.add_question("This is the question");
 
-        let res: (u16, Game) = test_post(
+        let res: (u16, Game) = test_post_in_memory(
+            repository.clone(),
             "/api/games",
             CreateGameRequest {
                 question_ids: vec![question.id],
+                picks_visibility: PicksVisibility::AfterLock,
             },
             None,
         )
         .await;
 
         assert_eq!(res.0, 200);
+        assert_eq!(res.1.picks_visibility, PicksVisibility::AfterLock);
 
-        let gqs = game_questions::dsl::game_questions
-            .select(game_questions::dsl::id)
-            .load::<i32>(&conn)
-            .unwrap();
+        let gqs = repository.find_questions_by_game(res.1.id).await.unwrap();
 
         assert_eq!(gqs.len(), 1);
-
-        diesel::delete(game_questions::table)
-            .execute(&conn)
-            .unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
-        diesel::delete(questions::table).execute(&conn).unwrap();
+        assert_eq!(gqs[0].id, question.id);
     }
 }
@@ -0,0 +1,129 @@
+use actix::Addr;
+use actix_identity::Identity;
+use actix_web::{
+    web::{Data, Path},
+    HttpResponse,
+};
+use serde_json::json;
+
+use auth::{get_claim_from_identity, Role};
+use db::repository::Repository;
+use errors::Error;
+
+use crate::websocket::{MessageToClient, Server, Topic};
+
+/// Lets the host remove a message from the chat. Everyone in the game is sent its id on
+/// `/chat-deleted`.
+pub async fn delete_chat_message(
+    id: Identity,
+    params: Path<(i32, i32)>,
+    repository: Data<dyn Repository>,
+    websocket_srv: Data<Addr<Server>>,
+) -> Result<HttpResponse, Error> {
+    let (game_id, message_id) = params.into_inner();
+    let (claim, _) = get_claim_from_identity(id)?;
+    if claim.role != Role::Owner || claim.game_id != game_id {
+        return Err(Error::Forbidden);
+    }
+
+    repository.delete_chat_message(game_id, message_id).await?;
+
+    websocket_srv.do_send(MessageToClient::new(
+        Topic::ChatDeleted,
+        game_id,
+        json!({ "id": message_id }),
+    ));
+
+    Ok(HttpResponse::Ok().json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web_actors::ws;
+    use awc::Client;
+    use futures::SinkExt;
+    use serde_json::json;
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::PicksVisibility,
+        repository::{ChatRepository, GameRepository, MemoryRepository, UserRepository},
+    };
+
+    use crate::tests::helpers::tests::{get_auth_token, get_memory_test_server, read_until_path};
+    use crate::websocket::Topic;
+
+    #[actix_rt::test]
+    async fn test_only_host_can_delete_chat_messages() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
+            .unwrap();
+        let message = repository
+            .create_chat_message(game.id, Some(user.id), "Hello".to_string())
+            .await
+            .unwrap();
+
+        let srv = get_memory_test_server(repository.clone());
+        let client = Client::default();
+        let route = format!("/api/games/{}/chat/{}", game.id, message.id);
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let res = srv
+            .delete(&route)
+            .insert_header(("Authorization", player_token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+
+        let mut player_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        player_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", player_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut player_ws, Topic::Players).await;
+
+        let owner_token = game.creator.clone().unwrap();
+        let res = srv
+            .delete(&route)
+            .insert_header(("Authorization", owner_token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        let msg = read_until_path(&mut player_ws, Topic::ChatDeleted).await;
+        assert_eq!(msg.data, json!({ "id": message.id }));
+        assert!(repository
+            .find_chat_messages(game.id, None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let res = srv
+            .delete(&route)
+            .insert_header(("Authorization", owner_token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 404);
+
+        drop(player_ws);
+        srv.stop().await;
+    }
+}
@@ -0,0 +1,198 @@
+use actix::Addr;
+use actix_identity::Identity;
+use actix_web::{
+    http::header::{CacheControl, CacheDirective},
+    web::{Data, Path},
+    HttpRequest, HttpResponse,
+};
+
+use auth::get_claim_from_identity;
+use errors::Error;
+
+use crate::config::WebsocketConfig;
+use crate::websocket::{parse_event_id, start_event_stream, Server};
+
+/// Server-Sent Events alternative to the websocket, for networks that block websockets. Streams
+/// the same events, and resumes from the `Last-Event-ID` header when the client reconnects.
+pub async fn events(
+    id: Identity,
+    game_id: Path<i32>,
+    req: HttpRequest,
+    websocket_srv: Data<Addr<Server>>,
+    config: Data<WebsocketConfig>,
+) -> Result<HttpResponse, Error> {
+    let (claim, token) = get_claim_from_identity(id)?;
+    if claim.game_id != game_id.into_inner() {
+        return Err(Error::Forbidden);
+    }
+
+    let resume = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_event_id);
+
+    let stream = start_event_stream(
+        websocket_srv.get_ref().clone(),
+        token,
+        resume,
+        config.keep_alive_interval,
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use std::sync::Arc;
+
+    use actix_web::web::Bytes;
+    use futures::{Stream, StreamExt};
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::PicksVisibility,
+        repository::{GameRepository, MemoryRepository, UserRepository},
+    };
+
+    use crate::tests::helpers::tests::{get_auth_token, get_memory_test_server};
+    use crate::websocket::parse_event_id;
+
+    /// Reads the stream until an event for `path` arrives, returning its id line and data
+    async fn read_until_event<S, E>(stream: &mut S, path: &str) -> (Option<String>, String)
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Debug,
+    {
+        let mut buffer = String::new();
+        loop {
+            while let Some(end) = buffer.find("\n\n") {
+                let event: String = buffer.drain(..end + 2).collect();
+                let mut id = None;
+                let mut event_path = None;
+                let mut data = None;
+                for line in event.lines() {
+                    if let Some(value) = line.strip_prefix("id: ") {
+                        id = Some(value.to_string());
+                    } else if let Some(value) = line.strip_prefix("event: ") {
+                        event_path = Some(value.to_string());
+                    } else if let Some(value) = line.strip_prefix("data: ") {
+                        data = Some(value.to_string());
+                    }
+                }
+                if event_path.as_deref() == Some(path) {
+                    return (id, data.unwrap());
+                }
+            }
+
+            let chunk = actix_rt::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .expect("Timed out waiting for event")
+                .expect("Event stream ended")
+                .unwrap();
+            buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_event_stream_sends_events_and_resumes() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
+            .unwrap();
+
+        let srv = get_memory_test_server(repository);
+        let route = format!("/api/games/{}/events", game.id);
+
+        let owner_token = game.creator.clone().unwrap();
+        let mut res = srv
+            .get(&route)
+            .insert_header(("Authorization", owner_token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+
+        let (players_id, data) = read_until_event(&mut res, "/players").await;
+        assert!(data.contains("agmcleod"));
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let mut player_res = srv
+            .get(&route)
+            .insert_header(("Authorization", player_token))
+            .send()
+            .await
+            .unwrap();
+        read_until_event(&mut player_res, "/presence").await;
+        drop(player_res);
+
+        // the player coming online is missed, then replayed on reconnect
+        drop(res);
+        let mut res = srv
+            .get(&route)
+            .insert_header(("Authorization", owner_token))
+            .insert_header(("Last-Event-ID", players_id.clone().unwrap()))
+            .send()
+            .await
+            .unwrap();
+        let (presence_id, data) = read_until_event(&mut res, "/presence").await;
+        let players_id = parse_event_id(&players_id.unwrap()).unwrap();
+        let presence_id = parse_event_id(&presence_id.unwrap()).unwrap();
+        assert_eq!(presence_id.epoch, players_id.epoch);
+        assert!(presence_id.seq > players_id.seq);
+        assert!(data.contains("\"online\":true"));
+
+        drop(res);
+        srv.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_event_stream_requires_matching_game() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let srv = get_memory_test_server(repository);
+        let route = format!("/api/games/{}/events", game.id);
+
+        let res = srv.get(&route).send().await.unwrap();
+        assert_eq!(res.status().as_u16(), 401);
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id + 1,
+            Role::Spectator,
+        ));
+        let res = srv
+            .get(&route)
+            .insert_header(("Authorization", token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+
+        srv.stop().await;
+    }
+}
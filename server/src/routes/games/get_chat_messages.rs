@@ -0,0 +1,166 @@
+use actix_identity::Identity;
+use actix_web::web::{Data, Json, Path, Query};
+use serde::{Deserialize, Serialize};
+
+use auth::identity_matches_game_id;
+use db::{models::ChatMessageDetails, repository::Repository};
+use errors::Error;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct ChatMessagesQuery {
+    before: Option<i32>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetChatMessagesResponse {
+    pub data: Vec<ChatMessageDetails>,
+    /// Pass as `before` to load the previous page. None once the start of the chat is reached.
+    pub next_before: Option<i32>,
+}
+
+pub async fn get_chat_messages(
+    id: Identity,
+    game_id: Path<i32>,
+    query: Query<ChatMessagesQuery>,
+    repository: Data<dyn Repository>,
+) -> Result<Json<GetChatMessagesResponse>, Error> {
+    let game_id = game_id.into_inner();
+    identity_matches_game_id(id, game_id)?;
+
+    let before = query.before;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let data = repository
+        .find_chat_messages(game_id, before, limit)
+        .await?;
+
+    let next_before = if data.len() as i64 == limit {
+        data.first().map(|message| message.id)
+    } else {
+        None
+    };
+
+    Ok(Json(GetChatMessagesResponse { data, next_before }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::PicksVisibility,
+        repository::{ChatRepository, GameRepository, MemoryRepository, UserRepository},
+    };
+    use errors::ErrorResponse;
+
+    use super::GetChatMessagesResponse;
+    use crate::tests::helpers::tests::{get_auth_token, test_get_in_memory};
+
+    #[actix_rt::test]
+    async fn test_get_chat_messages_paginates() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
+            .unwrap();
+
+        repository
+            .create_chat_message(game.id, None, "Welcome".to_string())
+            .await
+            .unwrap();
+        for i in 0..3 {
+            repository
+                .create_chat_message(game.id, Some(user.id), format!("Message {}", i))
+                .await
+                .unwrap();
+        }
+
+        let token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+
+        let (status, page): (u16, GetChatMessagesResponse) = test_get_in_memory(
+            repository.clone(),
+            &format!("/api/games/{}/chat?limit=2", game.id),
+            Some(token.clone()),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let bodies: Vec<&str> = page.data.iter().map(|m| m.body.as_str()).collect();
+        assert_eq!(bodies, vec!["Message 1", "Message 2"]);
+        assert_eq!(page.data[0].user_name, Some("agmcleod".to_string()));
+        assert!(page.next_before.is_some());
+
+        let (status, page): (u16, GetChatMessagesResponse) = test_get_in_memory(
+            repository.clone(),
+            &format!(
+                "/api/games/{}/chat?limit=2&before={}",
+                game.id,
+                page.next_before.unwrap()
+            ),
+            Some(token),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let bodies: Vec<&str> = page.data.iter().map(|m| m.body.as_str()).collect();
+        assert_eq!(bodies, vec!["Welcome", "Message 0"]);
+        assert_eq!(page.data[0].user_id, None);
+
+        let (status, page): (u16, GetChatMessagesResponse) = test_get_in_memory(
+            repository,
+            &format!(
+                "/api/games/{}/chat?limit=2&before={}",
+                game.id,
+                page.next_before.unwrap()
+            ),
+            Some(get_auth_token(PrivateClaim::new(
+                game.id,
+                game.slug.clone().unwrap(),
+                game.id,
+                Role::Spectator,
+            ))),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(page.data.is_empty());
+        assert_eq!(page.next_before, None);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_chat_messages_of_another_game() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id + 1,
+            Role::Owner,
+        ));
+        let (status, _): (u16, ErrorResponse) = test_get_in_memory(
+            repository,
+            &format!("/api/games/{}/chat", game.id),
+            Some(token),
+        )
+        .await;
+        assert_eq!(status, 403);
+    }
+}
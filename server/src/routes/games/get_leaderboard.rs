@@ -0,0 +1,219 @@
+use actix_identity::Identity;
+use actix_web::web::{Data, Json, Path};
+
+use auth::identity_matches_game_id;
+use db::repository::Repository;
+use errors::Error;
+
+use crate::handlers::{self, GetLeaderboardResponse};
+
+pub async fn get_leaderboard(
+    id: Identity,
+    game_id: Path<i32>,
+    repository: Data<dyn Repository>,
+) -> Result<Json<GetLeaderboardResponse>, Error> {
+    let game_id = game_id.into_inner();
+    identity_matches_game_id(id, game_id)?;
+
+    let leaderboard = handlers::get_leaderboard(repository.get_ref(), game_id).await?;
+
+    Ok(Json(leaderboard))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::{PicksVisibility, Round},
+        repository::{
+            GameRepository, MemoryRepository, Pick, PickRepository, RoundRepository, UserRepository,
+        },
+    };
+
+    use crate::handlers::GetLeaderboardResponse;
+    use crate::tests::helpers::tests::{get_auth_token, test_get_in_memory};
+
+    fn picks(question_ids: &[i32], answers: &[&str]) -> Vec<Pick> {
+        question_ids
+            .iter()
+            .zip(answers)
+            .map(|(question_id, answer)| Pick {
+                question_id: *question_id,
+                answer: answer.to_string(),
+            })
+            .collect()
+    }
+
+    /// Opens a round, saves everyone's picks, then locks and scores it
+    async fn play_round(
+        repository: &MemoryRepository,
+        game_id: i32,
+        question_ids: &[i32],
+        user_picks: Vec<(i32, [&str; 2])>,
+        answers: [&str; 2],
+    ) -> Round {
+        let round = repository
+            .create_round(game_id, "one".to_string(), "two".to_string())
+            .await
+            .unwrap();
+        for (user_id, user_answers) in user_picks {
+            repository
+                .create_picks(game_id, user_id, picks(question_ids, &user_answers))
+                .await
+                .unwrap();
+        }
+        repository.lock_active_round(game_id).await.unwrap();
+        assert!(repository
+            .score_round(game_id, picks(question_ids, &answers))
+            .await
+            .unwrap());
+
+        round
+    }
+
+    #[actix_rt::test]
+    async fn test_get_leaderboard_with_movement_and_history() {
+        let repository = Arc::new(MemoryRepository::new());
+        let question_ids = [
+            repository.add_question("Who wins?").id,
+            repository.add_question("Any cannon rushes?").id,
+        ];
+        let game = repository
+            .create_game(&question_ids, PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let mut players = Vec::new();
+        for user_name in ["agmcleod", "smurf", "zerg"] {
+            players.push(
+                repository
+                    .create_user(game.id, user_name.to_string())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let first = play_round(
+            &repository,
+            game.id,
+            &question_ids,
+            vec![
+                (players[0].id, ["one", "no"]),
+                (players[1].id, ["one", "yes"]),
+                (players[2].id, ["one", "no"]),
+            ],
+            ["one", "yes"],
+        )
+        .await;
+        let second = play_round(
+            &repository,
+            game.id,
+            &question_ids,
+            vec![
+                (players[0].id, ["two", "no"]),
+                (players[1].id, ["one", "yes"]),
+                (players[2].id, ["two", "no"]),
+            ],
+            ["two", "no"],
+        )
+        .await;
+        // joined after the last round was scored, so isn't in its standings
+        repository
+            .create_user(game.id, "late".to_string())
+            .await
+            .unwrap();
+
+        let token = get_auth_token(PrivateClaim::new(
+            players[0].id,
+            players[0].user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let (status, res): (u16, GetLeaderboardResponse) = test_get_in_memory(
+            repository.clone(),
+            &format!("/api/games/{}/leaderboard", game.id),
+            Some(token.clone()),
+        )
+        .await;
+        assert_eq!(status, 200);
+
+        assert_eq!(res.leaderboard.round_id, Some(second.id));
+        let entries: Vec<(i32, &str, i32, i32, Option<i32>)> = res
+            .leaderboard
+            .entries
+            .iter()
+            .map(|e| {
+                (
+                    e.rank,
+                    e.user_name.as_str(),
+                    e.score,
+                    e.round_score,
+                    e.movement,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (1, "agmcleod", 3, 2, Some(1)),
+                (1, "zerg", 3, 2, Some(1)),
+                (3, "smurf", 2, 0, Some(-2)),
+                (4, "late", 0, 0, None),
+            ]
+        );
+
+        assert_eq!(res.history.len(), 2);
+        assert_eq!(res.history[0].round_id, first.id);
+        assert_eq!(res.history[0].standings[0].user_id, players[1].id);
+        assert_eq!(res.history[1].round_id, second.id);
+        assert_eq!(res.history[1].standings.len(), 3);
+
+        // zerg leaving moves smurf up to second, but they still fell two places in the last round
+        repository
+            .delete_user(game.id, players[2].id)
+            .await
+            .unwrap();
+        let (_, res): (u16, GetLeaderboardResponse) = test_get_in_memory(
+            repository,
+            &format!("/api/games/{}/leaderboard", game.id),
+            Some(token),
+        )
+        .await;
+        let smurf = res
+            .leaderboard
+            .entries
+            .iter()
+            .find(|e| e.user_id == players[1].id)
+            .unwrap();
+        assert_eq!(smurf.rank, 2);
+        assert_eq!(smurf.movement, Some(-2));
+    }
+
+    #[actix_rt::test]
+    async fn test_get_leaderboard_before_any_rounds() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id,
+            Role::Spectator,
+        ));
+        let (status, res): (u16, GetLeaderboardResponse) = test_get_in_memory(
+            repository,
+            &format!("/api/games/{}/leaderboard", game.id),
+            Some(token),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(res.leaderboard.round_id, None);
+        assert!(res.leaderboard.entries.is_empty());
+        assert!(res.history.is_empty());
+    }
+}
@@ -1,85 +1,62 @@
+use actix::Addr;
 use actix_identity::Identity;
-use actix_web::web::{block, Data, Json, Path};
+use actix_web::web::{Data, Json, Path};
 
 use auth::identity_matches_game_id;
-use db::{
-    get_conn,
-    models::{User, UserDetails},
-    PgPool,
-};
+use db::repository::Repository;
 use errors;
 
+use crate::handlers::{self, PlayerDetails};
+use crate::websocket::{GetOnlinePlayers, Server};
+
 pub async fn get_players(
     id: Identity,
     game_id: Path<i32>,
-    pool: Data<PgPool>,
-) -> Result<Json<Vec<UserDetails>>, errors::Error> {
+    repository: Data<dyn Repository>,
+    websocket_srv: Data<Addr<Server>>,
+) -> Result<Json<Vec<PlayerDetails>>, errors::Error> {
     let game_id = game_id.into_inner();
     identity_matches_game_id(id, game_id)?;
 
-    let connection = get_conn(&pool)?;
-    let res = block(move || User::find_all_by_game_id(&connection, game_id)).await?;
-    let users = res?;
+    let online_ids = websocket_srv.send(GetOnlinePlayers { game_id }).await?;
+
+    let players = handlers::get_players(repository.get_ref(), game_id, online_ids).await?;
 
-    Ok(Json(users))
+    Ok(Json(players))
 }
 
 #[cfg(test)]
 mod tests {
-    use diesel::{self, RunQueryDsl};
+    use std::sync::Arc;
 
-    use crate::tests::helpers::tests::{get_auth_token, test_get};
+    use crate::handlers::PlayerDetails;
+    use crate::tests::helpers::tests::{get_auth_token, test_get_in_memory};
     use auth::{PrivateClaim, Role};
     use db::{
-        get_conn,
-        models::{Game, User, UserDetails},
-        new_pool,
-        schema::{games, users},
+        models::PicksVisibility,
+        repository::{GameRepository, MemoryRepository, UserRepository},
     };
     use errors::ErrorResponse;
 
-    #[derive(Insertable)]
-    #[table_name = "users"]
-    struct NewUser {
-        user_name: String,
-        game_id: i32,
-    }
-
-    #[derive(Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        slug: Option<String>,
-    }
-
     #[actix_rt::test]
     async fn test_get_players_as_player() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .default_values()
-            .get_result(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
             .unwrap();
-
-        let game_2: Game = diesel::insert_into(games::table)
-            .default_values()
-            .get_result(&conn)
+        let game_2 = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
             .unwrap();
 
-        let user: User = diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod".to_string(),
-                game_id: game.id,
-            })
-            .get_result(&conn)
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
             .unwrap();
-
-        diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod2".to_string(),
-                game_id: game_2.id,
-            })
-            .execute(&conn)
+        repository
+            .create_user(game_2.id, "agmcleod2".to_string())
+            .await
             .unwrap();
 
         let cookie = get_auth_token(PrivateClaim::new(
@@ -88,109 +65,94 @@ mod tests {
             game.id,
             Role::Owner,
         ));
-        let res = test_get(&format!("/api/games/{}/players", game.id), Some(cookie)).await;
+        let res = test_get_in_memory(
+            repository,
+            &format!("/api/games/{}/players", game.id),
+            Some(cookie),
+        )
+        .await;
         assert_eq!(res.0, 200);
 
-        let body: Vec<UserDetails> = res.1;
+        let body: Vec<PlayerDetails> = res.1;
         // only returns one, as second player is part of another game
         assert_eq!(body.len(), 1);
-
-        diesel::delete(users::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        assert_eq!(body[0].user.user_name, "agmcleod");
+        assert!(!body[0].online);
     }
 
     #[actix_rt::test]
     async fn test_get_players_as_owner() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
-
-        diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod".to_string(),
-                game_id: game.id,
-            })
-            .execute(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
             .unwrap();
 
-        diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod2".to_string(),
-                game_id: game.id,
-            })
-            .execute(&conn)
-            .unwrap();
-
-        let token = get_auth_token(PrivateClaim::new(
-            game.id,
-            game.slug.unwrap(),
-            game.id,
-            Role::Player,
-        ));
-        let res = test_get(&format!("/api/games/{}/players", game.id), Some(token)).await;
+        let mut users = Vec::new();
+        for user_name in ["agmcleod", "agmcleod2"] {
+            users.push(
+                repository
+                    .create_user(game.id, user_name.to_string())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let token = users[0].session_id.clone();
+        let res = test_get_in_memory(
+            repository,
+            &format!("/api/games/{}/players", game.id),
+            token,
+        )
+        .await;
         assert_eq!(res.0, 200);
 
-        let body: Vec<UserDetails> = res.1;
+        let body: Vec<PlayerDetails> = res.1;
         // returns both as they are both apart of this game
         assert_eq!(body.len(), 2);
-
-        diesel::delete(users::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 
     #[actix_rt::test]
     async fn test_get_players_forbidden() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        let other_game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        let user = repository
+            .create_user(other_game.id, "agmcleod".to_string())
+            .await
             .unwrap();
 
-        let token = get_auth_token(PrivateClaim::new(
-            1,
-            "".to_string(),
-            game.id + 1,
-            Role::Player,
-        ));
-        let res = test_get(&format!("/api/games/{}/players", game.id), Some(token)).await;
+        let res = test_get_in_memory(
+            repository,
+            &format!("/api/games/{}/players", game.id),
+            user.session_id,
+        )
+        .await;
         assert_eq!(res.0, 403);
 
         let body: ErrorResponse = res.1;
         assert_eq!(body.errors.get(0).unwrap(), "Forbidden");
-
-        diesel::delete(users::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 
     #[actix_rt::test]
     async fn test_get_players_unauthorized() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
             .unwrap();
 
-        let res = test_get(&format!("/api/games/{}/players", game.id), None).await;
+        let res =
+            test_get_in_memory(repository, &format!("/api/games/{}/players", game.id), None).await;
         assert_eq!(res.0, 401);
 
         let body: ErrorResponse = res.1;
         assert_eq!(body.errors.get(0).unwrap(), "Unauthorized");
-
-        diesel::delete(users::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 }
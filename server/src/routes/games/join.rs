@@ -1,18 +1,16 @@
+use actix::Addr;
 use actix_web::{
-    web::{block, Data, Json},
+    web::{Data, Json},
     Result,
 };
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
-use db::{
-    get_conn,
-    models::{Game, User},
-    PgPool,
-};
+use db::{models::User, repository::Repository};
 use errors::Error;
 
 use crate::validate::validate;
+use crate::websocket::{client_messages, Server};
 
 #[derive(Clone, Deserialize, Serialize, Validate)]
 pub struct JoinRequest {
@@ -22,66 +20,62 @@ pub struct JoinRequest {
     slug: String,
 }
 
-pub async fn join(pool: Data<PgPool>, params: Json<JoinRequest>) -> Result<Json<User>, Error> {
+pub async fn join(
+    repository: Data<dyn Repository>,
+    websocket_srv: Data<Addr<Server>>,
+    params: Json<JoinRequest>,
+) -> Result<Json<User>, Error> {
     validate(&params)?;
-    let connection = get_conn(&pool).unwrap();
-
-    let res = block(move || {
-        let game = Game::find_by_slug(&connection, &params.slug)?;
-        if User::find_by_game_id_and_name(&connection, game.id, &params.name).is_ok() {
-            return Err(Error::UnprocessableEntity("Username is taken".to_string()));
-        }
-        let new_user = User::create(&connection, params.name.clone(), game.id)?;
-        Ok(new_user)
-    })
-    .await?;
+    let game = repository.find_game_by_slug(&params.slug).await?;
+    if repository
+        .find_user_by_name(game.id, &params.name)
+        .await
+        .is_ok()
+    {
+        return Err(Error::UnprocessableEntity("Username is taken".to_string()));
+    }
+    let new_user = repository.create_user(game.id, params.name.clone()).await?;
 
-    let new_user = res?;
+    client_messages::send_players(&websocket_srv, repository.get_ref(), new_user.game_id).await;
 
     Ok(Json(new_user))
 }
 
 #[cfg(test)]
 mod tests {
-    use diesel::RunQueryDsl;
+    use std::sync::Arc;
+
+    use actix_web_actors::ws;
+    use awc::Client;
+    use futures::SinkExt;
 
     use db::{
-        get_conn,
-        models::{Game, User},
-        new_pool,
-        schema::{games, users},
+        models::{Game, PicksVisibility, User},
+        repository::{GameRepository, MemoryRepository, UserRepository},
     };
     use errors::ErrorResponse;
 
     use super::JoinRequest;
-    use crate::tests::helpers::tests::test_post;
-
-    #[derive(Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        slug: String,
-    }
+    use crate::handlers::PlayerDetails;
+    use crate::tests::helpers::tests::{
+        get_memory_test_server, read_until_path, test_post_in_memory,
+    };
+    use crate::websocket::Topic;
 
-    #[derive(Insertable)]
-    #[table_name = "users"]
-    struct NewUser {
-        user_name: String,
-        game_id: i32,
+    async fn create_game(repository: &MemoryRepository) -> Game {
+        repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap()
     }
 
     #[actix_rt::test]
     async fn test_join_game() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
 
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: "abc123".to_string(),
-            })
-            .get_result(&conn)
-            .unwrap();
-
-        let res: (u16, User) = test_post(
+        let res: (u16, User) = test_post_in_memory(
+            repository,
             "/api/games/join",
             JoinRequest {
                 name: "agmcleod".to_string(),
@@ -94,14 +88,51 @@ mod tests {
         assert_eq!(res.0, 200);
 
         assert_eq!(res.1.user_name, "agmcleod");
+        assert_eq!(res.1.game_id, game.id);
+    }
+
+    #[actix_rt::test]
+    async fn test_join_game_sends_players() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
+
+        let srv = get_memory_test_server(repository);
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", game.creator.unwrap()).into(),
+            ))
+            .await
+            .unwrap();
+        let msg = read_until_path(&mut ws_conn, Topic::Players).await;
+        assert_eq!(msg.data, serde_json::json!([]));
+
+        let res = srv
+            .post("/api/games/join")
+            .send_json(&JoinRequest {
+                name: "agmcleod".to_string(),
+                slug: game.slug.unwrap(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        let msg = read_until_path(&mut ws_conn, Topic::Players).await;
+        let players: Vec<PlayerDetails> = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].user.user_name, "agmcleod");
+        assert!(!players[0].online);
 
-        diesel::delete(users::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        drop(ws_conn);
+        srv.stop().await;
     }
 
     #[actix_rt::test]
     async fn test_game_not_found() {
-        let res: (u16, ErrorResponse) = test_post(
+        let repository = Arc::new(MemoryRepository::new());
+        let res: (u16, ErrorResponse) = test_post_in_memory(
+            repository,
             "/api/games/join",
             JoinRequest {
                 name: "agmcleod".to_string(),
@@ -116,28 +147,19 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_join_game_with_duplicate_name() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
 
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: "newgam".to_string(),
-            })
-            .get_result(&conn)
-            .unwrap();
-
-        diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod".to_string(),
-                game_id: game.id,
-            })
-            .execute(&conn)
+        repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
             .unwrap();
 
-        let res: (u16, ErrorResponse) = test_post(
+        let res: (u16, ErrorResponse) = test_post_in_memory(
+            repository,
             "/api/games/join",
             JoinRequest {
-                slug: "newgam".to_string(),
+                slug: game.slug.unwrap(),
                 name: "agmcleod".to_string(),
             },
             None,
@@ -146,8 +168,5 @@ mod tests {
 
         assert_eq!(res.0, 422);
         assert_eq!(res.1.errors[0], "Username is taken");
-
-        diesel::delete(users::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 }
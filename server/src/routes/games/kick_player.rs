@@ -0,0 +1,253 @@
+use actix::Addr;
+use actix_identity::Identity;
+use actix_web::{
+    web::{Data, Path},
+    HttpResponse,
+};
+
+use auth::{get_claim_from_identity, Role};
+use db::repository::Repository;
+use errors::Error;
+
+use crate::websocket::{client_messages, Kick, Server};
+
+/// Lets the host remove a player from the game
+pub async fn kick_player(
+    id: Identity,
+    params: Path<(i32, i32)>,
+    repository: Data<dyn Repository>,
+    websocket_srv: Data<Addr<Server>>,
+) -> Result<HttpResponse, Error> {
+    let (game_id, user_id) = params.into_inner();
+    let (claim, _) = get_claim_from_identity(id)?;
+    if claim.role != Role::Owner || claim.game_id != game_id {
+        return Err(Error::Forbidden);
+    }
+
+    repository.delete_user(game_id, user_id).await?;
+    // the player's open sessions would otherwise keep getting the game's messages
+    websocket_srv.do_send(Kick { game_id, user_id });
+
+    client_messages::send_players(&websocket_srv, repository.get_ref(), game_id).await;
+
+    Ok(HttpResponse::Ok().json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use actix_web_actors::ws;
+    use awc::Client;
+    use futures::{SinkExt, StreamExt};
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::PicksVisibility,
+        repository::{ChatRepository, GameRepository, MemoryRepository, UserRepository},
+    };
+
+    use crate::handlers::PlayerDetails;
+    use crate::tests::helpers::tests::{
+        get_auth_token, get_memory_test_server, next_reply, next_server_message, read_until_path,
+    };
+    use crate::websocket::{ErrorCode, PresenceUpdate, ServerMessage, Topic};
+
+    #[actix_rt::test]
+    async fn test_host_can_kick_players() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let mut users = Vec::new();
+        for user_name in ["agmcleod", "smurf"] {
+            users.push(
+                repository
+                    .create_user(game.id, user_name.to_string())
+                    .await
+                    .unwrap(),
+            );
+        }
+        repository
+            .create_chat_message(game.id, Some(users[1].id), "ez".to_string())
+            .await
+            .unwrap();
+
+        let srv = get_memory_test_server(repository.clone());
+        let client = Client::default();
+        let route = format!("/api/games/{}/players/{}", game.id, users[1].id);
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            users[0].id,
+            users[0].user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let res = srv
+            .delete(&route)
+            .insert_header(("Authorization", player_token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+
+        let owner_token = game.creator.clone().unwrap();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut ws_conn, Topic::Players).await;
+
+        let kicked_token = get_auth_token(PrivateClaim::new(
+            users[1].id,
+            users[1].user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let mut kicked_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        kicked_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", kicked_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut kicked_ws, Topic::Presence).await;
+        let msg = read_until_path(&mut ws_conn, Topic::Presence).await;
+        let presence: PresenceUpdate = serde_json::from_value(msg.data).unwrap();
+        assert!(presence.online);
+
+        let res = srv
+            .delete(&route)
+            .insert_header(("Authorization", owner_token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        // the kicked player's session is closed, without getting the players sent after the kick
+        loop {
+            let frame = actix_rt::time::timeout(Duration::from_secs(5), kicked_ws.next())
+                .await
+                .expect("Timed out waiting for the kicked session to close")
+                .expect("Websocket stream closed")
+                .unwrap();
+            match frame {
+                ws::Frame::Close(reason) => {
+                    assert_eq!(reason.unwrap().code, ws::CloseCode::Policy);
+                    break;
+                }
+                ws::Frame::Text(_) => panic!("Kicked session was sent {:?}", frame),
+                _ => {}
+            }
+        }
+        assert!(kicked_ws.next().await.is_none());
+
+        // going offline is announced once the `Server` has heard from the game, so it can arrive
+        // before or after the players
+        let (mut presence, mut players) = (None, None);
+        while presence.is_none() || players.is_none() {
+            if let ServerMessage::Event(msg) = next_server_message(&mut ws_conn).await {
+                match msg.path {
+                    Topic::Presence => {
+                        presence = Some(serde_json::from_value::<PresenceUpdate>(msg.data).unwrap())
+                    }
+                    Topic::Players => {
+                        players =
+                            Some(serde_json::from_value::<Vec<PlayerDetails>>(msg.data).unwrap())
+                    }
+                    _ => {}
+                }
+            }
+        }
+        assert_eq!(
+            presence.unwrap(),
+            PresenceUpdate {
+                user_id: users[1].id,
+                online: false,
+            }
+        );
+
+        let players = players.unwrap();
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].user.user_name, "agmcleod");
+
+        let remaining = repository
+            .find_chat_messages(game.id, None, 10)
+            .await
+            .unwrap();
+        assert!(remaining.is_empty());
+
+        let res = srv
+            .delete(&route)
+            .insert_header(("Authorization", owner_token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 404);
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_kicked_player_cannot_reconnect() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        let user = repository
+            .create_user(game.id, "smurf".to_string())
+            .await
+            .unwrap();
+        let kicked_token = user.session_id.clone().unwrap();
+
+        let srv = get_memory_test_server(repository);
+        let res = srv
+            .delete(format!("/api/games/{}/players/{}", game.id, user.id))
+            .insert_header(("Authorization", game.creator.clone().unwrap()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        // the token is still valid, but there's no longer a player it belongs to
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", kicked_token).into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut ws_conn).await {
+            ServerMessage::Error(error) => assert_eq!(error.code, ErrorCode::Unauthorized),
+            msg => panic!("Expected an error, got {:?}", msg),
+        }
+
+        let res = srv
+            .get(format!("/api/games/{}/events", game.id))
+            .insert_header(("Authorization", kicked_token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 401);
+
+        let res = srv
+            .get("/api/current-round")
+            .insert_header(("Authorization", kicked_token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 401);
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+}
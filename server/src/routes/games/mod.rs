@@ -1,9 +1,25 @@
 mod create;
+mod delete_chat_message;
+mod events;
+mod get_chat_messages;
+mod get_leaderboard;
 mod get_players;
 mod join;
+mod kick_player;
+mod rename_player;
+mod set_picks_visibility;
+mod spectate;
 mod status;
 
 pub use self::create::*;
+pub use self::delete_chat_message::*;
+pub use self::events::*;
+pub use self::get_chat_messages::*;
+pub use self::get_leaderboard::*;
 pub use self::get_players::*;
 pub use self::join::*;
+pub use self::kick_player::*;
+pub use self::rename_player::*;
+pub use self::set_picks_visibility::*;
+pub use self::spectate::*;
 pub use self::status::*;
@@ -0,0 +1,162 @@
+use actix::Addr;
+use actix_identity::Identity;
+use actix_web::web::{Data, Json, Path};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use auth::{get_claim_from_identity, Role};
+use db::{models::UserDetails, repository::Repository};
+use errors::Error;
+
+use crate::validate::validate;
+use crate::websocket::{client_messages, Server};
+
+#[derive(Clone, Deserialize, Serialize, Validate)]
+pub struct RenamePlayerRequest {
+    #[validate(length(min = "3"))]
+    name: String,
+}
+
+/// Changes a player's name. Players can rename themselves, and the host can rename anyone.
+pub async fn rename_player(
+    id: Identity,
+    path: Path<(i32, i32)>,
+    repository: Data<dyn Repository>,
+    websocket_srv: Data<Addr<Server>>,
+    params: Json<RenamePlayerRequest>,
+) -> Result<Json<UserDetails>, Error> {
+    validate(&params)?;
+    let (game_id, user_id) = path.into_inner();
+    let (claim, _) = get_claim_from_identity(id)?;
+    let allowed = match claim.role {
+        Role::Owner => true,
+        Role::Player => claim.id == user_id,
+        Role::Spectator => false,
+    };
+    if !allowed || claim.game_id != game_id {
+        return Err(Error::Forbidden);
+    }
+
+    match repository.find_user_by_name(game_id, &params.name).await {
+        Ok(user) if user.id != user_id => {
+            return Err(Error::UnprocessableEntity("Username is taken".to_string()))
+        }
+        _ => {}
+    }
+    let user = repository
+        .rename_user(game_id, user_id, params.name.clone())
+        .await?;
+
+    client_messages::send_players(&websocket_srv, repository.get_ref(), game_id).await;
+
+    Ok(Json(UserDetails {
+        id: user.id,
+        user_name: user.user_name,
+        game_id: user.game_id,
+        score: user.score,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web_actors::ws;
+    use awc::Client;
+    use futures::SinkExt;
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::{PicksVisibility, UserDetails},
+        repository::{GameRepository, MemoryRepository, UserRepository},
+    };
+    use errors::ErrorResponse;
+
+    use super::RenamePlayerRequest;
+    use crate::handlers::PlayerDetails;
+    use crate::tests::helpers::tests::{get_auth_token, get_memory_test_server, read_until_path};
+    use crate::websocket::Topic;
+
+    #[actix_rt::test]
+    async fn test_rename_player() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let mut users = Vec::new();
+        for user_name in ["agmcleod", "smurf"] {
+            users.push(
+                repository
+                    .create_user(game.id, user_name.to_string())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let srv = get_memory_test_server(repository);
+        let client = Client::default();
+
+        let owner_token = game.creator.clone().unwrap();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut ws_conn, Topic::Players).await;
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            users[0].id,
+            users[0].user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+
+        // players can't rename each other
+        let res = srv
+            .put(format!("/api/games/{}/players/{}", game.id, users[1].id))
+            .insert_header(("Authorization", player_token.clone()))
+            .send_json(&RenamePlayerRequest {
+                name: "notasmurf".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+
+        let route = format!("/api/games/{}/players/{}", game.id, users[0].id);
+        let mut res = srv
+            .put(&route)
+            .insert_header(("Authorization", player_token.clone()))
+            .send_json(&RenamePlayerRequest {
+                name: "smurf".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 422);
+        let err: ErrorResponse = res.json().await.unwrap();
+        assert_eq!(err.errors[0], "Username is taken");
+
+        let mut res = srv
+            .put(&route)
+            .insert_header(("Authorization", player_token))
+            .send_json(&RenamePlayerRequest {
+                name: "aaron".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        let user: UserDetails = res.json().await.unwrap();
+        assert_eq!(user.user_name, "aaron");
+
+        let msg = read_until_path(&mut ws_conn, Topic::Players).await;
+        let players: Vec<PlayerDetails> = serde_json::from_value(msg.data).unwrap();
+        assert!(players.iter().any(|p| p.user.user_name == "aaron"));
+        assert!(!players.iter().any(|p| p.user.user_name == "agmcleod"));
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+}
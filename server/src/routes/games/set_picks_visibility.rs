@@ -0,0 +1,132 @@
+use actix::Addr;
+use actix_identity::Identity;
+use actix_web::web::{Data, Json, Path};
+use serde::{Deserialize, Serialize};
+
+use auth::{get_claim_from_identity, Role};
+use db::{
+    models::{Game, PicksVisibility},
+    repository::Repository,
+};
+use errors::Error;
+
+use crate::websocket::{client_messages, Server};
+
+#[derive(Deserialize, Serialize)]
+pub struct SetPicksVisibilityRequest {
+    pub picks_visibility: PicksVisibility,
+}
+
+/// Lets the host change who can see everyone's picks. The latest round's picks are sent out
+/// again, so anyone that can now see them gets them right away.
+pub async fn set_picks_visibility(
+    id: Identity,
+    game_id: Path<i32>,
+    repository: Data<dyn Repository>,
+    websocket_srv: Data<Addr<Server>>,
+    params: Json<SetPicksVisibilityRequest>,
+) -> Result<Json<Game>, Error> {
+    let game_id = game_id.into_inner();
+    let (claim, _) = get_claim_from_identity(id)?;
+    if claim.role != Role::Owner || claim.game_id != game_id {
+        return Err(Error::Forbidden);
+    }
+
+    let game = repository
+        .set_picks_visibility(game_id, params.picks_visibility)
+        .await?;
+
+    client_messages::send_game_status(&websocket_srv, repository.get_ref(), game_id).await;
+    client_messages::send_round_picks(&websocket_srv, repository.get_ref(), game_id).await;
+
+    Ok(Json(game))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web_actors::ws;
+    use awc::Client;
+    use futures::SinkExt;
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::{Game, PicksVisibility},
+        repository::{GameRepository, MemoryRepository, RoundRepository, UserRepository},
+    };
+
+    use crate::handlers::GetRoundPicksResponse;
+    use crate::tests::helpers::tests::{get_auth_token, get_memory_test_server, read_until_path};
+    use crate::websocket::Topic;
+
+    use super::SetPicksVisibilityRequest;
+
+    #[actix_rt::test]
+    async fn test_host_can_reveal_picks() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
+            .unwrap();
+
+        repository
+            .create_round(game.id, "one".to_string(), "two".to_string())
+            .await
+            .unwrap();
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let srv = get_memory_test_server(repository);
+        let route = format!("/api/games/{}/picks-visibility", game.id);
+        let body = SetPicksVisibilityRequest {
+            picks_visibility: PicksVisibility::AfterLock,
+        };
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let res = srv
+            .put(&route)
+            .insert_header(("Authorization", player_token.clone()))
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", player_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut ws_conn, Topic::Players).await;
+
+        let owner_token = game.creator.clone().unwrap();
+        let mut res = srv
+            .put(&route)
+            .insert_header(("Authorization", owner_token))
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        let updated: Game = res.json().await.unwrap();
+        assert_eq!(updated.picks_visibility, PicksVisibility::AfterLock);
+
+        let msg = read_until_path(&mut ws_conn, Topic::Picks).await;
+        let picks: GetRoundPicksResponse = serde_json::from_value(msg.data).unwrap();
+        assert!(picks.locked);
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+}
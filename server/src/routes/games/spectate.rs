@@ -0,0 +1,108 @@
+use actix_web::{
+    web::{Data, Json},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use auth::{create_jwt, PrivateClaim, Role};
+use db::repository::Repository;
+use errors::Error;
+
+use crate::validate::validate;
+
+#[derive(Clone, Deserialize, Serialize, Validate)]
+pub struct SpectateRequest {
+    #[validate(length(equal = "6"))]
+    slug: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SpectateResponse {
+    pub game_id: i32,
+    pub slug: String,
+    pub token: String,
+}
+
+pub async fn spectate(
+    repository: Data<dyn Repository>,
+    params: Json<SpectateRequest>,
+) -> Result<Json<SpectateResponse>, Error> {
+    validate(&params)?;
+    let game = repository.find_game_by_slug(&params.slug).await?;
+
+    let slug = game.slug.unwrap_or_default();
+    // spectators don't get a users row, so the claim is tied to the game the same way the owner's is
+    let token = create_jwt(PrivateClaim::new(
+        game.id,
+        slug.clone(),
+        game.id,
+        Role::Spectator,
+    ))?;
+
+    Ok(Json(SpectateResponse {
+        game_id: game.id,
+        slug,
+        token,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use auth::{decode_jwt, Role};
+    use db::{
+        models::PicksVisibility,
+        repository::{GameRepository, MemoryRepository, UserRepository},
+    };
+    use errors::ErrorResponse;
+
+    use super::{SpectateRequest, SpectateResponse};
+    use crate::tests::helpers::tests::test_post_in_memory;
+
+    #[actix_rt::test]
+    async fn test_spectate_game() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        let slug = game.slug.clone().unwrap();
+
+        let res: (u16, SpectateResponse) = test_post_in_memory(
+            repository.clone(),
+            "/api/games/spectate",
+            SpectateRequest { slug: slug.clone() },
+            None,
+        )
+        .await;
+
+        assert_eq!(res.0, 200);
+        assert_eq!(res.1.game_id, game.id);
+        assert_eq!(res.1.slug, slug);
+
+        let claim = decode_jwt(&res.1.token).unwrap();
+        assert_eq!(claim.role, Role::Spectator);
+        assert_eq!(claim.game_id, game.id);
+
+        // no seat is taken on the scoreboard
+        let players = repository.find_users_by_game(game.id).await.unwrap();
+        assert!(players.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_spectate_game_not_found() {
+        let res: (u16, ErrorResponse) = test_post_in_memory(
+            Arc::new(MemoryRepository::new()),
+            "/api/games/spectate",
+            SpectateRequest {
+                slug: "-fake-".to_string(),
+            },
+            None,
+        )
+        .await;
+
+        assert_eq!(res.0, 404);
+    }
+}
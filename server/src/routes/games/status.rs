@@ -2,7 +2,7 @@ use actix_identity::Identity;
 use actix_web::web::{Data, Json, Path};
 
 use auth::identity_matches_game_id;
-use db::{get_conn, PgPool};
+use db::repository::Repository;
 use errors;
 
 use crate::handlers::{get_game_status, StatusResponse};
@@ -10,178 +10,100 @@ use crate::handlers::{get_game_status, StatusResponse};
 pub async fn status(
     id: Identity,
     game_id: Path<i32>,
-    pool: Data<PgPool>,
+    repository: Data<dyn Repository>,
 ) -> Result<Json<StatusResponse>, errors::Error> {
     let game_id = game_id.into_inner();
     identity_matches_game_id(id, game_id)?;
 
-    let connection = get_conn(&pool)?;
-    let response = get_game_status(connection, game_id).await?;
+    let response = get_game_status(repository.get_ref(), game_id).await?;
 
     Ok(Json(response))
 }
 
 #[cfg(test)]
 mod tests {
-    use diesel::{self, RunQueryDsl};
+    use std::sync::Arc;
 
     use auth::{PrivateClaim, Role};
     use db::{
-        get_conn,
-        models::Game,
-        new_pool,
-        schema::{games, rounds},
+        models::PicksVisibility,
+        repository::{GameRepository, MemoryRepository, RoundRepository},
     };
 
     use crate::handlers::StatusResponse;
-    use crate::tests::helpers::tests::{get_auth_token, test_get};
-
-    #[derive(Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        slug: Option<String>,
-    }
-
-    #[derive(Insertable)]
-    #[table_name = "rounds"]
-    struct NewRound {
-        pub player_one: String,
-        pub player_two: String,
-        pub game_id: i32,
-        pub locked: bool,
-        pub finished: bool,
-    }
+    use crate::tests::helpers::tests::{get_auth_token, test_get_in_memory};
 
     #[actix_rt::test]
     async fn test_get_game_status() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::AfterLock)
+            .await
             .unwrap();
-
-        diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-                locked: true,
-                finished: false,
-            })
-            .execute(&conn)
-            .unwrap();
-
-        diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-                locked: true,
-                finished: false,
-            })
-            .execute(&conn)
+        repository
+            .create_round(game.id, "one".to_string(), "two".to_string())
+            .await
             .unwrap();
+        repository.lock_active_round(game.id).await.unwrap();
 
         // isnt locked, but wrong game id
-        let other_game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("dfg888".to_string()),
-            })
-            .get_result(&conn)
+        let other_game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
             .unwrap();
-        diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: other_game.id,
-                locked: false,
-                finished: false,
-            })
-            .execute(&conn)
+        repository
+            .create_round(other_game.id, "one".to_string(), "two".to_string())
+            .await
             .unwrap();
 
         let token = get_auth_token(PrivateClaim::new(
             game.id,
-            game.slug.unwrap(),
+            game.slug.clone().unwrap(),
             game.id,
             Role::Owner,
         ));
-        let res: (u16, StatusResponse) =
-            test_get(&format!("/api/games/{}", game.id), Some(token)).await;
-        assert_eq!(res.0, 200);
-
-        assert_eq!(res.1.slug, "abc123");
-        assert_eq!(res.1.open_round, false);
-        assert_eq!(res.1.unfinished_round, true);
-
-        diesel::delete(rounds::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        let (status, body): (u16, StatusResponse) =
+            test_get_in_memory(repository, &format!("/api/games/{}", game.id), Some(token)).await;
+        assert_eq!(status, 200);
+
+        assert_eq!(body.slug, game.slug.unwrap());
+        assert_eq!(body.open_round, false);
+        assert_eq!(body.unfinished_round, true);
+        assert_eq!(body.picks_visibility, PicksVisibility::AfterLock);
     }
 
     #[actix_rt::test]
     async fn test_get_game_status_open_round() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
-
-        diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-                locked: true,
-                finished: true,
-            })
-            .execute(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
             .unwrap();
-
-        diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-                locked: true,
-                finished: true,
-            })
-            .execute(&conn)
-            .unwrap();
-
-        diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-                locked: false,
-                finished: true,
-            })
-            .execute(&conn)
+        for _ in 0..2 {
+            repository
+                .create_round(game.id, "one".to_string(), "two".to_string())
+                .await
+                .unwrap();
+            repository.lock_active_round(game.id).await.unwrap();
+            repository.score_round(game.id, Vec::new()).await.unwrap();
+        }
+        repository
+            .create_round(game.id, "one".to_string(), "two".to_string())
+            .await
             .unwrap();
 
         let token = get_auth_token(PrivateClaim::new(
             game.id,
-            game.slug.unwrap(),
+            game.slug.clone().unwrap(),
             game.id,
             Role::Owner,
         ));
-        let res: (u16, StatusResponse) =
-            test_get(&format!("/api/games/{}", game.id), Some(token)).await;
-        assert_eq!(res.0, 200);
-
-        assert_eq!(res.1.slug, "abc123");
-        assert_eq!(res.1.open_round, true);
-        assert_eq!(res.1.unfinished_round, false);
+        let (status, body): (u16, StatusResponse) =
+            test_get_in_memory(repository, &format!("/api/games/{}", game.id), Some(token)).await;
+        assert_eq!(status, 200);
 
-        diesel::delete(rounds::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        assert_eq!(body.slug, game.slug.unwrap());
+        assert_eq!(body.open_round, true);
+        assert_eq!(body.unfinished_round, true);
     }
 }
@@ -0,0 +1,88 @@
+use actix_web::{web::Data, HttpResponse};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+
+use db::{get_conn, migrations, DbPool};
+use errors::Error;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HealthResponse {
+    /// The newest migration run against the database
+    pub schema_version: Option<String>,
+    pub pending_migrations: Vec<String>,
+}
+
+/// Responds with 503 while there are migrations left to run
+pub async fn health(pool: Data<DbPool>) -> Result<HttpResponse, Error> {
+    let mut connection = get_conn(&pool).await?;
+    let status = migrations::schema_status(&mut connection).await?;
+
+    let mut response = if status.is_up_to_date() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+
+    Ok(response.json(HealthResponse {
+        schema_version: status.version,
+        pending_migrations: status.pending,
+    }))
+}
+
+/// Liveness probe, responds as long as the server is handling requests
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness probe, responds with 503 when a connection can't be had from the pool, or the
+/// database can't run a query
+pub async fn readyz(pool: Data<DbPool>) -> HttpResponse {
+    let ready = match get_conn(&pool).await {
+        Ok(mut connection) => diesel::sql_query("SELECT 1")
+            .execute(&mut connection)
+            .await
+            .inspect_err(|err| error!("Readiness query failed - {}", err))
+            .is_ok(),
+        // already logged by get_conn
+        Err(_) => false,
+    };
+
+    if ready {
+        HttpResponse::Ok().body("ok")
+    } else {
+        HttpResponse::ServiceUnavailable().body("unavailable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{self, TestRequest};
+
+    use db::migrations::migration_versions;
+
+    use super::HealthResponse;
+    use crate::tests::helpers::tests::{get_service, test_get};
+
+    #[actix_rt::test]
+    async fn test_health_reports_schema_version() {
+        let res: (u16, HealthResponse) = test_get("/health", None).await;
+
+        assert_eq!(res.0, 200);
+        assert_eq!(
+            res.1.schema_version,
+            migration_versions().unwrap().last().cloned()
+        );
+        assert!(res.1.pending_migrations.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_probes_respond_ok() {
+        let srv = get_service().await;
+
+        for uri in ["/healthz", "/readyz"] {
+            let res = test::call_service(&srv, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(res.status(), 200);
+            assert_eq!(test::read_body(res).await, "ok");
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use actix::Addr;
+use actix_web::{http::header, web::Data, HttpRequest, HttpResponse};
+
+use db::DbPool;
+use errors::Error;
+
+use crate::metrics::Metrics;
+use crate::websocket::{GetStats, Server};
+
+/// Metrics in Prometheus' text format. Needs the `METRICS_TOKEN` as a bearer token, if it's set.
+pub async fn metrics(
+    req: HttpRequest,
+    metrics: Data<Metrics>,
+    pool: Data<DbPool>,
+    websocket_srv: Data<Addr<Server>>,
+) -> Result<HttpResponse, Error> {
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !metrics.authorized(authorization) {
+        return Err(Error::Unauthorized);
+    }
+
+    let stats = websocket_srv.send(GetStats).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render(&pool.status(), &stats)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix::Actor;
+    use actix_web::{
+        test::{self, TestRequest},
+        web::Data,
+        App,
+    };
+    use actix_web_actors::ws;
+    use awc::Client;
+    use diesel_async::RunQueryDsl;
+    use futures::SinkExt;
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        get_conn,
+        models::Game,
+        new_pool,
+        repository::{MemoryRepository, Repository},
+        schema::games,
+    };
+
+    use crate::metrics::Metrics;
+    use crate::routes::routes;
+    use crate::tests::helpers::tests::{
+        get_auth_token, get_service, get_test_server, read_until_path,
+    };
+    use crate::websocket::{Server, Topic};
+
+    #[derive(Insertable)]
+    #[diesel(table_name = games)]
+    struct NewGame {
+        slug: String,
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics_counts_requests_by_route() {
+        let srv = get_service().await;
+        test::call_service(&srv, TestRequest::get().uri("/healthz").to_request()).await;
+
+        let res = test::call_service(&srv, TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(res.status(), 200);
+
+        let body = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert!(lines
+            .contains(&"http_requests_total{method=\"GET\",route=\"/healthz\",status=\"200\"} 1"));
+        assert!(lines.contains(&"websocket_sessions 0"));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("db_pool_max_connections ")));
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics_need_the_token_when_one_is_set() {
+        let repository: Arc<dyn Repository> = Arc::new(MemoryRepository::new());
+        let srv = test::init_service(
+            App::new()
+                .app_data(Data::new(Metrics::new(Some("scrape".to_string()))))
+                .app_data(Data::new(new_pool()))
+                .app_data(Data::new(Server::new(repository).start()))
+                .configure(routes),
+        )
+        .await;
+
+        for authorization in [None, Some("Bearer other")] {
+            let mut req = TestRequest::get().uri("/metrics");
+            if let Some(authorization) = authorization {
+                req = req.insert_header(("Authorization", authorization));
+            }
+            let res = test::call_service(&srv, req.to_request()).await;
+            assert_eq!(res.status(), 401);
+        }
+
+        let req = TestRequest::get()
+            .uri("/metrics")
+            .insert_header(("Authorization", "Bearer scrape"))
+            .to_request();
+        let res = test::call_service(&srv, req).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics_counts_game_sessions() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+        let client = Client::default();
+        let (_, mut ws_conn) = client.ws(srv.url("/ws/")).connect().await.unwrap();
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "owner".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", token).into(),
+            ))
+            .await
+            .unwrap();
+        // the players list is sent once the session has joined the game
+        read_until_path(&mut ws_conn, Topic::Players).await;
+
+        let mut res = client.get(srv.url("/metrics")).send().await.unwrap();
+        let body = String::from_utf8(res.body().await.unwrap().to_vec()).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert!(lines.contains(&"websocket_sessions 1"));
+        assert!(lines.contains(&"websocket_games 1"));
+        assert!(lines.contains(&"websocket_game_sessions 1"));
+
+        srv.stop().await;
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+}
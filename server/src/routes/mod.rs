@@ -4,11 +4,17 @@ use crate::middleware::Auth;
 use crate::websocket;
 
 pub mod games;
+pub mod health;
+pub mod metrics;
 pub mod questions;
 pub mod rounds;
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/ws/").route(web::get().to(websocket::ws_index)))
+        .service(web::resource("/health").route(web::get().to(health::health)))
+        .service(web::resource("/healthz").route(web::get().to(health::healthz)))
+        .service(web::resource("/readyz").route(web::get().to(health::readyz)))
+        .service(web::resource("/metrics").route(web::get().to(metrics::metrics)))
         .service(
             web::scope("").service(
                 web::scope("/api")
@@ -17,11 +23,33 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
                         web::scope("/games")
                             .route("", web::post().to(games::create))
                             .service(web::scope("/join").route("", web::post().to(games::join)))
+                            .service(
+                                web::scope("/spectate").route("", web::post().to(games::spectate)),
+                            )
                             .service(
                                 web::scope("/{id}")
                                     .wrap(Auth)
                                     .route("", web::get().to(games::status))
-                                    .route("/players", web::get().to(games::get_players)),
+                                    .route("/players", web::get().to(games::get_players))
+                                    .route(
+                                        "/players/{user_id}",
+                                        web::put().to(games::rename_player),
+                                    )
+                                    .route(
+                                        "/players/{user_id}",
+                                        web::delete().to(games::kick_player),
+                                    )
+                                    .route("/events", web::get().to(games::events))
+                                    .route("/leaderboard", web::get().to(games::get_leaderboard))
+                                    .route(
+                                        "/picks-visibility",
+                                        web::put().to(games::set_picks_visibility),
+                                    )
+                                    .route("/chat", web::get().to(games::get_chat_messages))
+                                    .route(
+                                        "/chat/{message_id}",
+                                        web::delete().to(games::delete_chat_message),
+                                    ),
                             ),
                     )
                     .service(
@@ -31,6 +59,10 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
                             .route("/set-picks", web::post().to(rounds::save_picks))
                             .route("/lock", web::post().to(rounds::lock_round))
                             .route("/picks", web::get().to(rounds::get_round_picks))
+                            .route(
+                                "/distribution",
+                                web::get().to(rounds::get_pick_distribution),
+                            )
                             .route("/score", web::post().to(rounds::score_round)),
                     )
                     .service(
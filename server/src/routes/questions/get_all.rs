@@ -1,36 +1,29 @@
 use actix_web::{
-    web::{block, Data, Json},
+    web::{Data, Json},
     Result,
 };
 
-use db::{get_conn, models::Question, PgPool};
+use db::{models::Question, repository::Repository};
 use errors::Error;
 
-pub async fn get_all(pool: Data<PgPool>) -> Result<Json<Vec<Question>>, Error> {
-    let connection = get_conn(&pool).unwrap();
-
-    let res = block(move || Question::get_all(&connection)).await?;
-    let questions = res?;
+pub async fn get_all(repository: Data<dyn Repository>) -> Result<Json<Vec<Question>>, Error> {
+    let questions = repository.find_all_questions().await?;
 
     Ok(Json(questions))
 }
 
 #[cfg(test)]
 mod tests {
-    use diesel::{self, RunQueryDsl};
-
-    use crate::tests::helpers::tests::test_get;
-    use db::{get_conn, models::Question, new_pool, schema::questions};
+    use std::sync::Arc;
 
-    #[derive(Insertable)]
-    #[table_name = "questions"]
-    struct NewQuestion {
-        body: String,
-    }
+    use crate::tests::helpers::tests::test_get_in_memory;
+    use db::{models::Question, repository::MemoryRepository};
 
     #[actix_rt::test]
     async fn test_questions_empty() {
-        let res: (u16, Vec<Question>) = test_get("/api/questions", None).await;
+        let repository = Arc::new(MemoryRepository::new());
+        let res: (u16, Vec<Question>) =
+            test_get_in_memory(repository, "/api/questions", None).await;
         assert_eq!(res.0, 200);
 
         assert_eq!(res.1.len(), 0);
@@ -38,24 +31,17 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_questions_populated() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        diesel::insert_into(questions::table)
-            .values(NewQuestion {
-                body: "This is the question".to_string(),
-            })
-            .execute(&conn)
-            .unwrap();
-
-        let res = test_get("/api/questions", None).await;
-        assert_eq!(res.0, 200);
-
-        let body: Vec<Question> = res.1;
-
-        assert_eq!(body.len(), 1);
-        assert_eq!(body[0].body, "This is the question");
-
-        diesel::delete(questions::table).execute(&conn).unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        repository.add_question("Who wins?");
+        repository.add_question("Any cannon rushes?");
+
+        let (status, body): (u16, Vec<Question>) =
+            test_get_in_memory(repository, "/api/questions", None).await;
+        assert_eq!(status, 200);
+
+        // ordered by body
+        assert_eq!(body.len(), 2);
+        assert_eq!(body[0].body, "Any cannon rushes?");
+        assert_eq!(body[1].body, "Who wins?");
     }
 }
@@ -1,18 +1,14 @@
 use actix::Addr;
 use actix_identity::Identity;
 use actix_web::{
-    web::{block, Data, Json},
+    web::{Data, Json},
     Result,
 };
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
 use auth::{get_claim_from_identity, Role};
-use db::{
-    get_conn,
-    models::{Game, Round},
-    PgPool,
-};
+use db::{models::Round, repository::Repository};
 use errors::Error;
 
 use crate::validate::validate;
@@ -29,7 +25,7 @@ pub struct CreateRoundRequest {
 pub async fn create(
     id: Identity,
     websocket_srv: Data<Addr<Server>>,
-    pool: Data<PgPool>,
+    repository: Data<dyn Repository>,
     params: Json<CreateRoundRequest>,
 ) -> Result<Json<Round>, Error> {
     validate(&params)?;
@@ -39,110 +35,81 @@ pub async fn create(
         return Err(Error::Forbidden);
     }
 
-    let conn = get_conn(&pool)?;
-
-    let game_id = claim.game_id;
-
-    let res = block(move || {
-        Game::find_by_id(&conn, game_id).map_err(|err| match err {
+    let repository = repository.get_ref();
+    let game = repository
+        .find_game(claim.game_id)
+        .await
+        .map_err(|err| match err {
             // if the game didnt exist, return a forbidden error
             Error::NotFound(_) => Error::Forbidden,
             _ => err,
-        })
-    })
-    .await?;
-
-    let game = res?;
+        })?;
 
     if game.creator.is_none() || game.creator.unwrap() != token {
         return Err(Error::Forbidden);
     }
 
-    let conn = get_conn(&pool)?;
-    let res = block(move || {
-        Round::create(
-            &conn,
-            game_id,
+    let round = repository
+        .create_round(
+            claim.game_id,
             params.player_one.clone(),
             params.player_two.clone(),
         )
-    })
-    .await?;
-
-    let round = res?;
+        .await?;
 
-    let conn = get_conn(&pool)?;
-    client_messages::send_game_status(&websocket_srv, conn, claim.game_id).await;
-
-    let conn = get_conn(&pool)?;
-    client_messages::send_round_status(&websocket_srv, conn, claim.role, claim.id, claim.game_id)
-        .await;
+    client_messages::send_game_status(&websocket_srv, repository, claim.game_id).await;
+    client_messages::send_round_status(&websocket_srv, repository, claim.game_id).await;
 
     Ok(Json(round))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use actix_web_actors::ws;
     use awc::Client;
-    use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
-    use futures::{SinkExt, StreamExt};
+    use futures::SinkExt;
 
     use auth::{create_jwt, PrivateClaim, Role};
     use db::{
-        get_conn,
-        models::{Game, Round},
-        new_pool,
-        schema::{games, rounds},
+        models::{Game, PicksVisibility, Round},
+        repository::{GameRepository, MemoryRepository, RoundRepository, UserRepository},
     };
     use errors::ErrorResponse;
 
     use super::CreateRoundRequest;
     use crate::handlers::StatusResponse;
-    use crate::tests::helpers::tests::{get_test_server, get_websocket_frame_data, test_post};
+    use crate::tests::helpers::tests::{
+        get_memory_test_server, read_until_path, test_post_in_memory,
+    };
+    use crate::websocket::Topic;
 
-    #[derive(Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        slug: Option<String>,
+    async fn create_game(repository: &MemoryRepository) -> Game {
+        repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap()
     }
 
     #[actix_rt::test]
     async fn test_create_round_as_owner() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
-        let claim = PrivateClaim::new(
-            game.id,
-            game.slug.as_ref().unwrap().to_owned(),
-            game.id,
-            Role::Owner,
-        );
-        let token = create_jwt(claim).unwrap();
-
-        diesel::update(games::dsl::games.find(game.id))
-            .set(games::dsl::creator.eq(token.clone()))
-            .execute(&conn)
-            .unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
+        let token = game.creator.clone().unwrap();
 
-        let srv = get_test_server();
+        let srv = get_memory_test_server(repository);
 
         let client = Client::default();
-        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
 
         ws_conn
-            .1
             .send(ws::Message::Text(
                 format!("/auth {{\"token\":\"{}\"}}", token).into(),
             ))
             .await
             .unwrap();
+        read_until_path(&mut ws_conn, Topic::Players).await;
 
         let mut res = srv
             .post("/api/rounds")
@@ -161,57 +128,62 @@ mod tests {
         assert_eq!(round.player_one, "Boxer");
         assert_eq!(round.player_two, "Idra");
 
-        let mut stream = ws_conn.1.take(2);
-        // skip the first one, as it's a heartbeat
-        stream.next().await;
-        let msg = stream.next().await;
-
-        let data = get_websocket_frame_data(msg.unwrap().unwrap());
-        if data.is_some() {
-            let msg = data.unwrap();
-            assert_eq!(msg.path, "/game-status");
-            assert_eq!(msg.game_id, game.id);
-            let game_status: StatusResponse = serde_json::from_value(msg.data).unwrap();
-            // round unlocked & unfinished
-            assert_eq!(game_status.open_round, true);
-            assert_eq!(game_status.unfinished_round, true);
-            assert_eq!(game_status.slug, game.slug.unwrap());
-        } else {
-            assert!(false, "Message was not a string");
-        }
-
-        drop(stream);
+        let msg = read_until_path(&mut ws_conn, Topic::GameStatus).await;
+        assert_eq!(msg.game_id, game.id);
+        let game_status: StatusResponse = serde_json::from_value(msg.data).unwrap();
+        // round unlocked & unfinished
+        assert_eq!(game_status.open_round, true);
+        assert_eq!(game_status.unfinished_round, true);
+        assert_eq!(game_status.slug, game.slug.unwrap());
 
-        srv.stop().await;
+        drop(ws_conn);
 
-        diesel::delete(rounds::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        srv.stop().await;
     }
 
     #[actix_rt::test]
     async fn test_create_round_as_different_owner() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
+        let token = create_jwt(PrivateClaim::new(
+            game.id + 1,
+            "abc222".to_string(),
+            game.id + 1,
+            Role::Owner,
+        ))
+        .unwrap();
 
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Owner);
-        let token = create_jwt(claim).unwrap();
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds",
+            CreateRoundRequest {
+                player_one: "Boxer".to_string(),
+                player_two: "Idra".to_string(),
+            },
+            Some(token),
+        )
+        .await;
 
-        diesel::update(games::dsl::games.find(game.id))
-            .set(games::dsl::creator.eq(token.clone()))
-            .execute(&conn)
-            .unwrap();
+        assert_eq!(status, 403);
+
+        let round_results = repository.find_rounds_by_game(game.id).await.unwrap();
+        assert_eq!(round_results.len(), 0);
+    }
 
-        let wrong_claim =
-            PrivateClaim::new(game.id + 1, "abc222".to_string(), game.id + 1, Role::Owner);
-        let token = create_jwt(wrong_claim).unwrap();
+    #[actix_rt::test]
+    async fn test_create_round_as_invalid_owner_for_same_game() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
+        let token = create_jwt(PrivateClaim::new(
+            game.id,
+            "abc222".to_string(),
+            game.id,
+            Role::Owner,
+        ))
+        .unwrap();
 
-        let (status, _): (u16, ErrorResponse) = test_post(
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
             "/api/rounds",
             CreateRoundRequest {
                 player_one: "Boxer".to_string(),
@@ -223,35 +195,63 @@ mod tests {
 
         assert_eq!(status, 403);
 
-        let round_results: Vec<Round> = rounds::dsl::rounds.load::<Round>(&conn).unwrap();
+        let round_results = repository.find_rounds_by_game(game.id).await.unwrap();
         assert_eq!(round_results.len(), 0);
-
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 
     #[actix_rt::test]
-    async fn test_create_round_as_invalid_owner_for_same_game() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+    async fn test_create_round_while_round_is_open() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
+        let token = game.creator.clone();
+        let params = CreateRoundRequest {
+            player_one: "Boxer".to_string(),
+            player_two: "Idra".to_string(),
+        };
+
+        let (status, round): (u16, Round) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds",
+            params.clone(),
+            token.clone(),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            repository.find_active_round(game.id).await.unwrap().id,
+            round.id
+        );
 
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Owner);
-        let token = create_jwt(claim).unwrap();
+        let (status, err): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds",
+            CreateRoundRequest {
+                player_one: "Flash".to_string(),
+                player_two: "Jaedong".to_string(),
+            },
+            token,
+        )
+        .await;
 
-        diesel::update(games::dsl::games.find(game.id))
-            .set(games::dsl::creator.eq(token.clone()))
-            .execute(&conn)
-            .unwrap();
+        assert_eq!(status, 409);
+        assert_eq!(err.errors[0], "The game already has an open round");
 
-        let wrong_claim = PrivateClaim::new(game.id, "abc222".to_string(), game.id, Role::Owner);
-        let token = create_jwt(wrong_claim).unwrap();
+        let round_results = repository.find_rounds_by_game(game.id).await.unwrap();
+        assert_eq!(round_results.len(), 1);
+    }
 
-        let (status, _): (u16, ErrorResponse) = test_post(
+    #[actix_rt::test]
+    async fn test_create_round_as_player() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
+            .unwrap();
+        let token = user.session_id.unwrap();
+
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
             "/api/rounds",
             CreateRoundRequest {
                 player_one: "Boxer".to_string(),
@@ -263,32 +263,24 @@ mod tests {
 
         assert_eq!(status, 403);
 
-        let round_results: Vec<Round> = rounds::dsl::rounds.load::<Round>(&conn).unwrap();
+        let round_results = repository.find_rounds_by_game(game.id).await.unwrap();
         assert_eq!(round_results.len(), 0);
-
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 
     #[actix_rt::test]
-    async fn test_create_round_as_player() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Player);
-        let token = create_jwt(claim).unwrap();
-
-        diesel::update(games::dsl::games.find(game.id))
-            .set(games::dsl::creator.eq(token.clone()))
-            .execute(&conn)
-            .unwrap();
+    async fn test_create_round_as_spectator() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = create_game(&repository).await;
+        let token = create_jwt(PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id,
+            Role::Spectator,
+        ))
+        .unwrap();
 
-        let (status, _): (u16, ErrorResponse) = test_post(
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
             "/api/rounds",
             CreateRoundRequest {
                 player_one: "Boxer".to_string(),
@@ -300,9 +292,7 @@ mod tests {
 
         assert_eq!(status, 403);
 
-        let round_results: Vec<Round> = rounds::dsl::rounds.load::<Round>(&conn).unwrap();
+        let round_results = repository.find_rounds_by_game(game.id).await.unwrap();
         assert_eq!(round_results.len(), 0);
-
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 }
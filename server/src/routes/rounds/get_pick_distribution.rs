@@ -0,0 +1,279 @@
+use actix_identity::Identity;
+use actix_web::web::{Data, Json};
+
+use auth::get_claim_from_identity;
+use db::repository::Repository;
+use errors::Error;
+
+use crate::handlers::{self, PickDistribution};
+
+/// How the room picked in the latest round. Players and spectators only see it once the game's picks
+/// visibility reveals the round's picks, so it can't be used to copy the crowd.
+pub async fn get_pick_distribution(
+    id: Identity,
+    repository: Data<dyn Repository>,
+) -> Result<Json<PickDistribution>, Error> {
+    let (claim, _) = get_claim_from_identity(id)?;
+
+    let distribution = handlers::get_pick_distribution(repository.get_ref(), claim.game_id).await?;
+    if !distribution.visible_to(&claim.role) {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(Json(distribution))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web_actors::ws;
+    use awc::Client;
+    use futures::SinkExt;
+    use serde_json::json;
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        models::{Game, PicksVisibility, Question, Round, User},
+        repository::{
+            GameRepository, MemoryRepository, Pick, PickRepository, RoundRepository, UserRepository,
+        },
+    };
+    use errors::ErrorResponse;
+
+    use crate::handlers::PickDistribution;
+    use crate::tests::helpers::tests::{
+        get_auth_token, get_memory_test_server, next_server_message, read_until_path,
+        test_get_in_memory,
+    };
+    use crate::websocket::{ServerMessage, Topic};
+
+    async fn create_data(
+        repository: &MemoryRepository,
+        picks_visibility: PicksVisibility,
+    ) -> (Game, Vec<Question>, Round, Vec<User>) {
+        let questions = vec![
+            repository.add_question("Who wins?"),
+            repository.add_question("Goes to game 5?"),
+        ];
+        let question_ids: Vec<i32> = questions.iter().map(|question| question.id).collect();
+        let game = repository
+            .create_game(&question_ids, picks_visibility)
+            .await
+            .unwrap();
+
+        let round = repository
+            .create_round(game.id, "Serral".to_string(), "Maru".to_string())
+            .await
+            .unwrap();
+
+        let mut users = Vec::new();
+        for (i, name) in ["agmcleod", "smurf", "zerg", "terran", "protoss"]
+            .iter()
+            .enumerate()
+        {
+            let user = repository
+                .create_user(game.id, name.to_string())
+                .await
+                .unwrap();
+            repository
+                .create_picks(
+                    game.id,
+                    user.id,
+                    vec![Pick {
+                        question_id: questions[0].id,
+                        answer: if i == 0 { "Maru" } else { "Serral" }.to_string(),
+                    }],
+                )
+                .await
+                .unwrap();
+            users.push(user);
+        }
+
+        (game, questions, round, users)
+    }
+
+    #[actix_rt::test]
+    async fn test_pick_distribution_is_hidden_until_locked() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, round, users) =
+            create_data(&repository, PicksVisibility::AfterLock).await;
+
+        let (status, distribution): (u16, PickDistribution) = test_get_in_memory(
+            repository.clone(),
+            "/api/rounds/distribution",
+            game.creator.clone(),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(distribution.round_id, round.id);
+        let question = distribution
+            .questions
+            .iter()
+            .find(|q| q.question_id == questions[0].id)
+            .unwrap();
+        assert_eq!(question.total, 5);
+        assert_eq!(question.answers[0].answer, "Serral");
+        assert_eq!(question.answers[0].percentage, 80.0);
+        assert_eq!(question.correct_percentage, None);
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            users[0].id,
+            users[0].user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let (status, _): (u16, ErrorResponse) = test_get_in_memory(
+            repository.clone(),
+            "/api/rounds/distribution",
+            Some(player_token.clone()),
+        )
+        .await;
+        assert_eq!(status, 403);
+
+        repository.lock_active_round(game.id).await.unwrap();
+        let (status, _): (u16, PickDistribution) =
+            test_get_in_memory(repository, "/api/rounds/distribution", Some(player_token)).await;
+        assert_eq!(status, 200);
+    }
+
+    #[actix_rt::test]
+    async fn test_pick_distribution_is_hidden_from_players_in_host_only_games() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, _, _, users) = create_data(&repository, PicksVisibility::HostOnly).await;
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let (status, _): (u16, ErrorResponse) = test_get_in_memory(
+            repository.clone(),
+            "/api/rounds/distribution",
+            users[0].session_id.clone(),
+        )
+        .await;
+        assert_eq!(status, 403);
+
+        let (status, _): (u16, PickDistribution) =
+            test_get_in_memory(repository, "/api/rounds/distribution", game.creator.clone()).await;
+        assert_eq!(status, 200);
+    }
+
+    #[actix_rt::test]
+    async fn test_pick_distribution_is_sent_when_scored() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, _) = create_data(&repository, PicksVisibility::AfterLock).await;
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let srv = get_memory_test_server(repository);
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id,
+            Role::Spectator,
+        ));
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut ws_conn, Topic::Players).await;
+
+        let res = srv
+            .post("/api/rounds/score")
+            .append_header(("Authorization", game.creator.clone().unwrap()))
+            .send_json(&json!({
+                "answers": [
+                    {"question_id": questions[0].id, "answer": "Maru"},
+                    {"question_id": questions[1].id, "answer": "Yes"},
+                ]
+            }))
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        let msg = read_until_path(&mut ws_conn, Topic::PickDistribution).await;
+        let distribution: PickDistribution = serde_json::from_value(msg.data).unwrap();
+        assert!(distribution.finished);
+        let question = distribution
+            .questions
+            .iter()
+            .find(|q| q.question_id == questions[0].id)
+            .unwrap();
+        assert_eq!(question.correct_answer, Some("Maru".to_string()));
+        assert_eq!(question.correct_percentage, Some(20.0));
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_pick_distribution_is_only_sent_to_the_host_in_host_only_games() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, _) = create_data(&repository, PicksVisibility::HostOnly).await;
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let srv = get_memory_test_server(repository);
+        let client = Client::default();
+        let owner_token = game.creator.clone().unwrap();
+        let spectator_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id,
+            Role::Spectator,
+        ));
+
+        let mut connections = Vec::new();
+        for token in [&owner_token, &spectator_token] {
+            let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+            ws_conn
+                .send(ws::Message::Text(
+                    format!("/auth {{\"token\":\"{}\"}}", token).into(),
+                ))
+                .await
+                .unwrap();
+            read_until_path(&mut ws_conn, Topic::Players).await;
+            connections.push(ws_conn);
+        }
+
+        let res = srv
+            .post("/api/rounds/score")
+            .append_header(("Authorization", owner_token.clone()))
+            .send_json(&json!({
+                "answers": [
+                    {"question_id": questions[0].id, "answer": "Maru"},
+                    {"question_id": questions[1].id, "answer": "Yes"},
+                ]
+            }))
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        let msg = read_until_path(&mut connections[0], Topic::PickDistribution).await;
+        let distribution: PickDistribution = serde_json::from_value(msg.data).unwrap();
+        assert!(distribution.finished);
+
+        // the next round's status is sent after everything from scoring
+        let res = srv
+            .post("/api/rounds")
+            .append_header(("Authorization", owner_token))
+            .send_json(&json!({"player_one": "Serral", "player_two": "Maru"}))
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        let spectator_ws = &mut connections[1];
+        read_until_path(spectator_ws, Topic::Leaderboard).await;
+        loop {
+            if let ServerMessage::Event(msg) = next_server_message(spectator_ws).await {
+                assert_ne!(msg.path, Topic::PickDistribution);
+                if msg.path == Topic::GameStatus {
+                    break;
+                }
+            }
+        }
+
+        drop(connections);
+        srv.stop().await;
+    }
+}
@@ -1,189 +1,206 @@
-use actix_identity::Identity;
-use actix_web::web::{Data, Json};
-
-use auth::{get_claim_from_identity, Role};
-use db::{get_conn, PgPool};
-use errors::Error;
-
-use crate::handlers;
-
-pub async fn get_round_picks(
-    id: Identity,
-    pool: Data<PgPool>,
-) -> Result<Json<handlers::GetRoundPicksResponse>, Error> {
-    let (claim, _) = get_claim_from_identity(id)?;
-    if claim.role != Role::Owner {
-        return Err(Error::Forbidden);
-    }
-
-    let round_picks_response = handlers::get_round_picks(get_conn(&pool)?, claim.game_id).await?;
-
-    Ok(Json(round_picks_response))
-}
-
-#[cfg(test)]
-mod tests {
-    use diesel::{self, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
-
-    use auth::{create_jwt, PrivateClaim, Role};
-    use db::{
-        get_conn,
-        models::{
-            Game, NewGameQuestion, NewRound, NewUser, NewUserQuestion, Question, Round, User,
-            UserQuestion,
-        },
-        new_pool,
-        schema::{
-            game_questions, games, questions as questions_dsl, rounds, user_questions, users,
-        },
-    };
-    use errors::ErrorResponse;
-
-    use crate::handlers::GetRoundPicksResponse;
-    use crate::tests::helpers::tests::test_get;
-
-    #[derive(Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        slug: Option<String>,
-    }
-
-    fn create_test_data(conn: &PgConnection) -> (Game, User, Round, Vec<UserQuestion>) {
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(conn)
-            .unwrap();
-
-        let questions: Vec<Question> = diesel::insert_into(questions_dsl::table)
-            .values(&vec![
-                questions_dsl::body.eq("One question".to_string()),
-                questions_dsl::body.eq("Second question".to_string()),
-            ])
-            .get_results(conn)
-            .unwrap();
-
-        diesel::insert_into(game_questions::table)
-            .values(
-                questions
-                    .iter()
-                    .map(|q| NewGameQuestion {
-                        game_id: game.id,
-                        question_id: q.id,
-                    })
-                    .collect::<Vec<NewGameQuestion>>(),
-            )
-            .execute(conn)
-            .unwrap();
-
-        let user: User = diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod".to_string(),
-                game_id: game.id,
-            })
-            .get_result(conn)
-            .unwrap();
-
-        let round: Round = diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-            })
-            .get_result(conn)
-            .unwrap();
-
-        let new_user_questions: Vec<UserQuestion> = diesel::insert_into(user_questions::table)
-            .values(vec![
-                NewUserQuestion {
-                    user_id: user.id,
-                    question_id: questions[0].id,
-                    round_id: round.id,
-                    answer: "one".to_string(),
-                },
-                NewUserQuestion {
-                    user_id: user.id,
-                    question_id: questions[1].id,
-                    round_id: round.id,
-                    answer: "two".to_string(),
-                },
-            ])
-            .get_results(conn)
-            .unwrap();
-
-        (game, user, round, new_user_questions)
-    }
-
-    fn clear_game_data(conn: &PgConnection) {
-        diesel::delete(user_questions::table).execute(conn).unwrap();
-        diesel::delete(rounds::table).execute(conn).unwrap();
-        diesel::delete(users::table).execute(conn).unwrap();
-        diesel::delete(game_questions::table).execute(conn).unwrap();
-        diesel::delete(games::table).execute(conn).unwrap();
-        diesel::delete(questions_dsl::table).execute(conn).unwrap();
-    }
-
-    #[actix_rt::test]
-    async fn test_get_round_picks() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-        let (game, _, _, _) = create_test_data(&conn);
-
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Owner);
-
-        let (status, body): (u16, GetRoundPicksResponse) =
-            test_get("/api/rounds/picks", Some(create_jwt(claim).unwrap())).await;
-
-        assert_eq!(status, 200);
-        assert_eq!(body.data.len(), 2);
-        let first_pick = &body.data[0];
-        assert_eq!(first_pick.user_name, "agmcleod");
-        assert_eq!(first_pick.answer, "one");
-
-        let second_pick = &body.data[1];
-        assert_eq!(second_pick.user_name, "agmcleod");
-        assert_eq!(second_pick.answer, "two");
-
-        assert_eq!(body.locked, false);
-
-        clear_game_data(&conn);
-    }
-
-    #[actix_rt::test]
-    async fn test_get_round_picks_role_not_owner() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-        let (game, _, _, _) = create_test_data(&conn);
-
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Player);
-
-        let (status, _): (u16, ErrorResponse) =
-            test_get("/api/rounds/picks", Some(create_jwt(claim).unwrap())).await;
-
-        assert_eq!(status, 403);
-
-        clear_game_data(&conn);
-    }
-
-    #[actix_rt::test]
-    async fn test_get_round_picks_no_active_round() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-        let (game, _, round, _) = create_test_data(&conn);
-
-        diesel::update(rounds::dsl::rounds.find(round.id))
-            .set(rounds::dsl::locked.eq(true))
-            .execute(&conn)
-            .unwrap();
-
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Owner);
-
-        let (status, _): (u16, ErrorResponse) =
-            test_get("/api/rounds/picks", Some(create_jwt(claim).unwrap())).await;
-
-        assert_eq!(status, 404);
-
-        clear_game_data(&conn);
-    }
-}
+use actix_identity::Identity;
+use actix_web::web::{Data, Json};
+
+use auth::get_claim_from_identity;
+use db::repository::Repository;
+use errors::Error;
+
+use crate::handlers;
+
+/// Everyone's picks for the latest round. Players and spectators only get them once the game's
+/// picks visibility allows it.
+pub async fn get_round_picks(
+    id: Identity,
+    repository: Data<dyn Repository>,
+) -> Result<Json<handlers::GetRoundPicksResponse>, Error> {
+    let (claim, _) = get_claim_from_identity(id)?;
+
+    let round_picks = handlers::get_round_picks(repository.get_ref(), claim.game_id).await?;
+    if !round_picks.visible_to(&claim.role) {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(Json(round_picks.response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use auth::{create_jwt, PrivateClaim, Role};
+    use db::{
+        models::{Game, PicksVisibility, User},
+        repository::{
+            GameRepository, MemoryRepository, Pick, PickRepository, RoundRepository, UserRepository,
+        },
+    };
+    use errors::ErrorResponse;
+
+    use crate::handlers::GetRoundPicksResponse;
+    use crate::tests::helpers::tests::{get_memory_test_server, test_get_in_memory};
+
+    /// A game with an open round, and a player that picked "one" and "two" for its questions
+    async fn create_test_data(repository: &MemoryRepository) -> (Game, User) {
+        let questions = [
+            repository.add_question("One question"),
+            repository.add_question("Second question"),
+        ];
+        let game = repository
+            .create_game(
+                &[questions[0].id, questions[1].id],
+                PicksVisibility::HostOnly,
+            )
+            .await
+            .unwrap();
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
+            .unwrap();
+        repository
+            .create_round(game.id, "one".to_string(), "two".to_string())
+            .await
+            .unwrap();
+        repository
+            .create_picks(
+                game.id,
+                user.id,
+                vec![
+                    Pick {
+                        question_id: questions[0].id,
+                        answer: "one".to_string(),
+                    },
+                    Pick {
+                        question_id: questions[1].id,
+                        answer: "two".to_string(),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        (game, user)
+    }
+
+    #[actix_rt::test]
+    async fn test_get_round_picks() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, _) = create_test_data(&repository).await;
+
+        let (status, body): (u16, GetRoundPicksResponse) =
+            test_get_in_memory(repository, "/api/rounds/picks", game.creator.clone()).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body.data.len(), 2);
+        let first_pick = &body.data[0];
+        assert_eq!(first_pick.user_name, "agmcleod");
+        assert_eq!(first_pick.answer, "one");
+
+        let second_pick = &body.data[1];
+        assert_eq!(second_pick.user_name, "agmcleod");
+        assert_eq!(second_pick.answer, "two");
+
+        assert_eq!(body.locked, false);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_round_picks_role_not_owner() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (_, user) = create_test_data(&repository).await;
+
+        let (status, _): (u16, ErrorResponse) =
+            test_get_in_memory(repository, "/api/rounds/picks", user.session_id).await;
+
+        assert_eq!(status, 403);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_round_picks_locked_round() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, _) = create_test_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let (status, body): (u16, GetRoundPicksResponse) =
+            test_get_in_memory(repository, "/api/rounds/picks", game.creator.clone()).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body.data.len(), 2);
+        assert!(body.locked);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_round_picks_no_round() {
+        let repository = Arc::new(MemoryRepository::new());
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let (status, _): (u16, ErrorResponse) =
+            test_get_in_memory(repository, "/api/rounds/picks", game.creator.clone()).await;
+
+        assert_eq!(status, 404);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_round_picks_follows_visibility() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, user) = create_test_data(&repository).await;
+
+        let player_token = create_jwt(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ))
+        .unwrap();
+        let spectator_token = create_jwt(PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id,
+            Role::Spectator,
+        ))
+        .unwrap();
+
+        let srv = get_memory_test_server(repository.clone());
+        // the round only moves forward, so each case locks or scores it as needed
+        for (visibility, locked, finished, visible) in &[
+            (PicksVisibility::AfterLock, false, false, false),
+            (PicksVisibility::AfterLock, true, false, true),
+            (PicksVisibility::AfterScoring, true, false, false),
+            (PicksVisibility::AfterScoring, true, true, true),
+            (PicksVisibility::HostOnly, true, true, false),
+        ] {
+            repository
+                .set_picks_visibility(game.id, *visibility)
+                .await
+                .unwrap();
+            let round = repository.find_latest_round(game.id).await.unwrap();
+            if *locked && !round.locked {
+                repository.lock_active_round(game.id).await.unwrap();
+            }
+            if *finished && !round.finished {
+                repository.score_round(game.id, Vec::new()).await.unwrap();
+            }
+
+            for token in &[&player_token, &spectator_token] {
+                let res = srv
+                    .get("/api/rounds/picks")
+                    .insert_header(("Authorization", token.to_string()))
+                    .send()
+                    .await
+                    .unwrap();
+                let expected = if *visible { 200 } else { 403 };
+                assert_eq!(
+                    res.status().as_u16(),
+                    expected,
+                    "{:?} locked: {} finished: {}",
+                    visibility,
+                    locked,
+                    finished
+                );
+            }
+        }
+
+        srv.stop().await;
+    }
+}
@@ -1,12 +1,9 @@
 use actix::Addr;
 use actix_identity::Identity;
-use actix_web::{
-    web::{block, Data},
-    HttpResponse, Result,
-};
+use actix_web::{web::Data, HttpResponse, Result};
 
-use auth::{get_claim_from_identity, PrivateClaim, Role};
-use db::{get_conn, models::Round, Connection, PgPool};
+use auth::{get_claim_from_identity, Role};
+use db::repository::Repository;
 use errors::Error;
 
 use crate::websocket::{client_messages, Server};
@@ -14,113 +11,84 @@ use crate::websocket::{client_messages, Server};
 pub async fn lock_round(
     id: Identity,
     websocket_srv: Data<Addr<Server>>,
-    pool: Data<PgPool>,
+    repository: Data<dyn Repository>,
 ) -> Result<HttpResponse, Error> {
     let (claim, _) = get_claim_from_identity(id)?;
     if claim.role != Role::Owner {
         return Err(Error::Forbidden);
     }
 
-    let conn = get_conn(&pool)?;
-    let res: Result<(Connection, PrivateClaim), Error> = block(move || {
-        let round = Round::get_active_round_by_game_id(&conn, claim.game_id)?;
-        Round::lock(&conn, round.id)?;
-        Ok((conn, claim))
-    })
-    .await?;
+    let repository = repository.get_ref();
+    repository.lock_active_round(claim.game_id).await?;
 
-    let (conn, claim) = res?;
-
-    client_messages::send_game_status(&websocket_srv, conn, claim.game_id).await;
-    let conn = get_conn(&pool)?;
-    client_messages::send_round_status(&websocket_srv, conn, claim.role, claim.id, claim.game_id)
-        .await;
+    client_messages::send_game_status(&websocket_srv, repository, claim.game_id).await;
+    client_messages::send_round_status(&websocket_srv, repository, claim.game_id).await;
+    client_messages::send_pick_distribution(&websocket_srv, repository, claim.game_id).await;
+    client_messages::send_round_picks(&websocket_srv, repository, claim.game_id).await;
 
     Ok(HttpResponse::Ok().json(()))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use actix_web_actors::ws;
     use awc::Client;
-    use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
-    use futures::{SinkExt, StreamExt};
+    use futures::SinkExt;
     use serde_json;
 
-    use auth::{create_jwt, PrivateClaim, Role};
     use db::{
-        get_conn,
-        models::{Game, Round},
-        new_pool,
-        schema::{games, rounds},
+        models::{Game, PicksVisibility, Round},
+        repository::{GameRepository, MemoryRepository, RoundRepository, UserRepository},
     };
     use errors::ErrorResponse;
 
     use crate::handlers::{RoundStatusRepsonse, StatusResponse};
-    use crate::tests::helpers::tests::{get_test_server, get_websocket_frame_data, test_post};
+    use crate::tests::helpers::tests::{
+        get_memory_test_server, read_until_path, test_post_in_memory,
+    };
+    use crate::websocket::Topic;
 
-    #[derive(Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        slug: Option<String>,
-    }
+    /// A game with a scored round, and a round after it that's open for picks
+    async fn create_data(repository: &MemoryRepository) -> (Game, Round) {
+        let game = repository
+            .create_game(&[], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+        repository
+            .create_round(game.id, "serral".to_string(), "ty".to_string())
+            .await
+            .unwrap();
+        repository.lock_active_round(game.id).await.unwrap();
+        repository.score_round(game.id, Vec::new()).await.unwrap();
+        let round = repository
+            .create_round(game.id, "maru".to_string(), "zest".to_string())
+            .await
+            .unwrap();
 
-    #[derive(Insertable)]
-    #[table_name = "rounds"]
-    pub struct NewRound {
-        player_one: String,
-        player_two: String,
-        game_id: i32,
-        locked: bool,
+        (game, round)
     }
 
     #[actix_rt::test]
     async fn test_lock_current_round() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, round) = create_data(&repository).await;
+        let token = game.creator.clone().unwrap();
 
-        diesel::insert_into(rounds::table)
-            .values(vec![
-                NewRound {
-                    player_one: "maru".to_string(),
-                    player_two: "zest".to_string(),
-                    game_id: game.id,
-                    locked: false,
-                },
-                NewRound {
-                    player_one: "serral".to_string(),
-                    player_two: "ty".to_string(),
-                    game_id: game.id,
-                    locked: true,
-                },
-            ])
-            .execute(&conn)
-            .unwrap();
-
-        let slug = game.slug.as_ref().unwrap().clone();
-        let claim = PrivateClaim::new(game.id, slug, game.id, Role::Owner);
-        let token = create_jwt(claim).unwrap();
-
-        let srv = get_test_server();
+        let srv = get_memory_test_server(repository.clone());
 
         let client = Client::default();
-        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
 
         // auth this user with the websocket server
         ws_conn
-            .1
             .send(ws::Message::Text(
                 format!("/auth {{\"token\":\"{}\"}}", token).into(),
             ))
             .await
             .unwrap();
+        read_until_path(&mut ws_conn, Topic::Players).await;
 
         let res = srv
             .post("/api/rounds/lock")
@@ -131,133 +99,56 @@ mod tests {
 
         assert_eq!(res.status().as_u16(), 200);
 
-        let mut stream = ws_conn.1.take(3);
-        // skip the first one, as it's a heartbeat
-        stream.next().await;
-        let msg = stream.next().await;
-
-        let data = get_websocket_frame_data(msg.unwrap().unwrap());
-        if data.is_some() {
-            let msg = data.unwrap();
-            assert_eq!(msg.path, "/game-status");
-            assert_eq!(msg.game_id, game.id);
-            let game_status: StatusResponse = serde_json::from_value(msg.data).unwrap();
-            // both rounds are locked
-            assert_eq!(game_status.open_round, false);
-            assert_eq!(game_status.unfinished_round, true);
-            assert_eq!(game_status.slug, game.slug.unwrap());
-        } else {
-            assert!(false, "Message was not a string");
-        }
+        let msg = read_until_path(&mut ws_conn, Topic::GameStatus).await;
+        assert_eq!(msg.game_id, game.id);
+        let game_status: StatusResponse = serde_json::from_value(msg.data).unwrap();
+        // both rounds are locked
+        assert_eq!(game_status.open_round, false);
+        assert_eq!(game_status.unfinished_round, true);
+        assert_eq!(game_status.slug, game.slug.unwrap());
 
-        let msg = stream.next().await;
+        let msg = read_until_path(&mut ws_conn, Topic::RoundStatus).await;
+        assert_eq!(msg.game_id, game.id);
+        let round_status: RoundStatusRepsonse = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(round_status.locked, true);
 
-        let data = get_websocket_frame_data(msg.unwrap().unwrap());
-        if data.is_some() {
-            let msg = data.unwrap();
-            assert_eq!(msg.path, "/round-status");
-            assert_eq!(msg.game_id, game.id);
-            let round_status: RoundStatusRepsonse = serde_json::from_value(msg.data).unwrap();
-            assert_eq!(round_status.locked, true);
-        } else {
-            assert!(false, "Message was not a string");
-        }
-
-        drop(stream);
+        drop(ws_conn);
 
         srv.stop().await;
 
-        let results: Vec<Round> = rounds::dsl::rounds
-            .filter(rounds::dsl::game_id.eq(game.id))
-            .get_results(&conn)
-            .unwrap();
+        let results = repository.find_rounds_by_game(game.id).await.unwrap();
         assert_eq!(results.len(), 2);
         assert_eq!(results[0].locked, true);
         assert_eq!(results[1].locked, true);
-
-        diesel::delete(rounds::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        assert_eq!(results[1].id, round.id);
     }
 
     #[actix_rt::test]
     async fn test_lock_current_round_forbidden_for_player() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
-
-        diesel::insert_into(rounds::table)
-            .values(vec![
-                NewRound {
-                    player_one: "maru".to_string(),
-                    player_two: "zest".to_string(),
-                    game_id: game.id,
-                    locked: false,
-                },
-                NewRound {
-                    player_one: "serral".to_string(),
-                    player_two: "ty".to_string(),
-                    game_id: game.id,
-                    locked: true,
-                },
-            ])
-            .execute(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, _) = create_data(&repository).await;
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
             .unwrap();
+        let token = user.session_id.unwrap();
 
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap(), game.id, Role::Player);
-        let token = create_jwt(claim).unwrap();
-
-        let res: (u16, ErrorResponse) = test_post("/api/rounds/lock", (), Some(token)).await;
+        let res: (u16, ErrorResponse) =
+            test_post_in_memory(repository.clone(), "/api/rounds/lock", (), Some(token)).await;
 
         assert_eq!(res.0, 403);
-
-        diesel::delete(rounds::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        assert!(repository.find_active_round(game.id).await.is_ok());
     }
 
     #[actix_rt::test]
     async fn test_lock_current_round_no_active_round() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(&conn)
-            .unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, _) = create_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
 
-        diesel::insert_into(rounds::table)
-            .values(vec![
-                NewRound {
-                    player_one: "maru".to_string(),
-                    player_two: "zest".to_string(),
-                    game_id: game.id,
-                    locked: true,
-                },
-                NewRound {
-                    player_one: "serral".to_string(),
-                    player_two: "ty".to_string(),
-                    game_id: game.id,
-                    locked: true,
-                },
-            ])
-            .execute(&conn)
-            .unwrap();
-
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap(), game.id, Role::Owner);
-        let token = create_jwt(claim).unwrap();
-
-        let res: (u16, ErrorResponse) = test_post("/api/rounds/lock", (), Some(token)).await;
+        let res: (u16, ErrorResponse) =
+            test_post_in_memory(repository, "/api/rounds/lock", (), game.creator).await;
 
         assert_eq!(res.0, 404);
-
-        diesel::delete(rounds::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
     }
 }
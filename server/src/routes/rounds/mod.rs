@@ -1,4 +1,5 @@
 mod create;
+mod get_pick_distribution;
 mod get_round_picks;
 mod lock_round;
 mod save_picks;
@@ -6,6 +7,7 @@ mod score_round;
 mod status;
 
 pub use self::create::*;
+pub use self::get_pick_distribution::*;
 pub use self::get_round_picks::*;
 pub use self::lock_round::*;
 pub use self::save_picks::*;
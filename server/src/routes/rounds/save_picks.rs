@@ -1,88 +1,27 @@
-use std::collections::HashSet;
-
 use actix::Addr;
 use actix_identity::Identity;
 use actix_web::{
-    web::{block, Data, Json},
+    web::{Data, Json},
     HttpResponse, Result,
 };
-use diesel::PgConnection;
 use serde::{Deserialize, Serialize};
-use serde_json::to_value;
 
-use auth::{get_claim_from_identity, PrivateClaim, Role};
-use db::{
-    get_conn,
-    models::{GameQuestion, Round, UserQuestion},
-    PgPool,
-};
+use auth::{get_claim_from_identity, Role};
+use db::repository::Repository;
 use errors::Error;
 
-use crate::handlers::get_round_picks;
-use crate::websocket::{MessageToClient, Server};
-
-#[derive(Deserialize, Serialize)]
-pub struct Answer {
-    id: i32,
-    value: String,
-}
+use crate::handlers::{self, Answer};
+use crate::websocket::{client_messages, Server};
 
 #[derive(Deserialize, Serialize)]
 pub struct SavePicksParams {
     answers: Vec<Answer>,
 }
 
-fn validate_user_has_not_picked(
-    conn: &PgConnection,
-    claim: &PrivateClaim,
-    round_id: i32,
-) -> Result<(), Error> {
-    let results = UserQuestion::find_by_round_and_user(conn, round_id, claim.id)?;
-    if results.len() > 0 {
-        return Err(Error::BadRequest(
-            "User has already chosen picks for this round".to_string(),
-        ));
-    }
-
-    Ok(())
-}
-
-fn validate_selected_questions(
-    conn: &PgConnection,
-    claim: &PrivateClaim,
-    params: &Json<SavePicksParams>,
-) -> Result<(), Error> {
-    let questions = GameQuestion::get_questions_by_game_id(&conn, claim.game_id)?;
-
-    if questions.len() != params.answers.len() {
-        return Err(Error::BadRequest(format!(
-            "Received {} answers, expected {}",
-            params.answers.len(),
-            questions.len()
-        )));
-    }
-
-    let mut question_ids = HashSet::<i32>::new();
-    for question in &questions {
-        question_ids.insert(question.id);
-    }
-    // check if any answers map to questions not in this game
-    for answer in &params.answers {
-        if !question_ids.contains(&answer.id) {
-            return Err(Error::BadRequest(format!(
-                "Invalid question id: {}",
-                answer.id
-            )));
-        }
-    }
-
-    Ok(())
-}
-
 pub async fn save_picks(
     id: Identity,
     websocket_srv: Data<Addr<Server>>,
-    pool: Data<PgPool>,
+    repository: Data<dyn Repository>,
     params: Json<SavePicksParams>,
 ) -> Result<HttpResponse, Error> {
     let (claim, _) = get_claim_from_identity(id)?;
@@ -91,309 +30,211 @@ pub async fn save_picks(
         return Err(Error::Forbidden);
     }
 
-    let conn = get_conn(&pool)?;
-
-    let res: Result<PrivateClaim, Error> = block(move || {
-        let round = Round::get_active_round_by_game_id(&conn, claim.game_id)?;
-        validate_user_has_not_picked(&conn, &claim, round.id)?;
-        validate_selected_questions(&conn, &claim, &params)?;
-
-        for answer in &params.answers {
-            UserQuestion::create(&conn, claim.id, answer.id, round.id, answer.value.clone())?;
-        }
-
-        Ok(claim)
-    })
-    .await?;
-
-    let claim = res?;
+    let game_id = claim.game_id;
+    handlers::save_picks(repository.get_ref(), claim, params.into_inner().answers).await?;
 
-    let conn = get_conn(&pool)?;
-    let round_picks = get_round_picks(conn, claim.game_id).await;
-    match round_picks {
-        Ok(round_picks) => {
-            if let Ok(value) = to_value(round_picks) {
-                let msg = MessageToClient::new("/picks", claim.game_id, value);
-                websocket_srv.do_send(msg);
-            }
-        }
-        Err(err) => error!("{:?}", err),
-    }
+    client_messages::send_round_picks(&websocket_srv, repository.get_ref(), game_id).await;
 
     Ok(HttpResponse::Ok().json(()))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use actix_web_actors::ws;
     use awc::Client;
-    use diesel::{self, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
-    use futures::{SinkExt, StreamExt};
-    use serde::Serialize;
+    use futures::SinkExt;
 
     use auth::{create_jwt, PrivateClaim, Role};
     use db::{
-        get_conn,
-        models::{
-            Game, NewGameQuestion, NewRound, NewUser, NewUserQuestion, Question, Round, User,
-            UserAnswer, UserQuestion,
-        },
-        new_pool,
-        schema::{
-            game_questions, games, questions as questions_dsl, rounds, user_questions, users,
+        models::{Game, PicksVisibility, Question, Round, User},
+        repository::{
+            GameRepository, MemoryRepository, Pick, PickRepository, RoundRepository, UserRepository,
         },
     };
-    use errors::ErrorResponse;
+    use errors::{Error, ErrorResponse};
 
-    use super::{Answer, SavePicksParams};
+    use super::SavePicksParams;
+    use crate::handlers::Answer;
     use crate::handlers::GetRoundPicksResponse;
-    use crate::tests::helpers::tests::{get_test_server, get_websocket_frame_data, test_post};
-
-    #[derive(Serialize, Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        pub slug: Option<String>,
-    }
-
-    fn create_game_data(conn: &PgConnection) -> (Vec<Question>, Game, User, Round) {
-        let questions: Vec<Question> = diesel::insert_into(questions_dsl::table)
-            .values(&vec![
-                questions_dsl::body.eq("One question".to_string()),
-                questions_dsl::body.eq("Second question".to_string()),
-            ])
-            .get_results(conn)
-            .unwrap();
-
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame { slug: None })
-            .get_result(conn)
-            .unwrap();
-
-        diesel::insert_into(game_questions::table)
-            .values(
-                questions
-                    .iter()
-                    .map(|q| NewGameQuestion {
-                        game_id: game.id,
-                        question_id: q.id,
-                    })
-                    .collect::<Vec<NewGameQuestion>>(),
-            )
-            .execute(conn)
+    use crate::tests::helpers::tests::{
+        get_memory_test_server, read_until_path, test_post_in_memory,
+    };
+    use crate::websocket::Topic;
+
+    async fn create_game_data(repository: &MemoryRepository) -> (Vec<Question>, Game, User, Round) {
+        let questions = vec![
+            repository.add_question("One question"),
+            repository.add_question("Second question"),
+        ];
+        let question_ids: Vec<i32> = questions.iter().map(|question| question.id).collect();
+        let game = repository
+            .create_game(&question_ids, PicksVisibility::HostOnly)
+            .await
             .unwrap();
-
-        let user: User = diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod".to_string(),
-                game_id: game.id,
-            })
-            .get_result(conn)
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
             .unwrap();
-
-        let round: Round = diesel::insert_into(rounds::table)
-            .values(NewRound {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-            })
-            .get_result(conn)
+        let round = repository
+            .create_round(game.id, "one".to_string(), "two".to_string())
+            .await
             .unwrap();
 
         (questions, game, user, round)
     }
 
-    fn clear_game_data(conn: &PgConnection) {
-        diesel::delete(user_questions::table).execute(conn).unwrap();
-        diesel::delete(rounds::table).execute(conn).unwrap();
-        diesel::delete(users::table).execute(conn).unwrap();
-        diesel::delete(game_questions::table).execute(conn).unwrap();
-        diesel::delete(games::table).execute(conn).unwrap();
-        diesel::delete(questions_dsl::table).execute(conn).unwrap();
+    fn params(first_id: i32, second_id: i32) -> SavePicksParams {
+        SavePicksParams {
+            answers: vec![
+                Answer {
+                    id: first_id,
+                    value: "one".to_string(),
+                },
+                Answer {
+                    id: second_id,
+                    value: "two".to_string(),
+                },
+            ],
+        }
     }
 
     #[actix_rt::test]
     async fn test_can_save_picks() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let (questions, game, user, _) = create_game_data(&conn);
-
-        let claim = PrivateClaim::new(user.id, user.user_name.clone(), game.id, Role::Player);
-        let token = create_jwt(claim).unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, game, user, round) = create_game_data(&repository).await;
 
-        let srv = get_test_server();
+        let srv = get_memory_test_server(repository.clone());
 
         let client = Client::default();
         let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap();
 
+        // only the host sees everyone's picks while the round is open
         ws_conn
             .1
             .send(ws::Message::Text(
-                format!("/auth {{\"token\":\"{}\"}}", token).into(),
+                format!("/auth {{\"token\":\"{}\"}}", game.creator.unwrap()).into(),
             ))
             .await
             .unwrap();
+        read_until_path(&mut ws_conn.1, Topic::Players).await;
 
         let res = srv
             .post("/api/rounds/set-picks")
-            .append_header(("Authorization", token))
-            .send_json(&SavePicksParams {
-                answers: vec![
-                    Answer {
-                        id: questions[0].id,
-                        value: "one".to_string(),
-                    },
-                    Answer {
-                        id: questions[1].id,
-                        value: "two".to_string(),
-                    },
-                ],
-            })
+            .append_header(("Authorization", user.session_id.unwrap()))
+            .send_json(&params(questions[0].id, questions[1].id))
             .await
             .unwrap();
 
         assert_eq!(res.status().as_u16(), 200);
 
-        let mut stream = ws_conn.1.take(2);
-        // skip the first one, as it's a heartbeat
-        stream.next().await;
-        let msg = stream.next().await;
-
-        let data = get_websocket_frame_data(msg.unwrap().unwrap());
-        if data.is_some() {
-            let msg = data.unwrap();
-            assert_eq!(msg.path, "/picks");
-            assert_eq!(msg.game_id, game.id);
-            let round_picks: GetRoundPicksResponse = serde_json::from_value(msg.data).unwrap();
-            assert_eq!(round_picks.locked, false);
-            assert_eq!(round_picks.data[0].answer, "one");
-            assert_eq!(round_picks.data[1].answer, "two");
-            assert_eq!(round_picks.data.len(), 2);
-        } else {
-            assert!(false, "Message was not a string");
-        }
+        // skips the /players and /presence messages from joining
+        let msg = read_until_path(&mut ws_conn.1, Topic::Picks).await;
+        assert_eq!(msg.game_id, game.id);
+        let round_picks: GetRoundPicksResponse = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(round_picks.locked, false);
+        assert_eq!(round_picks.data[0].answer, "one");
+        assert_eq!(round_picks.data[1].answer, "two");
+        assert_eq!(round_picks.data.len(), 2);
 
-        drop(stream);
+        drop(ws_conn);
 
         srv.stop().await;
 
-        let answers: Vec<UserQuestion> = user_questions::dsl::user_questions
-            .filter(user_questions::dsl::user_id.eq(user.id))
-            .get_results(&conn)
+        let answers = repository
+            .find_picks_by_round_and_user(round.id, user.id)
+            .await
             .unwrap();
 
         assert_eq!(answers.len(), 2);
-
-        clear_game_data(&conn);
     }
 
     #[actix_rt::test]
     async fn test_owner_cannot_select() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let (questions, game, user, _) = create_game_data(&conn);
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, game, user, _) = create_game_data(&repository).await;
 
         let claim = PrivateClaim::new(user.id, user.user_name.clone(), game.id, Role::Owner);
         let token = create_jwt(claim).unwrap();
 
-        let (status, _): (u16, ErrorResponse) = test_post(
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository,
             "/api/rounds/set-picks",
-            SavePicksParams {
-                answers: vec![
-                    Answer {
-                        id: questions[0].id,
-                        value: "one".to_string(),
-                    },
-                    Answer {
-                        id: questions[1].id,
-                        value: "two".to_string(),
-                    },
-                ],
-            },
+            params(questions[0].id, questions[1].id),
             Some(token),
         )
         .await;
 
         assert_eq!(status, 403);
-
-        clear_game_data(&conn);
     }
 
     #[actix_rt::test]
-    async fn test_no_active_round() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+    async fn test_spectator_cannot_select() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, game, _, round) = create_game_data(&repository).await;
+
+        let claim = PrivateClaim::new(
+            game.id,
+            game.slug.clone().unwrap(),
+            game.id,
+            Role::Spectator,
+        );
+        let token = create_jwt(claim).unwrap();
+
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds/set-picks",
+            params(questions[0].id, questions[1].id),
+            Some(token),
+        )
+        .await;
 
-        let (questions, game, user, round) = create_game_data(&conn);
+        assert_eq!(status, 403);
 
-        diesel::update(rounds::table)
-            .set(rounds::dsl::locked.eq(true))
-            .filter(rounds::dsl::id.eq(round.id))
-            .execute(&conn)
-            .unwrap();
+        let answers = repository.find_picks_by_round(round.id).await.unwrap();
+        assert!(answers.is_empty());
+    }
 
-        let claim = PrivateClaim::new(user.id, user.user_name.clone(), game.id, Role::Player);
-        let token = create_jwt(claim).unwrap();
+    #[actix_rt::test]
+    async fn test_no_active_round() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, game, user, _) = create_game_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
 
-        let (status, _): (u16, ErrorResponse) = test_post(
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository,
             "/api/rounds/set-picks",
-            SavePicksParams {
-                answers: vec![
-                    Answer {
-                        id: questions[0].id,
-                        value: "one".to_string(),
-                    },
-                    Answer {
-                        id: questions[1].id,
-                        value: "two".to_string(),
-                    },
-                ],
-            },
-            Some(token),
+            params(questions[0].id, questions[1].id),
+            user.session_id,
         )
         .await;
 
         assert_eq!(status, 404);
-
-        clear_game_data(&conn);
     }
 
     #[actix_rt::test]
     async fn test_player_has_already_picked() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let (questions, game, user, round) = create_game_data(&conn);
-
-        diesel::insert_into(user_questions::table)
-            .values(NewUserQuestion {
-                user_id: user.id,
-                question_id: questions[0].id,
-                round_id: round.id,
-                answer: round.player_one.clone(),
-            })
-            .execute(&conn)
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, game, user, round) = create_game_data(&repository).await;
+
+        repository
+            .create_picks(
+                game.id,
+                user.id,
+                vec![Pick {
+                    question_id: questions[0].id,
+                    answer: round.player_one.clone(),
+                }],
+            )
+            .await
             .unwrap();
 
-        let claim = PrivateClaim::new(user.id, user.user_name.clone(), game.id, Role::Player);
-        let token = create_jwt(claim).unwrap();
-
-        let (status, err): (u16, ErrorResponse) = test_post(
+        let (status, err): (u16, ErrorResponse) = test_post_in_memory(
+            repository,
             "/api/rounds/set-picks",
-            SavePicksParams {
-                answers: vec![
-                    Answer {
-                        id: questions[0].id,
-                        value: "one".to_string(),
-                    },
-                    Answer {
-                        id: questions[1].id,
-                        value: "two".to_string(),
-                    },
-                ],
-            },
-            Some(token),
+            params(questions[0].id, questions[1].id),
+            user.session_id,
         )
         .await;
 
@@ -402,56 +243,105 @@ mod tests {
             err.errors[0],
             "User has already chosen picks for this round"
         );
+    }
 
-        clear_game_data(&conn);
+    #[actix_rt::test]
+    async fn test_duplicate_picks_conflict() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, game, user, round) = create_game_data(&repository).await;
+
+        let picks = || {
+            questions
+                .iter()
+                .map(|question| Pick {
+                    question_id: question.id,
+                    answer: round.player_one.clone(),
+                })
+                .collect::<Vec<Pick>>()
+        };
+
+        // a request racing past the has-picked check still hits the unique index
+        repository
+            .create_picks(game.id, user.id, picks())
+            .await
+            .unwrap();
+        let result = repository.create_picks(game.id, user.id, picks()).await;
+
+        assert_eq!(
+            result,
+            Err(Error::Conflict(
+                "User has already chosen picks for this round".to_string()
+            ))
+        );
+
+        // a batch picking the same question twice is rejected before replacing the saved picks
+        let twice = vec![picks().remove(0), picks().remove(0)];
+        let result = repository.replace_picks(game.id, user.id, twice).await;
+        assert_eq!(
+            result,
+            Err(Error::Conflict(
+                "User has already chosen picks for this round".to_string()
+            ))
+        );
+        assert_eq!(
+            repository
+                .find_picks_by_round(round.id)
+                .await
+                .unwrap()
+                .len(),
+            questions.len()
+        );
     }
 
     #[actix_rt::test]
-    async fn test_player_has_answered_valid_questions() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+    async fn test_picking_a_question_twice_conflicts() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, _, user, round) = create_game_data(&repository).await;
 
-        let (questions, game, user, _) = create_game_data(&conn);
+        // the same number of answers as questions, but one of them is missed
+        let (status, err): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds/set-picks",
+            params(questions[0].id, questions[0].id),
+            user.session_id,
+        )
+        .await;
 
-        let claim = PrivateClaim::new(user.id, user.user_name.clone(), game.id, Role::Player);
-        let token = create_jwt(claim).unwrap();
+        assert_eq!(status, 409);
+        assert_eq!(
+            err.errors[0],
+            "User has already chosen picks for this round"
+        );
+
+        let picks = repository.find_picks_by_round(round.id).await.unwrap();
+        assert!(picks.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_player_has_answered_valid_questions() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, _, user, _) = create_game_data(&repository).await;
 
         let answer_id = questions[0].id + 10;
-        let (status, err): (u16, ErrorResponse) = test_post(
+        let (status, err): (u16, ErrorResponse) = test_post_in_memory(
+            repository,
             "/api/rounds/set-picks",
-            SavePicksParams {
-                answers: vec![
-                    Answer {
-                        id: answer_id,
-                        value: "one".to_string(),
-                    },
-                    Answer {
-                        id: questions[1].id + 15,
-                        value: "two".to_string(),
-                    },
-                ],
-            },
-            Some(token),
+            params(answer_id, questions[1].id + 15),
+            user.session_id,
         )
         .await;
 
         assert_eq!(status, 400);
         assert_eq!(err.errors[0], format!("Invalid question id: {}", answer_id));
-
-        clear_game_data(&conn);
     }
 
     #[actix_rt::test]
     async fn test_player_missed_a_question() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, _, user, _) = create_game_data(&repository).await;
 
-        let (questions, game, user, _) = create_game_data(&conn);
-
-        let claim = PrivateClaim::new(user.id, user.user_name.clone(), game.id, Role::Player);
-        let token = create_jwt(claim).unwrap();
-
-        let (status, err): (u16, ErrorResponse) = test_post(
+        let (status, err): (u16, ErrorResponse) = test_post_in_memory(
+            repository,
             "/api/rounds/set-picks",
             SavePicksParams {
                 answers: vec![Answer {
@@ -459,13 +349,44 @@ mod tests {
                     value: "one".to_string(),
                 }],
             },
-            Some(token),
+            user.session_id,
         )
         .await;
 
         assert_eq!(status, 400);
         assert_eq!(err.errors[0], "Received 1 answers, expected 2".to_string());
+    }
+
+    #[actix_rt::test]
+    async fn test_saving_picks_twice_is_rejected() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (questions, _, user, round) = create_game_data(&repository).await;
+
+        let (status, _): (u16, ()) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds/set-picks",
+            params(questions[0].id, questions[1].id),
+            user.session_id.clone(),
+        )
+        .await;
+        assert_eq!(status, 200);
+
+        let picks = repository.find_picks_by_round(round.id).await.unwrap();
+        assert_eq!(picks.len(), 2);
+        assert_eq!(picks[0].user_name, "agmcleod");
+        assert_eq!(picks[0].answer, "one");
 
-        clear_game_data(&conn);
+        let (status, err): (u16, ErrorResponse) = test_post_in_memory(
+            repository,
+            "/api/rounds/set-picks",
+            params(questions[0].id, questions[1].id),
+            user.session_id,
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(
+            err.errors[0],
+            "User has already chosen picks for this round"
+        );
     }
 }
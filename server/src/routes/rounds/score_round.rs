@@ -1,21 +1,18 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
 use actix::Addr;
 use actix_identity::Identity;
 use actix_web::{
-    web::{block, Data, Json},
+    web::{Data, Json},
     HttpResponse,
 };
 use serde::{Deserialize, Serialize};
 
-use auth::{get_claim_from_identity, PrivateClaim, Role};
-use db::{
-    get_conn,
-    models::{Round, User, UserQuestion},
-    Connection, PgPool,
-};
+use auth::{get_claim_from_identity, Role};
+use db::repository::{Pick, Repository};
 use errors::Error;
 
+use crate::handlers::get_leaderboard;
 use crate::websocket::{client_messages, Server};
 
 #[derive(Deserialize, Serialize)]
@@ -29,10 +26,42 @@ pub struct Params {
     pub answers: Vec<Answer>,
 }
 
+/// Each answer has to be for one of the game's questions, and each question can only have one
+async fn validate_answers(
+    repository: &dyn Repository,
+    game_id: i32,
+    answers: &[Answer],
+) -> Result<(), Error> {
+    let game_question_ids: HashSet<i32> = repository
+        .find_questions_by_game(game_id)
+        .await?
+        .iter()
+        .map(|question| question.id)
+        .collect();
+
+    let mut question_ids = HashSet::new();
+    for answer in answers {
+        if !game_question_ids.contains(&answer.question_id) {
+            return Err(Error::BadRequest(format!(
+                "Invalid question id: {}",
+                answer.question_id
+            )));
+        }
+        if !question_ids.insert(answer.question_id) {
+            return Err(Error::BadRequest(format!(
+                "Received more than one answer for question id: {}",
+                answer.question_id
+            )));
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn score_round(
     id: Identity,
     websocket_srv: Data<Addr<Server>>,
-    pool: Data<PgPool>,
+    repository: Data<dyn Repository>,
     params: Json<Params>,
 ) -> Result<HttpResponse, Error> {
     let (claim, _) = get_claim_from_identity(id)?;
@@ -40,172 +69,133 @@ pub async fn score_round(
     if claim.role != Role::Owner {
         return Err(Error::Forbidden);
     }
+    let repository = repository.get_ref();
+    validate_answers(repository, claim.game_id, &params.answers).await?;
+
+    let answers = params
+        .into_inner()
+        .answers
+        .into_iter()
+        .map(|answer| Pick {
+            question_id: answer.question_id,
+            answer: answer.answer,
+        })
+        .collect();
+    if !repository.score_round(claim.game_id, answers).await? {
+        return Ok(HttpResponse::Ok().json(()));
+    }
 
-    let conn = get_conn(&pool)?;
-    let res: Result<(Connection, PrivateClaim), Error> = block(move || {
-        let round = Round::get_unfinished_round_by_game_id(&conn, claim.game_id)?;
-        let user_questions = UserQuestion::find_by_round(&conn, round.id)?;
-
-        let mut scores: HashMap<i32, i32> = HashMap::new();
-
-        for uq in &user_questions {
-            for answer in &params.answers {
-                if answer.question_id == uq.question_id && answer.answer == uq.answer {
-                    let score = {
-                        let s = scores.get(&uq.user_id).unwrap_or(&0);
-                        *s
-                    };
-                    scores.insert(uq.user_id, score + 1);
-                }
-            }
-        }
-
-        for (user_id, amount) in &scores {
-            User::add_score(&conn, *user_id, *amount)?;
-        }
-
-        Round::finish(&conn, round.id)?;
-
-        Ok((conn, claim))
-    })
-    .await?;
+    let leaderboard = get_leaderboard(repository, claim.game_id)
+        .await?
+        .leaderboard;
 
-    let (conn, claim) = res?;
-
-    client_messages::send_game_status(&websocket_srv, conn, claim.game_id).await;
-    let conn = get_conn(&pool)?;
-    client_messages::send_round_status(&websocket_srv, conn, claim.role, claim.id, claim.game_id)
-        .await;
+    client_messages::send_game_status(&websocket_srv, repository, claim.game_id).await;
+    client_messages::send_round_status(&websocket_srv, repository, claim.game_id).await;
+    client_messages::send_leaderboard(&websocket_srv, claim.game_id, &leaderboard);
+    client_messages::send_pick_distribution(&websocket_srv, repository, claim.game_id).await;
+    client_messages::send_round_picks(&websocket_srv, repository, claim.game_id).await;
 
     Ok(HttpResponse::Ok().json(()))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use actix_web_actors::ws;
     use awc::Client;
-    use diesel::{self, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
-    use futures::{SinkExt, StreamExt};
+    use futures::SinkExt;
 
     use db::{
-        get_conn,
-        models::{Game, NewUserQuestion, Question, Round, User},
-        new_pool,
-        schema::{games, questions as questions_dsl, rounds, user_questions, users},
+        models::{Game, PicksVisibility, Question, Round, User, UserDetails},
+        repository::{
+            GameRepository, LeaderboardRepository, MemoryRepository, Pick, PickRepository,
+            RoundRepository, UserRepository,
+        },
     };
 
-    use auth::{create_jwt, PrivateClaim, Role};
     use errors::ErrorResponse;
 
-    use crate::handlers::{RoundStatusRepsonse, StatusResponse};
-    use crate::tests::helpers::tests::{get_test_server, get_websocket_frame_data, test_post};
+    use crate::handlers::{Leaderboard, LeaderboardEntry, RoundStatusRepsonse, StatusResponse};
+    use crate::tests::helpers::tests::{
+        get_memory_test_server, read_until_path, test_post_in_memory,
+    };
+    use crate::websocket::Topic;
 
     use super::{Answer, Params};
 
-    #[derive(Insertable)]
-    #[table_name = "games"]
-    struct NewGame {
-        slug: Option<String>,
-    }
-
-    #[derive(Insertable)]
-    #[table_name = "rounds"]
-    pub struct NewRoundWithFlags {
-        pub player_one: String,
-        pub player_two: String,
-        pub game_id: i32,
-        pub locked: bool,
-        pub finished: bool,
+    fn picks(questions: &[Question], answers: &[&str]) -> Vec<Pick> {
+        questions
+            .iter()
+            .zip(answers)
+            .map(|(question, answer)| Pick {
+                question_id: question.id,
+                answer: answer.to_string(),
+            })
+            .collect()
     }
 
-    #[derive(Insertable)]
-    #[table_name = "users"]
-    pub struct NewUser {
-        pub user_name: String,
-        pub game_id: i32,
-        pub score: i32,
+    fn params(questions: &[Question], answers: &[&str]) -> Params {
+        Params {
+            answers: picks(questions, answers)
+                .into_iter()
+                .map(|pick| Answer {
+                    answer: pick.answer,
+                    question_id: pick.question_id,
+                })
+                .collect(),
+        }
     }
 
-    fn create_data(conn: &PgConnection) -> (Game, Vec<Question>, Round, User) {
-        let game: Game = diesel::insert_into(games::table)
-            .values(NewGame {
-                slug: Some("abc123".to_string()),
-            })
-            .get_result(conn)
-            .unwrap();
-
-        let questions: Vec<Question> = diesel::insert_into(questions_dsl::table)
-            .values(&vec![
-                questions_dsl::body.eq("One question".to_string()),
-                questions_dsl::body.eq("Second question".to_string()),
-            ])
-            .get_results(conn)
+    /// A game with a round open for picks, and a player that picked "one" for both questions
+    async fn create_data(repository: &MemoryRepository) -> (Game, Vec<Question>, Round, User) {
+        let questions = vec![
+            repository.add_question("One question"),
+            repository.add_question("Second question"),
+        ];
+        let question_ids: Vec<i32> = questions.iter().map(|question| question.id).collect();
+        let game = repository
+            .create_game(&question_ids, PicksVisibility::HostOnly)
+            .await
             .unwrap();
-
-        let round: Round = diesel::insert_into(rounds::table)
-            .values(NewRoundWithFlags {
-                player_one: "one".to_string(),
-                player_two: "two".to_string(),
-                game_id: game.id,
-                locked: true,
-                finished: false,
-            })
-            .get_result(conn)
+        let round = repository
+            .create_round(game.id, "one".to_string(), "two".to_string())
+            .await
             .unwrap();
-
-        let user: User = diesel::insert_into(users::table)
-            .values(NewUser {
-                user_name: "agmcleod".to_string(),
-                game_id: game.id,
-                score: 4,
-            })
-            .get_result(conn)
+        let user = repository
+            .create_user(game.id, "agmcleod".to_string())
+            .await
             .unwrap();
-
-        diesel::insert_into(user_questions::table)
-            .values(vec![
-                NewUserQuestion {
-                    question_id: questions[0].id,
-                    round_id: round.id,
-                    answer: "one".to_string(),
-                    user_id: user.id,
-                },
-                NewUserQuestion {
-                    question_id: questions[1].id,
-                    round_id: round.id,
-                    answer: "one".to_string(),
-                    user_id: user.id,
-                },
-            ])
-            .execute(conn)
+        repository
+            .create_picks(game.id, user.id, picks(&questions, &["one", "one"]))
+            .await
             .unwrap();
 
         (game, questions, round, user)
     }
 
-    fn delete_data(conn: &PgConnection) {
-        diesel::delete(user_questions::table).execute(conn).unwrap();
-        diesel::delete(rounds::table).execute(conn).unwrap();
-        diesel::delete(users::table).execute(conn).unwrap();
-        diesel::delete(games::table).execute(conn).unwrap();
-        diesel::delete(questions_dsl::table).execute(conn).unwrap();
+    async fn find_user(repository: &MemoryRepository, game_id: i32, user_id: i32) -> UserDetails {
+        repository
+            .find_users_by_game(game_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|user| user.id == user_id)
+            .unwrap()
     }
 
     #[actix_rt::test]
     async fn test_scoring_round_sums_amounts() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
-
-        let (game, questions, round, user) = create_data(&conn);
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, round, user) = create_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
 
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Owner);
-
-        let srv = get_test_server();
+        let srv = get_memory_test_server(repository.clone());
 
         let client = Client::default();
         let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap();
 
-        let token = create_jwt(claim).unwrap();
+        let token = game.creator.clone().unwrap();
         ws_conn
             .1
             .send(ws::Message::Text(
@@ -213,89 +203,162 @@ mod tests {
             ))
             .await
             .unwrap();
+        read_until_path(&mut ws_conn.1, Topic::Players).await;
 
         let res = srv
             .post("/api/rounds/score")
             .append_header(("Authorization", token))
-            .send_json(&Params {
-                answers: vec![
-                    Answer {
-                        answer: "one".to_string(),
-                        question_id: questions[0].id,
-                    },
-                    Answer {
-                        answer: "two".to_string(),
-                        question_id: questions[1].id,
-                    },
-                ],
-            })
+            .send_json(&params(&questions, &["one", "two"]))
             .await
             .unwrap();
 
         assert_eq!(res.status().as_u16(), 200);
 
-        let mut stream = ws_conn.1.take(3);
-        // skip the first one, as it's a heartbeat
-        stream.next().await;
-        let msg = stream.next().await;
-
-        let data = get_websocket_frame_data(msg.unwrap().unwrap());
-        if data.is_some() {
-            let msg = data.unwrap();
-            assert_eq!(msg.path, "/game-status");
-            assert_eq!(msg.game_id, game.id);
-            let game_status: StatusResponse = serde_json::from_value(msg.data).unwrap();
-            // round is locked and is now finished
-            assert_eq!(game_status.open_round, false);
-            assert_eq!(game_status.unfinished_round, false);
-            assert_eq!(game_status.slug, "abc123");
-        } else {
-            assert!(false, "Message was not a string");
-        }
+        let msg = read_until_path(&mut ws_conn.1, Topic::GameStatus).await;
+        assert_eq!(msg.game_id, game.id);
+        let game_status: StatusResponse = serde_json::from_value(msg.data).unwrap();
+        // round is locked and is now finished
+        assert_eq!(game_status.open_round, false);
+        assert_eq!(game_status.unfinished_round, false);
+        assert_eq!(game_status.slug, game.slug.clone().unwrap());
 
-        let msg = stream.next().await;
-
-        let data = get_websocket_frame_data(msg.unwrap().unwrap());
-        if data.is_some() {
-            let msg = data.unwrap();
-            assert_eq!(msg.path, "/round-status");
-            assert_eq!(msg.game_id, game.id);
-            let round_status: RoundStatusRepsonse = serde_json::from_value(msg.data).unwrap();
-            assert_eq!(round_status.locked, true);
-            assert_eq!(round_status.finished, true);
-            assert_eq!(round_status.picks_chosen, false);
-        } else {
-            assert!(false, "Message was not a string");
-        }
+        let msg = read_until_path(&mut ws_conn.1, Topic::RoundStatus).await;
+        assert_eq!(msg.game_id, game.id);
+        let round_status: RoundStatusRepsonse = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(round_status.locked, true);
+        assert_eq!(round_status.finished, true);
+        assert_eq!(round_status.picks_chosen, false);
 
-        drop(stream);
+        drop(ws_conn);
 
         srv.stop().await;
 
-        let updated_user: User = users::dsl::users.find(user.id).first(&conn).unwrap();
-        assert_eq!(updated_user.score, 5);
+        assert_eq!(find_user(&repository, game.id, user.id).await.score, 1);
 
-        let updated_round: Round = rounds::dsl::rounds.find(round.id).first(&conn).unwrap();
+        let updated_round = repository.find_latest_round(game.id).await.unwrap();
+        assert_eq!(updated_round.id, round.id);
         assert_eq!(updated_round.finished, true);
-
-        delete_data(&conn);
     }
 
     #[actix_rt::test]
-    async fn test_scoring_finished_rounds_returns_404() {
-        let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+    async fn test_scoring_round_sends_leaderboard() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, round, user) = create_data(&repository).await;
+        // ties with agmcleod once they both score their point
+        let other = repository
+            .create_user(game.id, "smurf".to_string())
+            .await
+            .unwrap();
+        repository
+            .create_picks(game.id, other.id, picks(&questions, &["two", "two"]))
+            .await
+            .unwrap();
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let srv = get_memory_test_server(repository);
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
 
-        let (game, questions, round, _) = create_data(&conn);
+        let token = game.creator.clone().unwrap();
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut ws_conn, Topic::Players).await;
 
-        diesel::update(rounds::dsl::rounds.find(round.id))
-            .set(rounds::dsl::finished.eq(true))
-            .execute(&conn)
+        let res = srv
+            .post("/api/rounds/score")
+            .append_header(("Authorization", token))
+            .send_json(&params(&questions, &["one", "two"]))
+            .await
             .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        let msg = read_until_path(&mut ws_conn, Topic::Leaderboard).await;
+        let leaderboard: Leaderboard = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(leaderboard.round_id, Some(round.id));
+        assert_eq!(
+            leaderboard.entries,
+            vec![
+                LeaderboardEntry {
+                    rank: 1,
+                    user_id: user.id,
+                    user_name: "agmcleod".to_string(),
+                    score: 1,
+                    round_score: 1,
+                    movement: None,
+                },
+                LeaderboardEntry {
+                    rank: 1,
+                    user_id: other.id,
+                    user_name: "smurf".to_string(),
+                    score: 1,
+                    round_score: 1,
+                    movement: None,
+                },
+            ]
+        );
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_scoring_finished_round_is_idempotent() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, user) = create_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
+
+        // a retried request scores the round once
+        for _ in 0..2 {
+            let (status, _): (u16, ()) = test_post_in_memory(
+                repository.clone(),
+                "/api/rounds/score",
+                params(&questions, &["one", "two"]),
+                game.creator.clone(),
+            )
+            .await;
+
+            assert_eq!(status, 200);
+        }
+
+        assert_eq!(find_user(&repository, game.id, user.id).await.score, 1);
+        assert_eq!(
+            repository
+                .find_leaderboard_snapshots(game.id)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_scoring_without_locked_round_returns_404() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, _) = create_data(&repository).await;
+
+        let (status, _): (u16, ErrorResponse) = test_post_in_memory(
+            repository,
+            "/api/rounds/score",
+            params(&questions[..1], &["one"]),
+            game.creator.clone(),
+        )
+        .await;
+
+        assert_eq!(status, 404);
+    }
 
-        let claim = PrivateClaim::new(game.id, game.slug.unwrap().clone(), game.id, Role::Owner);
+    #[actix_rt::test]
+    async fn test_scoring_duplicate_question_returns_400() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, user) = create_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
 
-        let (status, _): (u16, ErrorResponse) = test_post(
+        let (status, res): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
             "/api/rounds/score",
             Params {
                 answers: vec![
@@ -305,16 +368,163 @@ mod tests {
                     },
                     Answer {
                         answer: "two".to_string(),
-                        question_id: questions[1].id,
+                        question_id: questions[0].id,
                     },
                 ],
             },
-            Some(create_jwt(claim).unwrap()),
+            game.creator.clone(),
         )
         .await;
 
-        assert_eq!(status, 404);
+        assert_eq!(status, 400);
+        assert_eq!(
+            res.errors[0],
+            format!(
+                "Received more than one answer for question id: {}",
+                questions[0].id
+            )
+        );
+
+        // nothing was scored
+        assert_eq!(find_user(&repository, game.id, user.id).await.score, 0);
+        assert_eq!(
+            repository
+                .find_latest_round(game.id)
+                .await
+                .unwrap()
+                .finished,
+            false
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_scoring_unknown_question_returns_400() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, user) = create_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let unknown_id = questions[1].id + 100;
+        let (status, res): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds/score",
+            Params {
+                answers: vec![
+                    Answer {
+                        answer: "one".to_string(),
+                        question_id: questions[0].id,
+                    },
+                    Answer {
+                        answer: "two".to_string(),
+                        question_id: unknown_id,
+                    },
+                ],
+            },
+            game.creator.clone(),
+        )
+        .await;
+
+        assert_eq!(status, 400);
+        assert_eq!(
+            res.errors[0],
+            format!("Invalid question id: {}", unknown_id)
+        );
+        assert_eq!(find_user(&repository, game.id, user.id).await.score, 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_scoring_another_games_question_returns_400() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, user) = create_data(&repository).await;
+        repository.lock_active_round(game.id).await.unwrap();
+
+        let other_question = repository.add_question("Another game's question");
+        repository
+            .create_game(&[other_question.id], PicksVisibility::HostOnly)
+            .await
+            .unwrap();
+
+        let (status, res): (u16, ErrorResponse) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds/score",
+            Params {
+                answers: vec![
+                    Answer {
+                        answer: "one".to_string(),
+                        question_id: questions[0].id,
+                    },
+                    Answer {
+                        answer: "one".to_string(),
+                        question_id: other_question.id,
+                    },
+                ],
+            },
+            game.creator.clone(),
+        )
+        .await;
+
+        assert_eq!(status, 400);
+        assert_eq!(
+            res.errors[0],
+            format!("Invalid question id: {}", other_question.id)
+        );
+
+        // nothing was scored
+        assert_eq!(find_user(&repository, game.id, user.id).await.score, 0);
+        assert_eq!(
+            repository
+                .find_latest_round(game.id)
+                .await
+                .unwrap()
+                .finished,
+            false
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_scoring_round_scores_every_player() {
+        let repository = Arc::new(MemoryRepository::new());
+        let (game, questions, _, user) = create_data(&repository).await;
+
+        // even players get the first question right, and everyone gets the second one right
+        let mut players = Vec::new();
+        for i in 0..100 {
+            let player = repository
+                .create_user(game.id, format!("player{}", i))
+                .await
+                .unwrap();
+            let first = if i % 2 == 0 { "one" } else { "two" };
+            repository
+                .create_picks(game.id, player.id, picks(&questions, &[first, "two"]))
+                .await
+                .unwrap();
+            players.push(player);
+        }
+        repository.lock_active_round(game.id).await.unwrap();
 
-        delete_data(&conn);
+        let (status, _): (u16, ()) = test_post_in_memory(
+            repository.clone(),
+            "/api/rounds/score",
+            params(&questions, &["one", "two"]),
+            game.creator.clone(),
+        )
+        .await;
+
+        assert_eq!(status, 200);
+
+        let mut scores: Vec<(i32, i32)> = repository
+            .find_users_by_game(game.id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|user| (user.id, user.score))
+            .collect();
+        scores.sort();
+        assert_eq!(scores.len(), 101);
+        // picked "one" for both questions, so only got the first one right
+        assert_eq!(scores[0], (user.id, 1));
+        for (i, (player, score)) in players.iter().zip(&scores[1..]).enumerate() {
+            assert_eq!(score.0, player.id);
+            assert_eq!(score.1, if i % 2 == 0 { 2 } else { 1 });
+        }
     }
 }
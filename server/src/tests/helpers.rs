@@ -1,6 +1,7 @@
 #[cfg(test)]
 pub mod tests {
-    use std::time::SystemTime;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
 
     use actix::Actor;
     use actix_http::Request;
@@ -15,14 +16,42 @@ pub mod tests {
         App,
     };
     use actix_web_actors::ws;
+    use futures::{Stream, StreamExt};
     use serde::{de::DeserializeOwned, Deserialize, Serialize};
     use serde_json;
 
     use auth::{create_jwt, get_identity_service, PrivateClaim};
-    use db;
+    use db::{
+        self,
+        repository::{DbRepository, MemoryRepository, Repository},
+    };
 
+    use crate::config::WebsocketConfig;
+    use crate::metrics::Metrics;
+    use crate::middleware::{RequestMetrics, RequestTracing};
     use crate::routes::routes;
-    use crate::websocket::{MessageToClient, Server};
+    use crate::websocket::{MessageToClient, Server, ServerMessage, Topic};
+
+    /// Inserts the rows one at a time, returning them in order. diesel-async can't batch inserts
+    /// on SQLite, so test data goes in this way to run against either database.
+    macro_rules! insert_each {
+        ($conn:expr, $table:expr, $rows:expr $(,)?) => {{
+            let mut inserted = Vec::new();
+            for row in $rows {
+                inserted.push(
+                    diesel::insert_into($table)
+                        .values(row)
+                        .get_result($conn)
+                        .await
+                        .unwrap(),
+                );
+            }
+
+            inserted
+        }};
+    }
+
+    pub(crate) use insert_each;
 
     #[derive(Deserialize, Serialize, Debug)]
     struct CookieValue {
@@ -35,11 +64,37 @@ pub mod tests {
 
     pub async fn get_service(
     ) -> impl Service<Request, Response = ServiceResponse<EitherBody<BoxBody>>, Error = Error> {
+        let pool = db::new_pool();
+        let repository: Arc<dyn Repository> = Arc::new(DbRepository::new(pool.clone()));
+        let metrics = Data::new(Metrics::default());
         test::init_service(
             App::new()
                 .wrap(get_identity_service())
-                .app_data(Data::new(db::new_pool()))
-                .app_data(Data::new(Server::new(db::new_pool()).start()))
+                .wrap(RequestMetrics::new(metrics.clone()))
+                .wrap(RequestTracing)
+                .app_data(Data::new(pool))
+                .app_data(metrics)
+                .app_data(Data::from(repository.clone()))
+                .app_data(Data::new(Server::new(repository).start()))
+                .app_data(Data::new(WebsocketConfig::default()))
+                .configure(routes),
+        )
+        .await
+    }
+
+    /// Same as `get_service`, but backed by the in-memory repository, so tests using it don't
+    /// need a database and can run in parallel
+    pub async fn get_memory_service(
+        repository: Arc<MemoryRepository>,
+    ) -> impl Service<Request, Response = ServiceResponse<EitherBody<BoxBody>>, Error = Error> {
+        let repository: Arc<dyn Repository> = repository;
+        test::init_service(
+            App::new()
+                .wrap(get_identity_service())
+                .wrap(RequestTracing)
+                .app_data(Data::from(repository.clone()))
+                .app_data(Data::new(Server::new(repository).start()))
+                .app_data(Data::new(WebsocketConfig::default()))
                 .configure(routes),
         )
         .await
@@ -47,27 +102,40 @@ pub mod tests {
 
     pub fn get_test_server() -> actix_test::TestServer {
         actix_test::start(|| {
+            let pool = db::new_pool();
+            let repository: Arc<dyn Repository> = Arc::new(DbRepository::new(pool.clone()));
+            let metrics = Data::new(Metrics::default());
             App::new()
                 .wrap(get_identity_service())
-                .app_data(Data::new(db::new_pool()))
-                .app_data(Data::new(Server::new(db::new_pool()).start()))
+                .wrap(RequestMetrics::new(metrics.clone()))
+                .wrap(RequestTracing)
+                .app_data(Data::new(pool))
+                .app_data(metrics)
+                .app_data(Data::from(repository.clone()))
+                .app_data(Data::new(Server::new(repository).start()))
+                .app_data(Data::new(WebsocketConfig::default()))
                 .configure(routes)
         })
     }
 
-    /// Helper for HTTP GET integration tests
-    pub async fn test_get<R>(route: &str, token: Option<String>) -> (u16, R)
+    /// Same as `get_test_server`, but backed by the in-memory repository
+    pub fn get_memory_test_server(repository: Arc<MemoryRepository>) -> actix_test::TestServer {
+        actix_test::start(move || {
+            let repository: Arc<dyn Repository> = repository.clone();
+            App::new()
+                .wrap(get_identity_service())
+                .wrap(RequestTracing)
+                .app_data(Data::from(repository.clone()))
+                .app_data(Data::new(Server::new(repository).start()))
+                .app_data(Data::new(WebsocketConfig::default()))
+                .configure(routes)
+        })
+    }
+
+    async fn read_json_response<R>(res: ServiceResponse<EitherBody<BoxBody>>) -> (u16, R)
     where
         R: DeserializeOwned,
     {
-        let mut app = get_service().await;
-        let mut req = test::TestRequest::get().uri(route);
-        if let Some(token) = token {
-            req = req.append_header(("Authorization", token));
-        }
-
-        let res = test::call_service(&mut app, req.to_request()).await;
-
         let status = res.status().as_u16();
         let body = test::read_body(res).await;
         let json_body = serde_json::from_slice(&body).unwrap_or_else(|_| {
@@ -82,36 +150,64 @@ pub mod tests {
         (status, json_body)
     }
 
-    /// Helper for HTTP POST integration tests
-    pub async fn test_post<T: Serialize, R>(
+    fn get_request(route: &str, token: Option<String>) -> Request {
+        let mut req = test::TestRequest::get().uri(route);
+        if let Some(token) = token {
+            req = req.append_header(("Authorization", token));
+        }
+
+        req.to_request()
+    }
+
+    fn post_request<T: Serialize>(route: &str, params: T, token: Option<String>) -> Request {
+        let mut req = test::TestRequest::post().set_json(&params).uri(route);
+        if let Some(token) = token {
+            req = req.append_header(("Authorization", token));
+        }
+
+        req.to_request()
+    }
+
+    /// Helper for HTTP GET integration tests
+    pub async fn test_get<R>(route: &str, token: Option<String>) -> (u16, R)
+    where
+        R: DeserializeOwned,
+    {
+        let app = get_service().await;
+        let res = test::call_service(&app, get_request(route, token)).await;
+
+        read_json_response(res).await
+    }
+
+    /// `test_get`, against the in-memory repository
+    pub async fn test_get_in_memory<R>(
+        repository: Arc<MemoryRepository>,
         route: &str,
-        params: T,
         token: Option<String>,
     ) -> (u16, R)
     where
         R: DeserializeOwned,
     {
-        let mut app = get_service().await;
-
-        let mut req = test::TestRequest::post().set_json(&params).uri(route);
-        if let Some(token) = token {
-            req = req.append_header(("Authorization", token));
-        }
+        let app = get_memory_service(repository).await;
+        let res = test::call_service(&app, get_request(route, token)).await;
 
-        let res = test::call_service(&mut app, req.to_request()).await;
+        read_json_response(res).await
+    }
 
-        let status = res.status().as_u16();
-        let body = test::read_body(res).await;
-        let json_body = serde_json::from_slice(&body).unwrap_or_else(|_| {
-            panic!(
-                "read_response_json failed during deserialization. response: {} status: {}",
-                String::from_utf8(body.to_vec())
-                    .unwrap_or_else(|_| "Could not convert Bytes -> String".to_string()),
-                status
-            )
-        });
+    /// `test_post`, against the in-memory repository
+    pub async fn test_post_in_memory<T: Serialize, R>(
+        repository: Arc<MemoryRepository>,
+        route: &str,
+        params: T,
+        token: Option<String>,
+    ) -> (u16, R)
+    where
+        R: DeserializeOwned,
+    {
+        let app = get_memory_service(repository).await;
+        let res = test::call_service(&app, post_request(route, params, token)).await;
 
-        (status, json_body)
+        read_json_response(res).await
     }
 
     pub fn get_auth_token(private_claim: PrivateClaim) -> String {
@@ -119,16 +215,70 @@ pub mod tests {
     }
 
     pub fn get_websocket_frame_data(frame: ws::Frame) -> Option<MessageToClient> {
-        match frame {
-            ws::Frame::Text(t) => {
-                let bytes = t.as_ref();
-                let data = String::from_utf8(bytes.to_vec()).unwrap();
-                let value: MessageToClient = serde_json::from_str(&data).unwrap();
-                return Some(value);
-            }
-            _ => {}
+        if let Some(ServerMessage::Event(msg)) = get_websocket_server_message(frame) {
+            return Some(msg);
+        }
+
+        None
+    }
+
+    pub fn get_websocket_server_message(frame: ws::Frame) -> Option<ServerMessage> {
+        if let ws::Frame::Text(t) = frame {
+            let bytes = t.as_ref();
+            let data = String::from_utf8(bytes.to_vec()).unwrap();
+            let value: ServerMessage = serde_json::from_str(&data).unwrap();
+            return Some(value);
         }
 
         None
     }
+
+    /// Reads frames off a websocket stream until a message for `path` arrives, skipping any others
+    pub async fn read_until_path<S>(stream: &mut S, path: Topic) -> MessageToClient
+    where
+        S: Stream<Item = Result<ws::Frame, ws::ProtocolError>> + Unpin,
+    {
+        loop {
+            let frame = actix_rt::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .unwrap_or_else(|_| panic!("Timed out waiting for {:?}", path))
+                .expect("Websocket stream closed")
+                .unwrap();
+            if let Some(msg) = get_websocket_frame_data(frame) {
+                if msg.path == path {
+                    return msg;
+                }
+            }
+        }
+    }
+
+    /// Reads frames off a websocket stream until the next message from the server, skipping pings
+    pub async fn next_server_message<S>(stream: &mut S) -> ServerMessage
+    where
+        S: Stream<Item = Result<ws::Frame, ws::ProtocolError>> + Unpin,
+    {
+        loop {
+            let frame = actix_rt::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .expect("Timed out waiting for a server message")
+                .expect("Websocket stream closed")
+                .unwrap();
+            if let Some(msg) = get_websocket_server_message(frame) {
+                return msg;
+            }
+        }
+    }
+
+    /// Like `next_server_message`, but also skips broadcast events to get a reply to a command
+    pub async fn next_reply<S>(stream: &mut S) -> ServerMessage
+    where
+        S: Stream<Item = Result<ws::Frame, ws::ProtocolError>> + Unpin,
+    {
+        loop {
+            match next_server_message(stream).await {
+                ServerMessage::Event(_) => continue,
+                msg => return msg,
+            }
+        }
+    }
 }
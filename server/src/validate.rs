@@ -19,6 +19,15 @@ fn collect_errors(errors: ValidationErrors) -> Vec<String> {
 }
 
 pub fn validate<T>(params: &Json<T>) -> Result<(), Error>
+where
+    T: Validate,
+{
+    validate_params(&params.0)
+}
+
+/// Same as `validate`, for params that didn't come from a json request body, such as a websocket
+/// message
+pub fn validate_params<T>(params: &T) -> Result<(), Error>
 where
     T: Validate,
 {
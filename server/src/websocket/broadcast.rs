@@ -0,0 +1,63 @@
+use actix::prelude::{Message as ActixMessage, Recipient};
+use serde::{Deserialize, Serialize};
+
+use super::{TargetedMessageToClient, TargetedMessagesToClient};
+
+/// What's shared with every instance, for it to act on with its own sessions
+#[derive(Deserialize, Serialize)]
+pub enum Broadcast {
+    Message(TargetedMessageToClient),
+    Messages(TargetedMessagesToClient),
+    /// Closes every session the player has open in the game
+    Kick {
+        game_id: i32,
+        user_id: i32,
+    },
+    /// A player's first session on `instance` opened, or their last one there closed
+    Presence {
+        instance: String,
+        game_id: i32,
+        user_id: i32,
+        online: bool,
+    },
+    /// Every player with a session on `instance`, as (game id, user id). Sent every so often, so
+    /// instances started later catch up, and ones that stop without saying so are noticed.
+    PresenceSnapshot {
+        instance: String,
+        online: Vec<(i32, i32)>,
+    },
+}
+
+/// A broadcast every server instance delivers to its own sessions
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Deliver(pub Broadcast);
+
+/// How the websocket `Server` shares messages with the other instances running behind the same
+/// load balancer. Every published message comes back to each instance's `Server` as a `Deliver`,
+/// including the instance that published it.
+pub trait BroadcastBackend: Unpin + 'static {
+    /// Called once the `Server` actor has started
+    fn start(&mut self, server: Recipient<Deliver>);
+
+    fn publish(&self, msg: Broadcast);
+}
+
+/// Only delivers to sessions on this process. The default, for running a single instance.
+#[derive(Default)]
+pub struct InProcessBackend {
+    server: Option<Recipient<Deliver>>,
+}
+
+impl BroadcastBackend for InProcessBackend {
+    fn start(&mut self, server: Recipient<Deliver>) {
+        self.server = Some(server);
+    }
+
+    fn publish(&self, msg: Broadcast) {
+        match &self.server {
+            Some(server) => server.do_send(Deliver(msg)),
+            None => error!("Broadcast backend was not started"),
+        }
+    }
+}
@@ -1,24 +1,26 @@
 use actix::Addr;
-use actix_web::web::Data;
-use diesel::pg::PgConnection;
-use diesel::r2d2::{ConnectionManager, PooledConnection};
-use serde_json::to_value;
+use serde_json::{to_value, Value};
 
-use auth::Role;
+use auth::{PrivateClaim, Role};
+use db::repository::Repository;
+use errors::Error;
 
-use super::{MessageToClient, Server};
-use crate::handlers;
+use super::{
+    GetOnlinePlayers, MessageToClient, PresenceUpdate, Server, Target, TargetedMessageToClient,
+    TargetedMessagesToClient, Topic,
+};
+use crate::handlers::{self, Leaderboard, RoundStatusRepsonse};
 
 pub async fn send_game_status(
-    websocket_srv: &Data<Addr<Server>>,
-    connection: PooledConnection<ConnectionManager<PgConnection>>,
+    websocket_srv: &Addr<Server>,
+    repository: &dyn Repository,
     game_id: i32,
 ) {
-    let status_response = handlers::get_game_status(connection, game_id).await;
+    let status_response = handlers::get_game_status(repository, game_id).await;
     match status_response {
         Ok(status_response) => {
             if let Ok(value) = to_value(status_response) {
-                let msg = MessageToClient::new("/game-status", game_id, value);
+                let msg = MessageToClient::new(Topic::GameStatus, game_id, value);
                 websocket_srv.do_send(msg);
             }
         }
@@ -26,21 +28,181 @@ pub async fn send_game_status(
     }
 }
 
+/// Sends the latest round's picks to the host, and to players and spectators once the game's
+/// picks visibility allows it
+pub async fn send_round_picks(
+    websocket_srv: &Addr<Server>,
+    repository: &dyn Repository,
+    game_id: i32,
+) {
+    let round_picks = handlers::get_round_picks(repository, game_id).await;
+    match round_picks {
+        Ok(round_picks) => {
+            if let Ok(value) = to_value(&round_picks.response) {
+                for role in &[Role::Owner, Role::Player, Role::Spectator] {
+                    if round_picks.visible_to(role) {
+                        let msg = MessageToClient::new(Topic::Picks, game_id, value.clone());
+                        websocket_srv.do_send(TargetedMessageToClient::new(
+                            Target::Role(role.clone()),
+                            msg,
+                        ));
+                    }
+                }
+            }
+        }
+        // no rounds yet
+        Err(Error::NotFound(_)) => {}
+        Err(err) => error!("{:?}", err),
+    }
+}
+
+/// Sends everyone in the game its players, for when one joins, leaves or is renamed
+pub async fn send_players(websocket_srv: &Addr<Server>, repository: &dyn Repository, game_id: i32) {
+    let players: Result<_, Error> = async {
+        let online_ids = websocket_srv.send(GetOnlinePlayers { game_id }).await?;
+        handlers::get_players(repository, game_id, online_ids).await
+    }
+    .await;
+
+    match players {
+        Ok(players) => {
+            if let Ok(value) = to_value(players) {
+                let msg = MessageToClient::new(Topic::Players, game_id, value);
+                websocket_srv.do_send(msg);
+            }
+        }
+        Err(err) => error!("{:?}", err),
+    }
+}
+
+/// Tells everyone in the game a player came online or went offline
+pub fn send_presence(websocket_srv: &Addr<Server>, game_id: i32, update: PresenceUpdate) {
+    if let Ok(value) = to_value(update) {
+        let msg = MessageToClient::new(Topic::Presence, game_id, value);
+        websocket_srv.do_send(msg);
+    }
+}
+
+pub fn send_leaderboard(websocket_srv: &Addr<Server>, game_id: i32, leaderboard: &Leaderboard) {
+    if let Ok(value) = to_value(leaderboard) {
+        let msg = MessageToClient::new(Topic::Leaderboard, game_id, value);
+        websocket_srv.do_send(msg);
+    }
+}
+
+/// Sends the host how the room picked in the latest round, and players and spectators too once
+/// the game's picks visibility allows it
+pub async fn send_pick_distribution(
+    websocket_srv: &Addr<Server>,
+    repository: &dyn Repository,
+    game_id: i32,
+) {
+    match handlers::get_pick_distribution(repository, game_id).await {
+        Ok(distribution) => {
+            if let Ok(value) = to_value(&distribution) {
+                for role in &[Role::Owner, Role::Player, Role::Spectator] {
+                    if distribution.visible_to(role) {
+                        let msg =
+                            MessageToClient::new(Topic::PickDistribution, game_id, value.clone());
+                        websocket_srv.do_send(TargetedMessageToClient::new(
+                            Target::Role(role.clone()),
+                            msg,
+                        ));
+                    }
+                }
+            }
+        }
+        Err(err) => error!("{:?}", err),
+    }
+}
+
+/// Sends the latest round status to everyone in the game. `picks_chosen` is computed for each
+/// player, so every recipient gets their own view of the round, all as a single event.
 pub async fn send_round_status(
-    websocket_srv: &Data<Addr<Server>>,
-    connection: PooledConnection<ConnectionManager<PgConnection>>,
-    role: Role,
-    user_id: i32,
+    websocket_srv: &Addr<Server>,
+    repository: &dyn Repository,
     game_id: i32,
 ) {
-    let round_status = handlers::get_round_status(connection, role, user_id, game_id).await;
+    let round_status = handlers::get_round_status_for_game(repository, game_id).await;
     match round_status {
-        Ok(round_status) => {
-            if let Ok(value) = to_value(round_status) {
-                let msg = MessageToClient::new("/round-status", game_id, value);
-                websocket_srv.do_send(msg);
+        Ok((round_status, players)) => {
+            let mut messages = Vec::new();
+            for role in &[Role::Owner, Role::Spectator] {
+                if let Ok(value) = to_value(&round_status) {
+                    let msg = MessageToClient::new(Topic::RoundStatus, game_id, value);
+                    messages.push(TargetedMessageToClient::new(
+                        Target::Role(role.clone()),
+                        msg,
+                    ));
+                }
+            }
+
+            for (user_id, picks_chosen) in players {
+                let player_status = RoundStatusRepsonse {
+                    picks_chosen,
+                    ..round_status.clone()
+                };
+                if let Ok(value) = to_value(player_status) {
+                    let msg = MessageToClient::new(Topic::RoundStatus, game_id, value);
+                    messages.push(TargetedMessageToClient::new(Target::User(user_id), msg));
+                }
             }
+
+            websocket_srv.do_send(TargetedMessagesToClient { game_id, messages });
         }
         Err(err) => error!("{:?}", err),
     }
 }
+
+/// Sends the current game status, round status and picks to a single session, for clients that
+/// reconnected after too much was sent to replay it.
+pub async fn send_snapshot(
+    websocket_srv: &Addr<Server>,
+    repository: &dyn Repository,
+    claim: &PrivateClaim,
+    session_id: String,
+) {
+    let game_id = claim.game_id;
+    let send = |path: Topic, value: serde_json::Result<Value>| {
+        if let Ok(value) = value {
+            let msg = MessageToClient::new(path, game_id, value);
+            websocket_srv.do_send(TargetedMessageToClient::new(
+                Target::Session(session_id.clone()),
+                msg,
+            ));
+        }
+    };
+
+    let snapshot: Result<(), Error> = async {
+        let status_response = handlers::get_game_status(repository, game_id).await?;
+        send(Topic::GameStatus, to_value(status_response));
+
+        // games without a round yet have nothing more to send
+        let (round_status, players) =
+            match handlers::get_round_status_for_game(repository, game_id).await {
+                Err(Error::NotFound(_)) => return Ok(()),
+                res => res?,
+            };
+        let picks_chosen = claim.role == Role::Player
+            && players
+                .iter()
+                .any(|(user_id, picked)| *user_id == claim.id && *picked);
+        let round_status = RoundStatusRepsonse {
+            picks_chosen,
+            ..round_status
+        };
+        send(Topic::RoundStatus, to_value(round_status));
+
+        let round_picks = handlers::get_round_picks(repository, game_id).await?;
+        if round_picks.visible_to(&claim.role) {
+            send(Topic::Picks, to_value(round_picks.response));
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(err) = snapshot {
+        error!("{:?}", err);
+    }
+}
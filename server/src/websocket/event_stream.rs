@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use actix::{
+    fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner,
+    Handler, Running, WrapFuture,
+};
+use actix_web::web::Bytes;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use tracing::Span;
+use uuid::Uuid;
+
+use super::{
+    Auth, Close, Connect, Disconnect, MessageToClient, ResumeFrom, Server, SessionRecipient,
+};
+
+/// A Server-Sent Events connection, for clients that can't keep a websocket open. It joins the
+/// `Server` like a `WebSocketSession`, and writes each event it's sent to the response body.
+struct EventStreamSession {
+    id: String,
+    /// Comments are sent this often, so proxies don't close an idle stream, and so a closed
+    /// stream is noticed
+    keep_alive_interval: Duration,
+    resume: Option<ResumeFrom>,
+    sender: UnboundedSender<Result<Bytes, actix_web::Error>>,
+    server_addr: Addr<Server>,
+    token: String,
+    /// Its parent is the span of the request that opened the stream
+    span: Span,
+}
+
+impl EventStreamSession {
+    fn write(&self, ctx: &mut Context<Self>, data: String) {
+        if self.sender.unbounded_send(Ok(Bytes::from(data))).is_err() {
+            // the client went away
+            ctx.stop();
+        }
+    }
+}
+
+/// Reads the `epoch:seq` event id sent back as `Last-Event-ID`. An id without an epoch is still
+/// read, so the client is sent a snapshot rather than nothing.
+pub fn parse_event_id(id: &str) -> Option<ResumeFrom> {
+    let (epoch, seq) = match id.trim().split_once(':') {
+        Some((epoch, seq)) => (Some(epoch.to_string()), seq),
+        None => (None, id.trim()),
+    };
+
+    seq.parse().ok().map(|seq| ResumeFrom { epoch, seq })
+}
+
+/// Formats a message the way `EventSource` expects. The message's epoch and seq are its event id,
+/// which the browser sends back as `Last-Event-ID` when it reconnects.
+fn format_event(msg: &MessageToClient) -> serde_json::Result<String> {
+    let data = serde_json::to_string(msg)?;
+    let path = serde_json::to_value(msg.path)?;
+    let path = path.as_str().unwrap_or_default();
+    // ephemeral messages have no seq, and leave the last event id alone
+    if msg.seq == 0 {
+        Ok(format!("event: {}\ndata: {}\n\n", path, data))
+    } else {
+        Ok(format!(
+            "id: {}:{}\nevent: {}\ndata: {}\n\n",
+            msg.epoch, msg.seq, path, data
+        ))
+    }
+}
+
+impl Actor for EventStreamSession {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.keep_alive_interval, |act, ctx| {
+            act.write(ctx, ": keep-alive\n\n".to_string());
+        });
+
+        self.server_addr.do_send(Connect {
+            addr: SessionRecipient::EventStream {
+                events: ctx.address().recipient(),
+                close: ctx.address().recipient(),
+            },
+            id: self.id.clone(),
+        });
+        self.server_addr
+            .send(Auth {
+                id: self.id.clone(),
+                token: self.token.clone(),
+                resume: self.resume.clone(),
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                let _entered = act.span.enter();
+                match res {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(err)) => {
+                        warn!("Event stream failed to authenticate - {:?}", err);
+                        ctx.stop();
+                    }
+                    Err(err) => {
+                        error!("Error sending auth to server: {:?}", err);
+                        ctx.stop();
+                    }
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.server_addr.do_send(Disconnect {
+            id: self.id.clone(),
+        });
+        Running::Stop
+    }
+}
+
+impl Handler<MessageToClient> for EventStreamSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessageToClient, ctx: &mut Self::Context) {
+        match format_event(&msg) {
+            Ok(data) => self.write(ctx, data),
+            Err(err) => error!("Error formatting event - {:?}", err),
+        }
+    }
+}
+
+impl Handler<Close> for EventStreamSession {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) {
+        // ends the response
+        ctx.stop();
+    }
+}
+
+/// Starts a session for the owner of `token`, returning the stream to send as the response body.
+/// Events after `resume` are replayed, same as a reconnecting websocket.
+pub fn start_event_stream(
+    server_addr: Addr<Server>,
+    token: String,
+    resume: Option<ResumeFrom>,
+    keep_alive_interval: Duration,
+) -> UnboundedReceiver<Result<Bytes, actix_web::Error>> {
+    let (sender, receiver) = unbounded();
+    // tells the client how long to wait before reconnecting
+    let _ = sender.unbounded_send(Ok(Bytes::from_static(b"retry: 3000\n\n")));
+
+    let id = Uuid::new_v4().to_string();
+    EventStreamSession {
+        span: info_span!("event_stream", session_id = %id),
+        id,
+        keep_alive_interval,
+        resume,
+        sender,
+        server_addr,
+        token,
+    }
+    .start();
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{format_event, parse_event_id};
+    use crate::websocket::{MessageToClient, ResumeFrom, Topic};
+
+    #[test]
+    fn test_format_event() {
+        let mut msg = MessageToClient::new(Topic::Players, 1, json!([]));
+        msg.seq = 4;
+        msg.epoch = "abc".to_string();
+        assert_eq!(
+            format_event(&msg).unwrap(),
+            "id: abc:4\nevent: /players\ndata: {\"path\":\"/players\",\"data\":[],\"game_id\":1,\"seq\":4,\"epoch\":\"abc\"}\n\n"
+        );
+
+        let msg = MessageToClient::new(Topic::Reactions, 1, json!({}));
+        assert!(format_event(&msg)
+            .unwrap()
+            .starts_with("event: /reactions\n"));
+    }
+
+    #[test]
+    fn test_parse_event_id() {
+        assert_eq!(
+            parse_event_id("abc:4"),
+            Some(ResumeFrom {
+                epoch: Some("abc".to_string()),
+                seq: 4
+            })
+        );
+        assert_eq!(
+            parse_event_id("4"),
+            Some(ResumeFrom {
+                epoch: None,
+                seq: 4
+            })
+        );
+        assert_eq!(parse_event_id("abc:"), None);
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::sync::Arc;
+
+use actix::dev::Request;
+use actix::prelude::{Actor, ActorContext, Addr, Context, Handler, Message as ActixMessage};
+use serde::{Deserialize, Serialize};
+use serde_json::{error::Result as SerdeResult, to_string};
+use tracing::{Instrument, Span};
+use uuid::Uuid;
+
+use auth::{PrivateClaim, Role};
+use db::repository::Repository;
+
+use super::{
+    client_messages, Broadcast, Deliver, Message, MessageToClient, Server, ServerMessage,
+    SessionRecipient, SetPresence, Subscribe, Target, TargetedMessageToClient,
+    TargetedMessagesToClient, Topic,
+};
+
+/// How many events per game are kept around to replay to reconnecting clients. An event sent with
+/// a different message for each player still only takes one.
+const REPLAY_BUFFER_SIZE: usize = 100;
+
+struct Session {
+    addr: SessionRecipient,
+    claim: PrivateClaim,
+    // all topics are sent until the client subscribes to specific ones
+    topics: Option<HashSet<Topic>>,
+}
+
+impl Session {
+    fn is_recipient(&self, session_id: &str, target: &Target, path: &Topic) -> bool {
+        let subscribed = self
+            .topics
+            .as_ref()
+            .map(|topics| topics.contains(path))
+            .unwrap_or(true);
+
+        subscribed && target.matches(session_id, &self.claim)
+    }
+
+    /// `frame` is the message serialized for websockets
+    fn send(&self, msg: &MessageToClient, frame: &str) {
+        let result = match &self.addr {
+            SessionRecipient::Websocket { events, .. } => events
+                .try_send(Message(frame.to_string()))
+                .map_err(|err| format!("{:?}", err)),
+            SessionRecipient::EventStream { events, .. } => events
+                .try_send(msg.clone())
+                .map_err(|err| format!("{:?}", err)),
+        };
+        if let Err(err) = result {
+            error!("Error sending client message: {}", err);
+        }
+    }
+}
+
+/// Sent on `/presence` when a player's first session joins or their last one goes away
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct PresenceUpdate {
+    pub user_id: i32,
+    pub online: bool,
+}
+
+/// Where a reconnecting client left off: the `epoch` and `seq` of the last event it saw
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResumeFrom {
+    /// None for clients that only kept the seq, which can't be trusted to be from this epoch
+    pub epoch: Option<String>,
+    pub seq: u64,
+}
+
+/// The most recent events sent to a game, so they can be replayed to clients that reconnect
+struct GameHistory {
+    /// Random, so seqs from before the game's actor restarted, or from another instance, are
+    /// never mistaken for this one's
+    epoch: String,
+    last_seq: u64,
+    // each event's messages share its seq, and sessions are only sent those they're a target of
+    events: VecDeque<Vec<(Target, MessageToClient)>>,
+}
+
+impl GameHistory {
+    fn new() -> Self {
+        GameHistory {
+            epoch: Uuid::new_v4().simple().to_string(),
+            last_seq: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Gives the event's messages the next seq and keeps them, returning them to be sent
+    fn push(&mut self, messages: Vec<(Target, MessageToClient)>) -> Vec<(Target, MessageToClient)> {
+        self.last_seq += 1;
+        let messages: Vec<(Target, MessageToClient)> = messages
+            .into_iter()
+            .map(|(target, mut msg)| {
+                msg.seq = self.last_seq;
+                msg.epoch = self.epoch.clone();
+                (target, msg)
+            })
+            .collect();
+
+        if self.events.len() == REPLAY_BUFFER_SIZE {
+            self.events.pop_front();
+        }
+        self.events.push_back(messages.clone());
+
+        messages
+    }
+
+    /// Messages sent after `from`, or None if some have already fallen out of the buffer, or
+    /// `from` is from another epoch
+    fn since(&self, from: &ResumeFrom) -> Option<impl Iterator<Item = &(Target, MessageToClient)>> {
+        if from.epoch.as_deref() != Some(self.epoch.as_str()) {
+            return None;
+        }
+        let seq = from.seq;
+        let oldest_seq = self.last_seq - self.events.len() as u64;
+        if seq < oldest_seq || seq > self.last_seq {
+            return None;
+        }
+
+        Some(
+            self.events
+                .iter()
+                .flatten()
+                .filter(move |(_, msg)| msg.seq > seq),
+        )
+    }
+}
+
+/// Owns the sessions of a single game. Started by the `Server` when someone authenticates for the
+/// game, on one of its arbiters, so a busy game doesn't hold up messages for the others.
+pub struct GameServer {
+    game_id: i32,
+    history: GameHistory,
+    // user id -> number of sessions the player has open on this instance. Whether they're online
+    // on any instance is up to the `Server`.
+    presence: HashMap<i32, usize>,
+    repository: Arc<dyn Repository>,
+    server: Addr<Server>,
+    sessions: HashMap<String, Session>,
+    /// Work the actor spawns runs in this, so it's logged with the game's id
+    span: Span,
+}
+
+impl GameServer {
+    pub fn new(game_id: i32, repository: Arc<dyn Repository>, server: Addr<Server>) -> Self {
+        GameServer {
+            game_id,
+            history: GameHistory::new(),
+            presence: HashMap::new(),
+            repository,
+            server,
+            sessions: HashMap::new(),
+            span: info_span!(parent: None, "game", game_id),
+        }
+    }
+
+    /// Tells the `Server` the player came online or went offline on this instance. It's sent
+    /// right away, so the `Server` sees each player's changes in order.
+    fn set_presence(&self, user_id: i32, online: bool) -> Request<Server, SetPresence> {
+        self.server.send(SetPresence {
+            game_id: self.game_id,
+            user_id,
+            online,
+        })
+    }
+
+    /// Returns true if this is the player's first session on this instance
+    fn player_joined(&mut self, user_id: i32) -> bool {
+        let count = self.presence.entry(user_id).or_insert(0);
+        *count += 1;
+
+        *count == 1
+    }
+
+    fn player_left(&mut self, user_id: i32) {
+        let went_offline = match self.presence.get_mut(&user_id) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                self.presence.remove(&user_id);
+                true
+            }
+            None => false,
+        };
+
+        if went_offline {
+            let request = self.set_presence(user_id, false);
+            let server = self.server.clone();
+            let game_id = self.game_id;
+            actix::spawn(
+                async move {
+                    if let Ok(Some(update)) = request.await {
+                        client_messages::send_presence(&server, game_id, update);
+                    }
+                }
+                .instrument(self.span.clone()),
+            );
+        }
+    }
+
+    /// Sends each of an event's messages to the sessions it targets
+    fn send_msg_to_game_sessions(&mut self, messages: Vec<(Target, MessageToClient)>) {
+        let ephemeral = messages.iter().all(|(_, msg)| msg.path.is_ephemeral());
+        let messages = if ephemeral {
+            messages
+        } else {
+            self.history.push(messages)
+        };
+
+        for (target, msg) in &messages {
+            let frame: SerdeResult<String> = to_string(&ServerMessage::Event(msg.clone()));
+            if let Ok(frame) = frame {
+                for (id, session) in &self.sessions {
+                    if session.is_recipient(id, target, &msg.path) {
+                        session.send(msg, &frame);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes the player's sessions from the game and closes them, so a kicked player stops
+    /// getting its messages, and goes offline
+    fn kick(&mut self, user_id: i32) {
+        let target = Target::User(user_id);
+        let kicked: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(id, session)| target.matches(id, &session.claim))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in kicked {
+            if let Some(session) = self.sessions.remove(&id) {
+                session.addr.close();
+                self.player_left(user_id);
+            }
+        }
+    }
+
+    /// Sends a reconnecting session what it missed since `from`. If that's no longer in the
+    /// buffer, the current state of the game is sent instead.
+    fn replay(&self, session_id: &str, from: &ResumeFrom) {
+        let session = match self.sessions.get(session_id) {
+            Some(session) => session,
+            None => return,
+        };
+
+        match self.history.since(from) {
+            Some(missed) => {
+                for (target, msg) in missed {
+                    if !session.is_recipient(session_id, target, &msg.path) {
+                        continue;
+                    }
+                    if let Ok(frame) = to_string(&ServerMessage::Event(msg.clone())) {
+                        session.send(msg, &frame);
+                    }
+                }
+            }
+            None => {
+                info!("Sending snapshot to session {}", session_id);
+                let server = self.server.clone();
+                let repository = self.repository.clone();
+                let claim = session.claim.clone();
+                let session_id = session_id.to_string();
+                actix::spawn(
+                    async move {
+                        client_messages::send_snapshot(
+                            &server,
+                            repository.as_ref(),
+                            &claim,
+                            session_id,
+                        )
+                        .await;
+                    }
+                    .instrument(self.span.clone()),
+                );
+            }
+        }
+    }
+
+    /// Sends everyone the game's players, then whether the player that joined came online, if
+    /// they weren't already on another instance. The query runs in a spawned future, rather than
+    /// holding up this actor.
+    fn send_players(&self, joined: Option<Request<Server, SetPresence>>) {
+        let game_id = self.game_id;
+        let repository = self.repository.clone();
+        let server = self.server.clone();
+
+        actix::spawn(
+            async move {
+                let presence = match joined {
+                    Some(request) => request.await.unwrap_or_default(),
+                    None => None,
+                };
+                client_messages::send_players(&server, repository.as_ref(), game_id).await;
+
+                if let Some(update) = presence {
+                    client_messages::send_presence(&server, game_id, update);
+                }
+            }
+            .instrument(self.span.clone()),
+        );
+    }
+}
+
+impl Actor for GameServer {
+    type Context = Context<Self>;
+}
+
+/// Adds an authenticated session to its game
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Join {
+    pub id: String,
+    pub addr: SessionRecipient,
+    pub claim: PrivateClaim,
+    pub topics: Option<HashSet<Topic>>,
+    pub resume: Option<ResumeFrom>,
+}
+
+impl Handler<Join> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
+        // already authenticated
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.claim = msg.claim;
+            return;
+        }
+
+        let is_player = msg.claim.role == Role::Player;
+        let user_id = msg.claim.id;
+        self.sessions.insert(
+            msg.id.clone(),
+            Session {
+                addr: msg.addr,
+                claim: msg.claim,
+                topics: msg.topics,
+            },
+        );
+
+        if let Some(from) = msg.resume {
+            self.replay(&msg.id, &from);
+        }
+
+        let joined = if is_player && self.player_joined(user_id) {
+            Some(self.set_presence(user_id, true))
+        } else {
+            None
+        };
+        self.send_players(joined);
+    }
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Leave {
+    pub id: String,
+}
+
+impl Handler<Leave> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _: &mut Context<Self>) {
+        if let Some(session) = self.sessions.remove(&msg.id) {
+            if session.claim.role == Role::Player {
+                self.player_left(session.claim.id);
+            }
+        }
+    }
+}
+
+/// Sent by the `Server` once a game has had no sessions for a while
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct StopGame;
+
+impl Handler<StopGame> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, _: StopGame, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
+
+impl Handler<Subscribe> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) {
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.topics = Some(msg.topics.into_iter().collect());
+        }
+    }
+}
+
+impl Handler<Deliver> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Deliver, _: &mut Context<Self>) {
+        match msg.0 {
+            Broadcast::Message(TargetedMessageToClient {
+                target,
+                mut message,
+            }) => {
+                // the message is kept to be replayed, which would keep its span open until it's
+                // replaced
+                let span = mem::replace(&mut message.span, Span::none());
+                let _entered = span.enter();
+                self.send_msg_to_game_sessions(vec![(target, message)]);
+            }
+            Broadcast::Messages(TargetedMessagesToClient { messages, .. }) => {
+                // they're all sent from the same span
+                let span = messages
+                    .first()
+                    .map(|msg| msg.message.span.clone())
+                    .unwrap_or_else(Span::none);
+                let _entered = span.enter();
+                let messages = messages
+                    .into_iter()
+                    .map(|mut msg| {
+                        msg.message.span = Span::none();
+                        (msg.target, msg.message)
+                    })
+                    .collect();
+                self.send_msg_to_game_sessions(messages);
+            }
+            Broadcast::Kick { user_id, .. } => self.kick(user_id),
+            // handled by the `Server`
+            Broadcast::Presence { .. } | Broadcast::PresenceSnapshot { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GameHistory, ResumeFrom, REPLAY_BUFFER_SIZE};
+    use crate::websocket::{MessageToClient, Target, Topic};
+
+    fn players() -> Vec<(Target, MessageToClient)> {
+        vec![(
+            Target::Game,
+            MessageToClient::new(Topic::Players, 1, serde_json::Value::Null),
+        )]
+    }
+
+    fn from(history: &GameHistory, seq: u64) -> ResumeFrom {
+        ResumeFrom {
+            epoch: Some(history.epoch.clone()),
+            seq,
+        }
+    }
+
+    #[test]
+    fn test_game_history_replays_until_the_buffer_wraps() {
+        let mut history = GameHistory::new();
+        assert_eq!(history.since(&from(&history, 0)).unwrap().count(), 0);
+
+        for _ in 0..3 {
+            history.push(players());
+        }
+        let seqs: Vec<u64> = history
+            .since(&from(&history, 1))
+            .unwrap()
+            .map(|(_, msg)| msg.seq)
+            .collect();
+        assert_eq!(seqs, vec![2, 3]);
+        // a seq from the future means the server restarted since the client last connected
+        assert!(history.since(&from(&history, 4)).is_none());
+
+        for _ in 0..REPLAY_BUFFER_SIZE {
+            history.push(players());
+        }
+        // message 2 fell out of the buffer, so 1 can't be caught up anymore
+        assert!(history.since(&from(&history, 1)).is_none());
+        assert_eq!(
+            history.since(&from(&history, 3)).unwrap().count(),
+            REPLAY_BUFFER_SIZE
+        );
+    }
+
+    #[test]
+    fn test_game_history_only_replays_its_own_epoch() {
+        let mut history = GameHistory::new();
+        for _ in 0..3 {
+            let messages = history.push(players());
+            assert_eq!(messages[0].1.epoch, history.epoch);
+        }
+        assert!(history.since(&from(&history, 1)).is_some());
+
+        // the same seqs, counted by the game's actor before it restarted
+        let restarted = GameHistory::new();
+        assert_ne!(restarted.epoch, history.epoch);
+        assert!(history.since(&from(&restarted, 1)).is_none());
+        assert!(history
+            .since(&ResumeFrom {
+                epoch: None,
+                seq: 1
+            })
+            .is_none());
+    }
+
+    #[test]
+    fn test_game_history_keeps_an_event_for_many_players_in_one_slot() {
+        let mut history = GameHistory::new();
+        let round_status: Vec<(Target, MessageToClient)> = (1..=250)
+            .map(|user_id| {
+                (
+                    Target::User(user_id),
+                    MessageToClient::new(Topic::RoundStatus, 1, serde_json::Value::Null),
+                )
+            })
+            .collect();
+
+        let sent = history.push(round_status);
+        assert!(sent.iter().all(|(_, msg)| msg.seq == 1));
+        for _ in 1..REPLAY_BUFFER_SIZE {
+            history.push(players());
+        }
+
+        // the round status is still in the buffer, for every one of its players
+        let missed: Vec<&(Target, MessageToClient)> =
+            history.since(&from(&history, 0)).unwrap().collect();
+        assert_eq!(missed.len(), 250 + REPLAY_BUFFER_SIZE - 1);
+        assert!(missed
+            .iter()
+            .any(|(target, msg)| *target == Target::User(250) && msg.seq == 1));
+
+        history.push(players());
+        assert!(history.since(&from(&history, 0)).is_none());
+        assert!(history.since(&from(&history, 1)).is_some());
+    }
+}
@@ -1,51 +1,296 @@
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::Instant;
 
 use actix::{
     fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner,
-    Handler, StreamHandler, WrapFuture,
+    Handler, Running, StreamHandler, WrapFuture,
 };
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use serde::Deserialize;
 use serde_json;
+use tracing::{field, Instrument, Span};
 use uuid::Uuid;
 
+use auth::{PrivateClaim, Role};
+use db::repository::Repository;
 use errors::Error;
 
+use crate::config::WebsocketConfig;
+use crate::handlers::{self, Answer};
+use crate::middleware::record_claim;
+
+mod broadcast;
 pub mod client_messages;
+mod event_stream;
+mod game_server;
+#[cfg(feature = "postgres_broadcast")]
+mod postgres_backend;
+mod presence;
+mod protocol;
 mod server;
 
+pub use self::broadcast::*;
+pub use self::event_stream::*;
+pub use self::game_server::*;
+#[cfg(feature = "postgres_broadcast")]
+pub use self::postgres_backend::*;
+pub use self::presence::*;
+pub use self::protocol::*;
 pub use self::server::*;
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
-
 #[derive(Deserialize)]
 struct AuthReq {
     token: String,
+    #[serde(default)]
+    last_seq: Option<u64>,
+    #[serde(default)]
+    epoch: Option<String>,
 }
 
 pub struct WebSocketSession {
     id: String,
     hb: Instant,
     server_addr: Addr<Server>,
+    repository: Arc<dyn Repository>,
+    claim: Option<PrivateClaim>,
+    last_reaction: Option<Instant>,
+    config: WebsocketConfig,
+    /// Everything the session handles is logged in this, which gets the game_id and user_id once
+    /// it authenticates. Its parent is the span of the request that opened the websocket.
+    span: Span,
 }
 
 impl WebSocketSession {
-    fn new(server_addr: Addr<Server>) -> Self {
+    fn new(
+        server_addr: Addr<Server>,
+        repository: Arc<dyn Repository>,
+        config: WebsocketConfig,
+    ) -> Self {
+        let id = Uuid::new_v4().to_string();
+        let span = info_span!(
+            "websocket",
+            session_id = %id,
+            game_id = field::Empty,
+            user_id = field::Empty,
+        );
+
         Self {
-            id: Uuid::new_v4().to_string(),
+            id,
             hb: Instant::now(),
             server_addr,
+            repository,
+            claim: None,
+            last_reaction: None,
+            config,
+            span,
+        }
+    }
+
+    fn send_server_message(&self, ctx: &mut <Self as Actor>::Context, msg: &ServerMessage) {
+        match serde_json::to_string(msg) {
+            Ok(text) => ctx.text(text),
+            Err(err) => error!("Error serializing server message: {:?}", err),
+        }
+    }
+
+    fn send_error(&self, ctx: &mut <Self as Actor>::Context, error: ErrorFrame) {
+        self.send_server_message(ctx, &ServerMessage::Error(error));
+    }
+
+    /// `reply` is false for the legacy `/auth` command, whose clients don't expect an ack
+    fn authenticate(
+        &self,
+        ctx: &mut <Self as Actor>::Context,
+        id: Option<u64>,
+        token: String,
+        resume: Option<ResumeFrom>,
+        reply: bool,
+    ) {
+        self.server_addr
+            .send(Auth {
+                id: self.id.clone(),
+                token,
+                resume,
+            })
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                let _entered = act.span.clone().entered();
+                match res {
+                    Ok(Ok(claim)) => {
+                        record_claim(&act.span, &claim);
+                        act.claim = Some(claim);
+                        if reply {
+                            act.send_server_message(ctx, &ServerMessage::Ack { id });
+                        }
+                    }
+                    Ok(Err(err)) => act.send_error(ctx, ErrorFrame::from_error(id, &err)),
+                    Err(err) => {
+                        error!("Error sending auth to server: {:?}", err);
+                        act.send_error(
+                            ctx,
+                            ErrorFrame::new(id, ErrorCode::InternalError, "Internal Server Error"),
+                        );
+                    }
+                }
+                fut::ready(())
+            })
+            .spawn(ctx);
+    }
+
+    /// Validates and saves the player's picks the same way the http route does, then sends the
+    /// updated picks to whoever the game's picks visibility allows. `replace` overwrites picks
+    /// already made for the round.
+    fn save_picks(
+        &self,
+        ctx: &mut <Self as Actor>::Context,
+        id: Option<u64>,
+        answers: Vec<Answer>,
+        replace: bool,
+    ) {
+        let claim = match &self.claim {
+            Some(claim) if claim.role == Role::Player => claim.clone(),
+            Some(_) => return self.send_error(ctx, ErrorFrame::from_error(id, &Error::Forbidden)),
+            None => return self.send_error(ctx, ErrorFrame::from_error(id, &Error::Unauthorized)),
+        };
+
+        let repository = self.repository.clone();
+        let server_addr = self.server_addr.clone();
+        async move {
+            let game_id = claim.game_id;
+            if replace {
+                handlers::update_picks(repository.as_ref(), claim, answers).await?;
+            } else {
+                handlers::save_picks(repository.as_ref(), claim, answers).await?;
+            }
+
+            client_messages::send_round_picks(&server_addr, repository.as_ref(), game_id).await;
+
+            Ok(())
+        }
+        .instrument(self.span.clone())
+        .into_actor(self)
+        .then(move |res: Result<(), Error>, act, ctx| {
+            match res {
+                Ok(_) => act.send_server_message(ctx, &ServerMessage::Ack { id }),
+                Err(err) => act.send_error(ctx, ErrorFrame::from_error(id, &err)),
+            }
+            fut::ready(())
+        })
+        .spawn(ctx);
+    }
+
+    /// Saves a chat message from the host or a player, and sends it to everyone in the game
+    fn send_chat(&self, ctx: &mut <Self as Actor>::Context, id: Option<u64>, body: String) {
+        let claim = match &self.claim {
+            Some(claim) => claim.clone(),
+            None => return self.send_error(ctx, ErrorFrame::from_error(id, &Error::Unauthorized)),
+        };
+
+        let repository = self.repository.clone();
+        let server_addr = self.server_addr.clone();
+        async move {
+            let game_id = claim.game_id;
+            let message = handlers::send_chat_message(repository.as_ref(), claim, body).await?;
+            let value = serde_json::to_value(message)
+                .map_err(|_| Error::InternalServerError("Error serializing message".to_string()))?;
+            server_addr.do_send(MessageToClient::new(Topic::Chat, game_id, value));
+
+            Ok(())
+        }
+        .instrument(self.span.clone())
+        .into_actor(self)
+        .then(move |res: Result<(), Error>, act, ctx| {
+            match res {
+                Ok(_) => act.send_server_message(ctx, &ServerMessage::Ack { id }),
+                Err(err) => act.send_error(ctx, ErrorFrame::from_error(id, &err)),
+            }
+            fut::ready(())
+        })
+        .spawn(ctx);
+    }
+
+    fn react(&mut self, ctx: &mut <Self as Actor>::Context, id: Option<u64>, emote: Emote) {
+        let game_id = match &self.claim {
+            Some(claim) => claim.game_id,
+            None => return self.send_error(ctx, ErrorFrame::from_error(id, &Error::Unauthorized)),
+        };
+
+        let now = Instant::now();
+        if let Some(last_reaction) = self.last_reaction {
+            if now.duration_since(last_reaction) < self.config.reaction_throttle {
+                return self.send_error(
+                    ctx,
+                    ErrorFrame::new(id, ErrorCode::RateLimited, "Reacting too quickly"),
+                );
+            }
+        }
+        self.last_reaction = Some(now);
+
+        self.server_addr.do_send(React { game_id, emote });
+        self.send_server_message(ctx, &ServerMessage::Ack { id });
+    }
+
+    fn handle_client_frame(&mut self, frame: ClientFrame, ctx: &mut <Self as Actor>::Context) {
+        let id = frame.id;
+        match frame.message {
+            ClientMessage::Hello { version } => {
+                if version == PROTOCOL_VERSION {
+                    self.send_server_message(
+                        ctx,
+                        &ServerMessage::Hello {
+                            version: PROTOCOL_VERSION,
+                        },
+                    );
+                } else {
+                    self.send_error(
+                        ctx,
+                        ErrorFrame::new(
+                            id,
+                            ErrorCode::UnsupportedVersion,
+                            &format!("Server supports protocol version {}", PROTOCOL_VERSION),
+                        ),
+                    );
+                    ctx.close(Some(ws::CloseCode::Protocol.into()));
+                    ctx.stop();
+                }
+            }
+            ClientMessage::Auth {
+                token,
+                last_seq,
+                epoch,
+            } => {
+                let resume = last_seq.map(|seq| ResumeFrom { epoch, seq });
+                self.authenticate(ctx, id, token, resume, true)
+            }
+            ClientMessage::Subscribe { topics } => {
+                self.server_addr.do_send(Subscribe {
+                    id: self.id.clone(),
+                    topics,
+                });
+                self.send_server_message(ctx, &ServerMessage::Ack { id });
+            }
+            ClientMessage::SubmitPicks { answers } => self.save_picks(ctx, id, answers, false),
+            ClientMessage::UpdatePicks { answers } => self.save_picks(ctx, id, answers, true),
+            ClientMessage::SendChat { body } => self.send_chat(ctx, id, body),
+            ClientMessage::React { emote } => self.react(ctx, id, emote),
+            ClientMessage::Ping => {
+                self.hb = Instant::now();
+                self.send_server_message(ctx, &ServerMessage::Pong { id });
+            }
+            ClientMessage::Ack { seq } => {
+                // replay is driven by the last_seq sent on auth, so acks are only logged
+                debug!("Session {} acknowledged up to {}", self.id, seq);
+            }
         }
     }
 
     fn send_heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+        ctx.run_interval(self.config.heartbeat_interval, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.config.client_timeout {
+                let _entered = act.span.enter();
                 info!("Websocket Client heartbeat failed, disconnecting!");
-                act.server_addr.do_send(Disconnect { id: act.id.clone() });
-                // stop actor
+                // stop actor, which disconnects it from the server
                 ctx.stop();
 
                 // don't try to send a ping
@@ -65,7 +310,10 @@ impl Actor for WebSocketSession {
         let session_addr = ctx.address();
         self.server_addr
             .send(Connect {
-                addr: session_addr.recipient(),
+                addr: SessionRecipient::Websocket {
+                    events: session_addr.clone().recipient(),
+                    close: session_addr.recipient(),
+                },
                 id: self.id.clone(),
             })
             .into_actor(self)
@@ -78,6 +326,14 @@ impl Actor for WebSocketSession {
             })
             .wait(ctx);
     }
+
+    /// Runs however the session ends: a close frame, a heartbeat timeout, or a dropped connection
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.server_addr.do_send(Disconnect {
+            id: self.id.clone(),
+        });
+        Running::Stop
+    }
 }
 
 impl Handler<Message> for WebSocketSession {
@@ -88,8 +344,22 @@ impl Handler<Message> for WebSocketSession {
     }
 }
 
+impl Handler<Close> for WebSocketSession {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some("Removed from the game".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let span = self.span.clone();
+        let _entered = span.enter();
         match msg {
             Ok(ws::Message::Ping(msg)) => {
                 self.hb = Instant::now();
@@ -100,32 +370,62 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
             }
             Ok(ws::Message::Text(text)) => {
                 let message = text.trim();
-                // has routing pattern
-                if message.starts_with("/") {
+                // legacy routing pattern, from before messages were json
+                if message.starts_with('/') {
                     let args: Vec<&str> = message.splitn(2, ' ').collect();
                     match args[0] {
                         "/auth" => {
                             let params: Result<AuthReq, serde_json::Error> =
-                                serde_json::from_str(args[1]);
+                                serde_json::from_str(args.get(1).unwrap_or(&""));
                             if let Ok(params) = params {
-                                self.server_addr.do_send(Auth {
-                                    id: self.id.clone(),
-                                    token: params.token,
-                                });
+                                let AuthReq {
+                                    token,
+                                    last_seq,
+                                    epoch,
+                                } = params;
+                                let resume = last_seq.map(|seq| ResumeFrom { epoch, seq });
+                                self.authenticate(ctx, None, token, resume, false);
                             } else {
-                                ctx.text("Invalid request params");
+                                self.send_error(
+                                    ctx,
+                                    ErrorFrame::new(
+                                        None,
+                                        ErrorCode::InvalidMessage,
+                                        "Invalid request params",
+                                    ),
+                                );
                             }
                         }
-                        _ => ctx.text(format!("unknown command {:?}", message)),
+                        _ => self.send_error(
+                            ctx,
+                            ErrorFrame::new(
+                                None,
+                                ErrorCode::InvalidMessage,
+                                &format!("unknown command {:?}", message),
+                            ),
+                        ),
                     }
+                    return;
+                }
+
+                match serde_json::from_str::<ClientFrame>(message) {
+                    Ok(frame) => self.handle_client_frame(frame, ctx),
+                    Err(err) => self.send_error(
+                        ctx,
+                        ErrorFrame::new(None, ErrorCode::InvalidMessage, &err.to_string()),
+                    ),
                 }
             }
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
+            Ok(ws::Message::Binary(_)) => self.send_error(
+                ctx,
+                ErrorFrame::new(
+                    None,
+                    ErrorCode::InvalidMessage,
+                    "Binary frames are not supported",
+                ),
+            ),
             Ok(ws::Message::Close(reason)) => {
                 info!("closed ws session");
-                self.server_addr.do_send(Disconnect {
-                    id: self.id.clone(),
-                });
                 ctx.close(reason);
                 ctx.stop();
             }
@@ -142,12 +442,739 @@ pub async fn ws_index(
     req: HttpRequest,
     stream: web::Payload,
     server_addr: web::Data<Addr<Server>>,
+    repository: web::Data<dyn Repository>,
+    config: web::Data<WebsocketConfig>,
 ) -> Result<HttpResponse, Error> {
-    let res = ws::start(
-        WebSocketSession::new(server_addr.get_ref().clone()),
+    let res = ws::WsResponseBuilder::new(
+        WebSocketSession::new(
+            server_addr.get_ref().clone(),
+            repository.into_inner(),
+            config.get_ref().clone(),
+        ),
         &req,
         stream,
-    )?;
+    )
+    .frame_size(config.max_frame_size)
+    .start()?;
 
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web_actors::ws;
+    use awc::Client;
+    use diesel::{self, ExpressionMethods};
+    use diesel_async::RunQueryDsl;
+    use futures::SinkExt;
+    use serde_json::{self, json};
+
+    use auth::{PrivateClaim, Role};
+    use db::{
+        get_conn,
+        models::{
+            ChatMessageDetails, Game, GameQuestion, NewGameQuestion, NewRound, NewUser, Question,
+            Round, User, UserQuestion,
+        },
+        new_pool,
+        schema::{
+            chat_messages, game_questions, games, questions as questions_dsl, rounds,
+            user_questions, users,
+        },
+        DbConnection,
+    };
+
+    use super::{Emote, ErrorCode, ReactionBurst, ServerMessage, Topic, PROTOCOL_VERSION};
+    use crate::handlers::GetRoundPicksResponse;
+    use crate::tests::helpers::tests::{
+        get_auth_token, get_test_server, insert_each, next_reply, next_server_message,
+        read_until_path,
+    };
+
+    #[derive(Insertable)]
+    #[diesel(table_name = games)]
+    struct NewGame {
+        slug: String,
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_hello_handshake() {
+        let srv = get_test_server();
+
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+
+        ws_conn
+            .send(ws::Message::Text(
+                format!("{{\"type\":\"hello\",\"version\":{}}}", PROTOCOL_VERSION).into(),
+            ))
+            .await
+            .unwrap();
+
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Hello { version } => assert_eq!(version, PROTOCOL_VERSION),
+            msg => panic!("Expected hello, received {:?}", msg),
+        }
+
+        ws_conn
+            .send(ws::Message::Text(
+                "{\"type\":\"hello\",\"version\":0}".into(),
+            ))
+            .await
+            .unwrap();
+
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Error(err) => assert_eq!(err.code, ErrorCode::UnsupportedVersion),
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_invalid_messages_return_error_frames() {
+        let srv = get_test_server();
+
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+
+        ws_conn
+            .send(ws::Message::Text("{\"type\":\"dance\",\"id\":4}".into()))
+            .await
+            .unwrap();
+
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Error(err) => assert_eq!(err.code, ErrorCode::InvalidMessage),
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        ws_conn
+            .send(ws::Message::Binary("abc".into()))
+            .await
+            .unwrap();
+
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Error(err) => assert_eq!(err.code, ErrorCode::InvalidMessage),
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        ws_conn
+            .send(ws::Message::Text(
+                "{\"type\":\"auth\",\"token\":\"not a token\",\"id\":5}".into(),
+            ))
+            .await
+            .unwrap();
+
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Error(err) => {
+                assert_eq!(err.code, ErrorCode::Unauthorized);
+                assert_eq!(err.id, Some(5));
+            }
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        drop(ws_conn);
+        srv.stop().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_auth_acks_and_respects_subscriptions() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+
+        ws_conn
+            .send(ws::Message::Text(
+                "{\"type\":\"subscribe\",\"topics\":[\"/game-status\"],\"id\":1}".into(),
+            ))
+            .await
+            .unwrap();
+
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Ack { id } => assert_eq!(id, Some(1)),
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        ws_conn
+            .send(ws::Message::Text(
+                format!("{{\"type\":\"auth\",\"token\":\"{}\",\"id\":2}}", token).into(),
+            ))
+            .await
+            .unwrap();
+
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Ack { id } => assert_eq!(id, Some(2)),
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        ws_conn
+            .send(ws::Message::Text("{\"type\":\"ping\",\"id\":3}".into()))
+            .await
+            .unwrap();
+
+        // the /players broadcast from authenticating isn't subscribed to, so the pong is next
+        match next_server_message(&mut ws_conn).await {
+            ServerMessage::Pong { id } => assert_eq!(id, Some(3)),
+            msg => panic!("Expected pong, received {:?}", msg),
+        }
+
+        drop(ws_conn);
+        srv.stop().await;
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    async fn create_round_with_player(
+        conn: &mut DbConnection,
+    ) -> (Vec<Question>, Game, User, Round) {
+        let questions: Vec<Question> = insert_each!(
+            conn,
+            questions_dsl::table,
+            [
+                questions_dsl::body.eq("One question".to_string()),
+                questions_dsl::body.eq("Second question".to_string()),
+            ]
+        );
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        let _: Vec<GameQuestion> = insert_each!(
+            conn,
+            game_questions::table,
+            questions
+                .iter()
+                .map(|q| NewGameQuestion {
+                    game_id: game.id,
+                    question_id: q.id,
+                })
+                .collect::<Vec<NewGameQuestion>>(),
+        );
+
+        let user: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                user_name: "agmcleod".to_string(),
+                game_id: game.id,
+            })
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        let round: Round = diesel::insert_into(rounds::table)
+            .values(NewRound {
+                player_one: "one".to_string(),
+                player_two: "two".to_string(),
+                game_id: game.id,
+            })
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        (questions, game, user, round)
+    }
+
+    async fn clear_round_data(conn: &mut DbConnection) {
+        diesel::delete(chat_messages::table)
+            .execute(conn)
+            .await
+            .unwrap();
+        diesel::delete(user_questions::table)
+            .execute(conn)
+            .await
+            .unwrap();
+        diesel::delete(rounds::table).execute(conn).await.unwrap();
+        diesel::delete(users::table).execute(conn).await.unwrap();
+        diesel::delete(game_questions::table)
+            .execute(conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table).execute(conn).await.unwrap();
+        diesel::delete(questions_dsl::table)
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_submit_and_update_picks() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let (questions, game, user, round) = create_round_with_player(&mut conn).await;
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let mut owner_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        owner_ws
+            .send(ws::Message::Text(
+                json!({"type": "auth", "token": owner_token})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut owner_ws).await {
+            ServerMessage::Ack { .. } => {}
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        let mut player_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        player_ws
+            .send(ws::Message::Text(
+                json!({"type": "auth", "token": player_token})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Ack { .. } => {}
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        let answers = json!([
+            {"id": questions[0].id, "value": "one"},
+            {"id": questions[1].id, "value": "two"},
+        ]);
+        player_ws
+            .send(ws::Message::Text(
+                json!({"type": "submit_picks", "id": 1, "answers": answers})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Ack { id } => assert_eq!(id, Some(1)),
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        let msg = read_until_path(&mut owner_ws, Topic::Picks).await;
+        let picks: GetRoundPicksResponse = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(picks.data.len(), 2);
+
+        // same validation as the http route
+        player_ws
+            .send(ws::Message::Text(
+                json!({"type": "submit_picks", "id": 2, "answers": answers})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Error(err) => {
+                assert_eq!(err.id, Some(2));
+                assert_eq!(err.code, ErrorCode::BadRequest);
+                assert_eq!(err.message, "User has already chosen picks for this round");
+            }
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        player_ws
+            .send(ws::Message::Text(
+                json!({
+                    "type": "update_picks",
+                    "id": 3,
+                    "answers": [
+                        {"id": questions[0].id, "value": "two"},
+                        {"id": questions[1].id, "value": "two"},
+                    ],
+                })
+                .to_string()
+                .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Ack { id } => assert_eq!(id, Some(3)),
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        let user_questions = UserQuestion::find_by_round_and_user(&mut conn, round.id, user.id)
+            .await
+            .unwrap();
+        assert_eq!(user_questions.len(), 2);
+        assert!(user_questions.iter().all(|uq| uq.answer == "two"));
+
+        drop(owner_ws);
+        drop(player_ws);
+        srv.stop().await;
+        clear_round_data(&mut conn).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_submit_picks_requires_player() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let (questions, game, _, _) = create_round_with_player(&mut conn).await;
+
+        let srv = get_test_server();
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+
+        let submit = json!({
+            "type": "submit_picks",
+            "id": 1,
+            "answers": [{"id": questions[0].id, "value": "one"}],
+        })
+        .to_string();
+
+        ws_conn
+            .send(ws::Message::Text(submit.clone().into()))
+            .await
+            .unwrap();
+        match next_reply(&mut ws_conn).await {
+            ServerMessage::Error(err) => assert_eq!(err.code, ErrorCode::Unauthorized),
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Spectator,
+        ));
+        ws_conn
+            .send(ws::Message::Text(
+                json!({"type": "auth", "token": token}).to_string().into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut ws_conn).await {
+            ServerMessage::Ack { .. } => {}
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        ws_conn
+            .send(ws::Message::Text(submit.into()))
+            .await
+            .unwrap();
+        match next_reply(&mut ws_conn).await {
+            ServerMessage::Error(err) => assert_eq!(err.code, ErrorCode::Forbidden),
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        drop(ws_conn);
+        srv.stop().await;
+        clear_round_data(&mut conn).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_send_chat() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let (_, game, user, _) = create_round_with_player(&mut conn).await;
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let mut owner_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        owner_ws
+            .send(ws::Message::Text(
+                json!({"type": "auth", "token": owner_token})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut owner_ws).await {
+            ServerMessage::Ack { .. } => {}
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        let mut player_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        player_ws
+            .send(ws::Message::Text(
+                json!({"type": "auth", "token": player_token})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Ack { .. } => {}
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        player_ws
+            .send(ws::Message::Text(
+                json!({"type": "send_chat", "id": 1, "body": "  good luck  "})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Ack { id } => assert_eq!(id, Some(1)),
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        let msg = read_until_path(&mut owner_ws, Topic::Chat).await;
+        let message: ChatMessageDetails = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(message.body, "good luck");
+        assert_eq!(message.user_id, Some(user.id));
+        assert_eq!(message.user_name, Some("agmcleod".to_string()));
+
+        player_ws
+            .send(ws::Message::Text(
+                json!({"type": "send_chat", "id": 2, "body": " "})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Error(err) => {
+                assert_eq!(err.id, Some(2));
+                assert_eq!(err.code, ErrorCode::ValidationFailed);
+            }
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        // the fifth message within the window is the last one allowed
+        for id in 3..7 {
+            player_ws
+                .send(ws::Message::Text(
+                    json!({"type": "send_chat", "id": id, "body": "gg"})
+                        .to_string()
+                        .into(),
+                ))
+                .await
+                .unwrap();
+            match next_reply(&mut player_ws).await {
+                ServerMessage::Ack { id: reply_id } => assert_eq!(reply_id, Some(id)),
+                msg => panic!("Expected ack, received {:?}", msg),
+            }
+        }
+        player_ws
+            .send(ws::Message::Text(
+                json!({"type": "send_chat", "id": 7, "body": "gg"})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut player_ws).await {
+            ServerMessage::Error(err) => {
+                assert_eq!(err.code, ErrorCode::ValidationFailed);
+                assert_eq!(
+                    err.message,
+                    "Sending messages too quickly, try again shortly"
+                );
+            }
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        // the host has their own limit
+        owner_ws
+            .send(ws::Message::Text(
+                json!({"type": "send_chat", "id": 1, "body": "Round starts soon"})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut owner_ws).await {
+            ServerMessage::Ack { id } => assert_eq!(id, Some(1)),
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        drop(owner_ws);
+        drop(player_ws);
+        srv.stop().await;
+        clear_round_data(&mut conn).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_spectators_cannot_chat() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let (_, game, _, _) = create_round_with_player(&mut conn).await;
+
+        let srv = get_test_server();
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Spectator,
+        ));
+        ws_conn
+            .send(ws::Message::Text(
+                json!({"type": "auth", "token": token}).to_string().into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut ws_conn).await {
+            ServerMessage::Ack { .. } => {}
+            msg => panic!("Expected ack, received {:?}", msg),
+        }
+
+        ws_conn
+            .send(ws::Message::Text(
+                json!({"type": "send_chat", "body": "hi"})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut ws_conn).await {
+            ServerMessage::Error(err) => assert_eq!(err.code, ErrorCode::Forbidden),
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        drop(ws_conn);
+        srv.stop().await;
+        clear_round_data(&mut conn).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_reactions_are_throttled_and_sent_in_bursts() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let mut sessions = Vec::new();
+        for role in &[Role::Owner, Role::Spectator, Role::Spectator] {
+            let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+            let token = get_auth_token(PrivateClaim::new(
+                game.id,
+                "abc123".to_string(),
+                game.id,
+                role.clone(),
+            ));
+            ws_conn
+                .send(ws::Message::Text(
+                    json!({"type": "auth", "token": token}).to_string().into(),
+                ))
+                .await
+                .unwrap();
+            match next_reply(&mut ws_conn).await {
+                ServerMessage::Ack { .. } => {}
+                msg => panic!("Expected ack, received {:?}", msg),
+            }
+            sessions.push(ws_conn);
+        }
+
+        for (i, emote) in ["gg", "gg", "cheese"].iter().enumerate() {
+            sessions[i]
+                .send(ws::Message::Text(
+                    json!({"type": "react", "id": 1, "emote": emote})
+                        .to_string()
+                        .into(),
+                ))
+                .await
+                .unwrap();
+            match next_reply(&mut sessions[i]).await {
+                ServerMessage::Ack { id } => assert_eq!(id, Some(1)),
+                msg => panic!("Expected ack, received {:?}", msg),
+            }
+        }
+
+        // too soon after the last one
+        sessions[0]
+            .send(ws::Message::Text(
+                json!({"type": "react", "id": 2, "emote": "pog"})
+                    .to_string()
+                    .into(),
+            ))
+            .await
+            .unwrap();
+        match next_reply(&mut sessions[0]).await {
+            ServerMessage::Error(err) => {
+                assert_eq!(err.id, Some(2));
+                assert_eq!(err.code, ErrorCode::RateLimited);
+            }
+            msg => panic!("Expected error, received {:?}", msg),
+        }
+
+        let mut counts = std::collections::BTreeMap::new();
+        while counts.values().sum::<u32>() < 3 {
+            let msg = read_until_path(&mut sessions[1], Topic::Reactions).await;
+            // reactions aren't kept for replay
+            assert_eq!(msg.seq, 0);
+            let burst: ReactionBurst = serde_json::from_value(msg.data).unwrap();
+            for (emote, count) in burst.counts {
+                *counts.entry(emote).or_insert(0) += count;
+            }
+        }
+        assert_eq!(counts.get(&Emote::Gg), Some(&2));
+        assert_eq!(counts.get(&Emote::Cheese), Some(&1));
+        assert_eq!(counts.get(&Emote::Pog), None);
+
+        drop(sessions);
+        srv.stop().await;
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,532 @@
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::Recipient;
+use chrono::Utc;
+use diesel::sql_types::Text;
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::channel::oneshot;
+use futures::StreamExt;
+use postgres::{fallible_iterator::FallibleIterator, Client, NoTls};
+
+use db::{get_conn, schema::websocket_events, DbConnection, DbPool};
+use errors::Error;
+
+use super::{Broadcast, BroadcastBackend, Deliver};
+
+/// Postgres rejects NOTIFY payloads at 8000 bytes
+const MAX_NOTIFY_PAYLOAD: usize = 7999;
+const NOTIFY_CHANNEL: &str = "websocket_events";
+/// Notifications for events stored in `websocket_events` are this, then the event's id
+const STORED_EVENT_PREFIX: &str = "stored:";
+/// How long stored events are kept around for every instance to read
+const STORED_EVENT_LIFETIME: Duration = Duration::from_secs(60);
+const LISTEN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Publishes messages with `NOTIFY`, and has every instance `LISTEN` for them. Messages too large
+/// for a notification are saved to `websocket_events`, and only their id is notified.
+pub struct PostgresBackend {
+    database_url: String,
+    pool: DbPool,
+    publisher: Option<UnboundedSender<String>>,
+}
+
+impl PostgresBackend {
+    pub fn new(database_url: String, pool: DbPool) -> Self {
+        PostgresBackend {
+            database_url,
+            pool,
+            publisher: None,
+        }
+    }
+}
+
+fn listen(database_url: &str) -> Result<Client, postgres::Error> {
+    let mut client = Client::connect(database_url, NoTls)?;
+    client.batch_execute(&format!("LISTEN {}", NOTIFY_CHANNEL))?;
+    Ok(client)
+}
+
+/// Saves an event too large to notify with, returning what to notify instead. Events old enough
+/// that every instance has read them are cleared out at the same time.
+async fn store(connection: &mut DbConnection, payload: String) -> Result<String, Error> {
+    let expired =
+        Utc::now() - chrono::Duration::from_std(STORED_EVENT_LIFETIME).unwrap_or_default();
+    diesel::delete(websocket_events::table)
+        .filter(websocket_events::created_at.lt(expired))
+        .execute(connection)
+        .await?;
+
+    let id: i64 = diesel::insert_into(websocket_events::table)
+        .values(websocket_events::payload.eq(payload))
+        .returning(websocket_events::id)
+        .get_result(connection)
+        .await?;
+
+    Ok(format!("{}{}", STORED_EVENT_PREFIX, id))
+}
+
+/// The event a notification is for, reading it from `websocket_events` if it was stored
+fn read_event(client: &mut Client, payload: &str) -> Result<String, postgres::Error> {
+    let stored_id = payload
+        .strip_prefix(STORED_EVENT_PREFIX)
+        .and_then(|id| id.parse::<i64>().ok());
+    match stored_id {
+        Some(id) => {
+            let row =
+                client.query_one("SELECT payload FROM websocket_events WHERE id = $1", &[&id])?;
+            Ok(row.get(0))
+        }
+        None => Ok(payload.to_string()),
+    }
+}
+
+async fn notify(pool: &DbPool, payload: String) -> Result<(), Error> {
+    let mut connection = get_conn(pool).await?;
+    let payload = if payload.len() > MAX_NOTIFY_PAYLOAD {
+        store(&mut connection, payload).await?
+    } else {
+        payload
+    };
+    diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(NOTIFY_CHANNEL)
+        .bind::<Text, _>(payload)
+        .execute(&mut connection)
+        .await?;
+
+    Ok(())
+}
+
+impl BroadcastBackend for PostgresBackend {
+    fn start(&mut self, server: Recipient<Deliver>) {
+        let database_url = self.database_url.clone();
+        let (ready, mut listening) = oneshot::channel::<()>();
+        let mut ready = Some(ready);
+        // the sync postgres client can't be used from within the actix runtime, so it gets its own
+        // thread for the lifetime of the server
+        thread::spawn(move || loop {
+            match listen(&database_url) {
+                Ok(mut client) => {
+                    if let Some(ready) = ready.take() {
+                        let _ = ready.send(());
+                    }
+                    loop {
+                        let next = client
+                            .notifications()
+                            .timeout_iter(LISTEN_RETRY_INTERVAL)
+                            .next();
+                        match next {
+                            Ok(Some(notification)) => {
+                                let event = match read_event(&mut client, notification.payload()) {
+                                    Ok(event) => event,
+                                    Err(err) => {
+                                        error!("Failed to read stored websocket event - {}", err);
+                                        continue;
+                                    }
+                                };
+                                match serde_json::from_str(&event) {
+                                    Ok(msg) => server.do_send(Deliver(msg)),
+                                    Err(err) => error!("Invalid websocket event - {:?}", err),
+                                }
+                            }
+                            // nothing to deliver to anymore
+                            Ok(None) if !server.connected() => return,
+                            Ok(None) if client.is_closed() => break,
+                            Ok(None) => {}
+                            Err(err) => {
+                                error!("Lost connection listening for websocket events - {}", err);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(err) => error!("Failed to listen for websocket events - {}", err),
+            }
+
+            thread::sleep(LISTEN_RETRY_INTERVAL);
+        });
+        // notifications are sent one at a time from a single task, so every instance receives
+        // them in order
+        let (publisher, mut published) = unbounded::<String>();
+        let pool = self.pool.clone();
+        actix::spawn(async move {
+            // hold on to what's published until this instance is listening, so its own sessions
+            // don't miss any of it. The server carries on meanwhile.
+            if actix_rt::time::timeout(LISTEN_TIMEOUT, &mut listening)
+                .await
+                .is_err()
+            {
+                warn!("Websocket events from other instances are not being received yet");
+                let _ = listening.await;
+            }
+
+            while let Some(payload) = published.next().await {
+                if let Err(err) = notify(&pool, payload).await {
+                    error!("Failed to publish websocket event - {:?}", err);
+                }
+            }
+        });
+
+        self.publisher = Some(publisher);
+    }
+
+    fn publish(&self, msg: Broadcast) {
+        let publisher = match &self.publisher {
+            Some(publisher) => publisher,
+            None => return error!("Broadcast backend was not started"),
+        };
+
+        let payload = match serde_json::to_string(&msg) {
+            Ok(payload) => payload,
+            Err(err) => return error!("Error serializing websocket event - {:?}", err),
+        };
+
+        if publisher.unbounded_send(payload).is_err() {
+            error!("Websocket event publisher has stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Arc;
+
+    use actix::Actor;
+    use actix_web::{web::Data, App};
+    use actix_web_actors::ws;
+    use awc::Client;
+    use diesel_async::RunQueryDsl;
+    use futures::SinkExt;
+
+    use auth::{get_identity_service, PrivateClaim, Role};
+    use db::{
+        get_conn,
+        models::{Game, NewUser, User, UserDetails},
+        new_pool,
+        repository::{DbRepository, Repository},
+        schema::{games, users},
+    };
+
+    use super::{PostgresBackend, MAX_NOTIFY_PAYLOAD};
+    use crate::config::WebsocketConfig;
+    use crate::handlers::PlayerDetails;
+    use crate::routes::routes;
+    use crate::tests::helpers::tests::{get_auth_token, insert_each, read_until_path};
+    use crate::websocket::{PresenceUpdate, Server, Topic};
+
+    #[derive(Insertable)]
+    #[diesel(table_name = games)]
+    struct NewGame {
+        slug: String,
+    }
+
+    fn start_instance() -> actix_test::TestServer {
+        actix_test::start(|| {
+            let pool = db::new_pool();
+            let backend = PostgresBackend::new(env::var("DATABASE_URL").unwrap(), pool.clone());
+            let repository: Arc<dyn Repository> = Arc::new(DbRepository::new(pool.clone()));
+            App::new()
+                .wrap(get_identity_service())
+                .app_data(Data::new(pool))
+                .app_data(Data::from(repository.clone()))
+                .app_data(Data::new(
+                    Server::with_backend(repository, Box::new(backend)).start(),
+                ))
+                .app_data(Data::new(WebsocketConfig::default()))
+                .configure(routes)
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_postgres_backend_delivers_across_instances() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let user: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: game.id,
+                user_name: "agmcleod".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let instance_one = start_instance();
+        let instance_two = start_instance();
+        let client = Client::default();
+
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        let mut owner_ws = client
+            .ws(instance_one.url("/ws/"))
+            .connect()
+            .await
+            .unwrap()
+            .1;
+        owner_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut owner_ws, Topic::Players).await;
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let mut player_ws = client
+            .ws(instance_two.url("/ws/"))
+            .connect()
+            .await
+            .unwrap()
+            .1;
+        player_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", player_token).into(),
+            ))
+            .await
+            .unwrap();
+
+        // the player joined on the other instance
+        let msg = read_until_path(&mut owner_ws, Topic::Players).await;
+        let players: Vec<UserDetails> = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].user_name, "agmcleod");
+
+        // and its own sessions still get it, by way of the database
+        read_until_path(&mut player_ws, Topic::Players).await;
+
+        drop(owner_ws);
+        drop(player_ws);
+        instance_one.stop().await;
+        instance_two.stop().await;
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_postgres_backend_delivers_large_events_across_instances() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        // enough players that the list of them doesn't fit in a notification
+        let users: Vec<User> = insert_each!(
+            &mut conn,
+            users::table,
+            (0..200)
+                .map(|i| NewUser {
+                    game_id: game.id,
+                    user_name: format!("player with a long name {}", i),
+                })
+                .collect::<Vec<NewUser>>(),
+        );
+
+        let instance_one = start_instance();
+        let instance_two = start_instance();
+        let client = Client::default();
+
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        let mut owner_ws = client
+            .ws(instance_one.url("/ws/"))
+            .connect()
+            .await
+            .unwrap()
+            .1;
+        owner_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut owner_ws, Topic::Players).await;
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            users[0].id,
+            users[0].user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let mut player_ws = client
+            .ws(instance_two.url("/ws/"))
+            .connect()
+            .await
+            .unwrap()
+            .1;
+        player_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", player_token).into(),
+            ))
+            .await
+            .unwrap();
+
+        let msg = read_until_path(&mut owner_ws, Topic::Players).await;
+        assert!(serde_json::to_string(&msg).unwrap().len() > MAX_NOTIFY_PAYLOAD);
+        let players: Vec<UserDetails> = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(players.len(), 200);
+
+        drop(owner_ws);
+        drop(player_ws);
+        instance_one.stop().await;
+        instance_two.stop().await;
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_postgres_backend_shares_presence_across_instances() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let user: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: game.id,
+                user_name: "agmcleod".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let instance_one = start_instance();
+        let instance_two = start_instance();
+        let client = Client::default();
+
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        let mut owner_ws = client
+            .ws(instance_one.url("/ws/"))
+            .connect()
+            .await
+            .unwrap()
+            .1;
+        owner_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut owner_ws, Topic::Players).await;
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let mut player_ws = client
+            .ws(instance_two.url("/ws/"))
+            .connect()
+            .await
+            .unwrap()
+            .1;
+        player_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", player_token).into(),
+            ))
+            .await
+            .unwrap();
+
+        // the player is online on the other instance
+        let msg = read_until_path(&mut owner_ws, Topic::Players).await;
+        let players: Vec<PlayerDetails> = serde_json::from_value(msg.data).unwrap();
+        assert!(players[0].online);
+        let msg = read_until_path(&mut owner_ws, Topic::Presence).await;
+        let presence: PresenceUpdate = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(
+            presence,
+            PresenceUpdate {
+                user_id: user.id,
+                online: true,
+            }
+        );
+
+        let mut res = client
+            .get(instance_one.url(&format!("/api/games/{}/players", game.id)))
+            .insert_header(("Authorization", owner_token))
+            .send()
+            .await
+            .unwrap();
+        let players: Vec<PlayerDetails> = res.json().await.unwrap();
+        assert!(players[0].online);
+
+        player_ws.close().await.unwrap();
+        drop(player_ws);
+
+        let msg = read_until_path(&mut owner_ws, Topic::Presence).await;
+        let presence: PresenceUpdate = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(
+            presence,
+            PresenceUpdate {
+                user_id: user.id,
+                online: false,
+            }
+        );
+
+        drop(owner_ws);
+        instance_one.stop().await;
+        instance_two.stop().await;
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use super::PresenceUpdate;
+
+/// Which server instances each game's players have sessions open on, so a player shows as online
+/// to everyone in the game whichever instance they're connected to
+pub struct Presence {
+    instance: String,
+    // game id -> user id -> instances the player has a session on
+    games: HashMap<i32, HashMap<i32, HashSet<String>>>,
+    // other instance -> when it was last heard from
+    last_seen: HashMap<String, Instant>,
+}
+
+impl Presence {
+    /// `instance` identifies this one, and is never expired
+    pub fn new(instance: String) -> Self {
+        Presence {
+            instance,
+            games: HashMap::new(),
+            last_seen: HashMap::new(),
+        }
+    }
+
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    pub fn online_user_ids(&self, game_id: i32) -> HashSet<i32> {
+        self.games
+            .get(&game_id)
+            .map(|users| users.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Every player with a session on this instance, as (game id, user id)
+    pub fn local(&self) -> Vec<(i32, i32)> {
+        self.on_instance(&self.instance)
+    }
+
+    fn on_instance(&self, instance: &str) -> Vec<(i32, i32)> {
+        self.games
+            .iter()
+            .flat_map(|(game_id, users)| {
+                users
+                    .iter()
+                    .filter(|(_, instances)| instances.contains(instance))
+                    .map(move |(user_id, _)| (*game_id, *user_id))
+            })
+            .collect()
+    }
+
+    /// Notes that another instance is still around. Returns true if it hadn't been heard from
+    /// before, or had been expired.
+    pub fn seen(&mut self, instance: &str, now: Instant) -> bool {
+        self.last_seen.insert(instance.to_string(), now).is_none()
+    }
+
+    /// Records the player's first session on `instance` opening, or their last one closing.
+    /// Returns the update to send the game's sessions if that changed whether they're online on
+    /// any instance.
+    pub fn set(
+        &mut self,
+        instance: &str,
+        game_id: i32,
+        user_id: i32,
+        online: bool,
+    ) -> Option<PresenceUpdate> {
+        let users = self.games.entry(game_id).or_default();
+        let changed = if online {
+            let instances = users.entry(user_id).or_default();
+            instances.insert(instance.to_string()) && instances.len() == 1
+        } else {
+            let went_offline = match users.get_mut(&user_id) {
+                Some(instances) => instances.remove(instance) && instances.is_empty(),
+                None => false,
+            };
+            if went_offline {
+                users.remove(&user_id);
+            }
+
+            went_offline
+        };
+        if users.is_empty() {
+            self.games.remove(&game_id);
+        }
+
+        if changed {
+            Some(PresenceUpdate { user_id, online })
+        } else {
+            None
+        }
+    }
+
+    /// Replaces what's known about another instance with the players it says are online on it,
+    /// returning the updates for each game whose players changed
+    pub fn replace(&mut self, instance: &str, online: &[(i32, i32)]) -> Vec<(i32, PresenceUpdate)> {
+        let online: HashSet<(i32, i32)> = online.iter().cloned().collect();
+        let known: HashSet<(i32, i32)> = self.on_instance(instance).into_iter().collect();
+
+        let left = known
+            .difference(&online)
+            .map(|(game_id, user_id)| (*game_id, *user_id, false));
+        let joined = online
+            .difference(&known)
+            .map(|(game_id, user_id)| (*game_id, *user_id, true));
+        let changes: Vec<(i32, i32, bool)> = left.chain(joined).collect();
+
+        changes
+            .into_iter()
+            .filter_map(|(game_id, user_id, online)| {
+                self.set(instance, game_id, user_id, online)
+                    .map(|update| (game_id, update))
+            })
+            .collect()
+    }
+
+    /// Forgets the instances not heard from since `cutoff`, which have likely stopped without
+    /// saying so, taking their players offline
+    pub fn expire(&mut self, cutoff: Instant) -> Vec<(i32, PresenceUpdate)> {
+        let expired: Vec<String> = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| **seen < cutoff)
+            .map(|(instance, _)| instance.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .flat_map(|instance| {
+                self.last_seen.remove(&instance);
+                self.replace(&instance, &[])
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Presence;
+    use crate::websocket::PresenceUpdate;
+
+    #[test]
+    fn test_presence_is_merged_across_instances() {
+        let mut presence = Presence::new("one".to_string());
+
+        assert_eq!(
+            presence.set("one", 1, 10, true),
+            Some(PresenceUpdate {
+                user_id: 10,
+                online: true
+            })
+        );
+        // already online on the other instance
+        assert_eq!(presence.set("two", 1, 10, true), None);
+        assert_eq!(presence.set("two", 1, 11, true).unwrap().user_id, 11);
+        assert_eq!(presence.online_user_ids(1).len(), 2);
+        assert_eq!(presence.local(), vec![(1, 10)]);
+
+        // still has a session on the other instance
+        assert_eq!(presence.set("one", 1, 10, false), None);
+        assert!(presence.online_user_ids(1).contains(&10));
+        assert!(presence.set("two", 1, 10, false).is_some());
+        assert!(!presence.online_user_ids(1).contains(&10));
+
+        let changes = presence.replace("two", &[(1, 12), (2, 10)]);
+        assert_eq!(changes.len(), 3);
+        assert!(changes.contains(&(
+            1,
+            PresenceUpdate {
+                user_id: 11,
+                online: false
+            }
+        )));
+        assert_eq!(presence.online_user_ids(2).len(), 1);
+    }
+
+    #[test]
+    fn test_presence_expires_instances_not_heard_from() {
+        let mut presence = Presence::new("one".to_string());
+        let start = Instant::now();
+
+        assert!(presence.seen("two", start));
+        assert!(!presence.seen("two", start));
+        presence.set("one", 1, 10, true);
+        presence.set("two", 1, 11, true);
+
+        // this instance is never expired
+        let changes = presence.expire(start + Duration::from_secs(1));
+        assert_eq!(
+            changes,
+            vec![(
+                1,
+                PresenceUpdate {
+                    user_id: 11,
+                    online: false
+                }
+            )]
+        );
+        assert_eq!(presence.online_user_ids(1).len(), 1);
+        assert!(presence.seen("two", start));
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use errors::Error;
+
+use super::MessageToClient;
+use crate::handlers::Answer;
+
+/// Bumped whenever a breaking change is made to the messages below
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The kinds of events the server pushes to clients. Serialized as the event's path.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Topic {
+    #[serde(rename = "/game-status")]
+    GameStatus,
+    #[serde(rename = "/round-status")]
+    RoundStatus,
+    #[serde(rename = "/picks")]
+    Picks,
+    #[serde(rename = "/pick-distribution")]
+    PickDistribution,
+    #[serde(rename = "/players")]
+    Players,
+    #[serde(rename = "/presence")]
+    Presence,
+    #[serde(rename = "/leaderboard")]
+    Leaderboard,
+    #[serde(rename = "/chat")]
+    Chat,
+    #[serde(rename = "/chat-deleted")]
+    ChatDeleted,
+    #[serde(rename = "/reactions")]
+    Reactions,
+}
+
+impl Topic {
+    /// Ephemeral events aren't kept for replay, they're stale by the time a client reconnects
+    pub fn is_ephemeral(&self) -> bool {
+        matches!(self, Topic::Reactions)
+    }
+}
+
+/// The reactions the crowd can send during a round
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Emote {
+    Gg,
+    Pog,
+    Cheese,
+    Hype,
+    Rip,
+}
+
+/// Sent on `/reactions` with how many of each emote were sent since the last burst
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ReactionBurst {
+    pub counts: BTreeMap<Emote, u32>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Version handshake, answered with the server's `Hello`
+    Hello {
+        version: u32,
+    },
+    /// `last_seq` and `epoch` are from the last event seen before reconnecting. Anything missed
+    /// since is replayed, or the current state is sent if too much was missed, or the epoch
+    /// doesn't match.
+    Auth {
+        token: String,
+        #[serde(default)]
+        last_seq: Option<u64>,
+        #[serde(default)]
+        epoch: Option<String>,
+    },
+    /// Limits the events this session receives to the given topics
+    Subscribe {
+        topics: Vec<Topic>,
+    },
+    /// Saves the player's picks for the open round, same as `POST /api/rounds/set-picks`
+    SubmitPicks {
+        answers: Vec<Answer>,
+    },
+    /// Replaces the player's picks for the open round
+    UpdatePicks {
+        answers: Vec<Answer>,
+    },
+    /// Posts a message to the game's chat, sent to everyone on `/chat`
+    SendChat {
+        body: String,
+    },
+    /// An emote shown to everyone in the game. Not saved, and throttled per session.
+    React {
+        emote: Emote,
+    },
+    Ping,
+    /// Acknowledges server events up to `seq`
+    Ack {
+        seq: u64,
+    },
+}
+
+/// A client message with an optional id, which is echoed back on the reply to it
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct ClientFrame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub message: ClientMessage,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidMessage,
+    UnsupportedVersion,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    BadRequest,
+    Conflict,
+    ValidationFailed,
+    RateLimited,
+    InternalError,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct ErrorFrame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorFrame {
+    pub fn new(id: Option<u64>, code: ErrorCode, message: &str) -> Self {
+        ErrorFrame {
+            id,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// Mirrors how `errors::Error` is turned into an HTTP response
+    pub fn from_error(id: Option<u64>, error: &Error) -> Self {
+        match error {
+            Error::BadRequest(message) => ErrorFrame::new(id, ErrorCode::BadRequest, message),
+            Error::NotFound(message) => ErrorFrame::new(id, ErrorCode::NotFound, message),
+            Error::Conflict(message) => ErrorFrame::new(id, ErrorCode::Conflict, message),
+            Error::UnprocessableEntity(message) => {
+                ErrorFrame::new(id, ErrorCode::ValidationFailed, message)
+            }
+            Error::ValidationError(messages) => {
+                ErrorFrame::new(id, ErrorCode::ValidationFailed, &messages.join(", "))
+            }
+            Error::Unauthorized | Error::CannotDecodeJwtToken(_) => {
+                ErrorFrame::new(id, ErrorCode::Unauthorized, "Unauthorized")
+            }
+            Error::Forbidden => ErrorFrame::new(id, ErrorCode::Forbidden, "Forbidden"),
+            _ => {
+                error!("Internal server error: {:?}", error);
+                ErrorFrame::new(id, ErrorCode::InternalError, "Internal Server Error")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Hello {
+        version: u32,
+    },
+    Event(MessageToClient),
+    Ack {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+    },
+    Pong {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+    },
+    Error(ErrorFrame),
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, json};
+
+    use errors::Error;
+
+    use super::{ClientFrame, ClientMessage, ErrorCode, ErrorFrame, ServerMessage, Topic};
+    use crate::websocket::MessageToClient;
+
+    #[test]
+    fn test_parses_tagged_client_frames() {
+        let frame: ClientFrame = serde_json::from_str(r#"{"type":"ping","id":3}"#).unwrap();
+        assert_eq!(frame.id, Some(3));
+        assert_eq!(frame.message, ClientMessage::Ping);
+
+        let frame: ClientFrame =
+            serde_json::from_str(r#"{"type":"subscribe","topics":["/players","/picks"]}"#).unwrap();
+        assert_eq!(frame.id, None);
+        assert_eq!(
+            frame.message,
+            ClientMessage::Subscribe {
+                topics: vec![Topic::Players, Topic::Picks]
+            }
+        );
+
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"type":"launch_nukes"}"#).is_err());
+    }
+
+    #[test]
+    fn test_serializes_events_with_path() {
+        let msg = ServerMessage::Event(MessageToClient::new(Topic::Players, 1, json!([])));
+        assert_eq!(
+            serde_json::to_value(msg).unwrap(),
+            json!({"type": "event", "path": "/players", "game_id": 1, "data": [], "seq": 0})
+        );
+    }
+
+    #[test]
+    fn test_error_frame_from_validation_error() {
+        let error = Error::ValidationError(vec!["name is required".to_string()]);
+        let frame = ErrorFrame::from_error(Some(1), &error);
+        assert_eq!(frame.code, ErrorCode::ValidationFailed);
+        assert_eq!(frame.message, "name is required");
+        assert_eq!(
+            serde_json::to_value(ServerMessage::Error(frame)).unwrap(),
+            json!({"type": "error", "id": 1, "code": "validation_failed", "message": "name is required"})
+        );
+    }
+}
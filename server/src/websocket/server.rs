@@ -1,148 +1,443 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use actix::prelude::{Actor, Context, Handler, Message as ActixMessage, Recipient};
+use actix::prelude::{
+    fut, Actor, ActorFutureExt, Addr, Arbiter, AsyncContext, Context, Handler,
+    Message as ActixMessage, MessageResult, Recipient, ResponseActFuture, WrapFuture,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::{error::Result as SerdeResult, to_string, to_value, Value};
+use serde_json::{to_value, Value};
+use tracing::Span;
+use uuid::Uuid;
 
-use auth::decode_jwt;
-use db::{get_conn, models::User, PgPool};
+use auth::{decode_jwt, PrivateClaim, Role};
+use db::repository::Repository;
 use errors::Error;
 
+use crate::handlers::verify_claim;
+
+use super::{
+    Broadcast, BroadcastBackend, Deliver, Emote, GameServer, InProcessBackend, Join, Leave,
+    Presence, PresenceUpdate, ReactionBurst, ResumeFrom, StopGame, Topic,
+};
+
+/// How long a game's actor, and the messages it can replay, stick around once nobody is connected
+const GAME_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Reactions are collected for this long, then sent to each game as one message
+const REACTION_BURST_INTERVAL: Duration = Duration::from_millis(500);
+/// How often each instance tells the others which players are connected to it. An instance not
+/// heard from for a few of these is taken to have stopped, and its players go offline.
+const PRESENCE_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+const PRESENCE_SNAPSHOTS_MISSED: u32 = 3;
+
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
 pub struct Message(pub String);
 
-#[derive(ActixMessage, Deserialize, Serialize)]
+/// Tells a session to close, once it's been removed from its game
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Close;
+
+/// Where a session's events are sent. Websockets are sent the frame already serialized, once for
+/// all of a game's sessions, while event streams are sent the event to format themselves.
+#[derive(Clone)]
+pub enum SessionRecipient {
+    Websocket {
+        events: Recipient<Message>,
+        close: Recipient<Close>,
+    },
+    EventStream {
+        events: Recipient<MessageToClient>,
+        close: Recipient<Close>,
+    },
+}
+
+impl SessionRecipient {
+    pub fn close(&self) {
+        match self {
+            SessionRecipient::Websocket { close, .. }
+            | SessionRecipient::EventStream { close, .. } => close.do_send(Close),
+        }
+    }
+}
+
+#[derive(ActixMessage, Clone, Debug, Deserialize, Serialize)]
 #[rtype(result = "()")]
 pub struct MessageToClient {
-    pub path: String,
+    pub path: Topic,
     pub data: Value,
     pub game_id: i32,
+    /// Position in the game's stream of messages, assigned by the server when it's sent
+    #[serde(default)]
+    pub seq: u64,
+    /// Which stream `seq` counts in. It starts over whenever the game's actor does, so a client
+    /// resuming from another epoch is sent a snapshot instead.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub epoch: String,
+    /// The span the message was sent from, so the actors it passes through log in it too. Isn't
+    /// sent to clients or other instances.
+    #[serde(skip, default = "Span::none")]
+    pub span: Span,
 }
 
 impl MessageToClient {
-    pub fn new(path: &str, game_id: i32, data: Value) -> MessageToClient {
+    pub fn new(path: Topic, game_id: i32, data: Value) -> MessageToClient {
         Self {
-            path: path.to_string(),
+            path,
             data,
             game_id,
+            seq: 0,
+            epoch: String::new(),
+            span: Span::current(),
         }
     }
 }
 
-struct Session {
-    addr: Recipient<Message>,
-    token: Option<String>,
-    // should only be one, but lets track multiple in case
-    game_ids: Vec<i32>,
+/// Which of a game's sessions a message is delivered to
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Target {
+    Game,
+    Role(Role),
+    /// A single player, by their users id
+    User(i32),
+    /// A single websocket session, by its id. Only ever delivered by this instance.
+    Session(String),
 }
 
-impl Session {
-    fn new(addr: Recipient<Message>) -> Self {
-        Session {
-            addr,
-            token: None,
-            game_ids: Vec::new(),
+impl Target {
+    pub fn matches(&self, session_id: &str, claim: &PrivateClaim) -> bool {
+        match self {
+            Target::Game => true,
+            Target::Role(role) => claim.role == *role,
+            // owner & spectator claims use the game id as their id, so only match players
+            Target::User(user_id) => claim.role == Role::Player && claim.id == *user_id,
+            Target::Session(id) => id == session_id,
         }
     }
 }
 
+#[derive(ActixMessage, Deserialize, Serialize)]
+#[rtype(result = "()")]
+pub struct TargetedMessageToClient {
+    pub target: Target,
+    pub message: MessageToClient,
+}
+
+impl TargetedMessageToClient {
+    pub fn new(target: Target, message: MessageToClient) -> Self {
+        TargetedMessageToClient { target, message }
+    }
+}
+
+/// One event with a different message for each of its targets, like the round status with each
+/// player's own `picks_chosen`. The messages share a seq, and take a single slot in the game's
+/// replay buffer however many players there are.
+#[derive(ActixMessage, Deserialize, Serialize)]
+#[rtype(result = "()")]
+pub struct TargetedMessagesToClient {
+    pub game_id: i32,
+    pub messages: Vec<TargetedMessageToClient>,
+}
+
+struct Session {
+    addr: SessionRecipient,
+    game_id: Option<i32>,
+    // subscriptions made before authenticating, handed to the game once joined
+    topics: Option<HashSet<Topic>>,
+}
+
+struct Game {
+    addr: Addr<GameServer>,
+    sessions: usize,
+}
+
+/// Supervises the websocket sessions on this instance. Each game's sessions are handed off to a
+/// `GameServer` actor, which does the work of delivering that game's messages.
 pub struct Server {
-    game_to_sessions: HashMap<i32, Vec<String>>,
-    pool: PgPool,
+    arbiters: Vec<Arbiter>,
+    backend: Box<dyn BroadcastBackend>,
+    games: HashMap<i32, Game>,
+    messages_broadcast: u64,
+    next_arbiter: usize,
+    presence: Presence,
+    // game id -> reactions waiting for the next burst
+    reactions: HashMap<i32, ReactionBurst>,
+    repository: Arc<dyn Repository>,
     sessions: HashMap<String, Session>,
 }
 
 impl Server {
-    pub fn new(pool: PgPool) -> Self {
+    pub fn new(repository: Arc<dyn Repository>) -> Self {
+        Server::with_backend(repository, Box::new(InProcessBackend::default()))
+    }
+
+    pub fn with_backend(
+        repository: Arc<dyn Repository>,
+        backend: Box<dyn BroadcastBackend>,
+    ) -> Self {
         Server {
-            game_to_sessions: HashMap::new(),
-            pool,
+            arbiters: Vec::new(),
+            backend,
+            games: HashMap::new(),
+            messages_broadcast: 0,
+            next_arbiter: 0,
+            presence: Presence::new(Uuid::new_v4().simple().to_string()),
+            reactions: HashMap::new(),
+            repository,
             sessions: HashMap::new(),
         }
     }
 
-    fn send_msg_to_game_sessions(&self, game_id: &i32, data: SerdeResult<String>) {
-        if let Some(session_ids) = self.game_to_sessions.get(game_id) {
-            for id in session_ids {
-                if let Some(session) = self.sessions.get(id) {
-                    if let Ok(ref data) = data {
-                        match session.addr.try_send(Message(data.clone())) {
-                            Err(err) => {
-                                error!("Error sending client message: {:?}", err);
-                            }
-                            _ => {}
-                        }
+    /// Starts the game's actor if it isn't running, spreading games across a thread per core
+    fn get_or_start_game(&mut self, game_id: i32, ctx: &mut Context<Self>) -> &mut Game {
+        if self.arbiters.is_empty() {
+            let threads = thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1);
+            self.arbiters = (0..threads).map(|_| Arbiter::new()).collect();
+        }
+
+        let arbiter = &self.arbiters[self.next_arbiter % self.arbiters.len()];
+        let repository = self.repository.clone();
+        let server = ctx.address();
+        let next_arbiter = &mut self.next_arbiter;
+        self.games.entry(game_id).or_insert_with(|| {
+            *next_arbiter += 1;
+            Game {
+                addr: GameServer::start_in_arbiter(&arbiter.handle(), move |_| {
+                    GameServer::new(game_id, repository, server)
+                }),
+                sessions: 0,
+            }
+        })
+    }
+
+    /// Hands an authenticated session to its game, leaving any game it was in before
+    fn join_game(
+        &mut self,
+        msg: Auth,
+        private_claim: PrivateClaim,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), Error> {
+        let (addr, previous_game_id, topics) = match self.sessions.get(&msg.id) {
+            Some(session) => (
+                session.addr.clone(),
+                session.game_id,
+                session.topics.clone(),
+            ),
+            None => {
+                error!("Session not found: {}", msg.id);
+                return Err(Error::NotFound("Session not found".to_string()));
+            }
+        };
+
+        let game_id = private_claim.game_id;
+        match previous_game_id {
+            Some(previous_game_id) if previous_game_id == game_id => {}
+            // a session only belongs to one game, so switching games leaves the last one
+            Some(previous_game_id) => {
+                self.leave_game(&msg.id, previous_game_id, ctx);
+                self.get_or_start_game(game_id, ctx).sessions += 1;
+            }
+            None => self.get_or_start_game(game_id, ctx).sessions += 1,
+        }
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.game_id = Some(game_id);
+        }
+
+        self.get_or_start_game(game_id, ctx).addr.do_send(Join {
+            id: msg.id,
+            addr,
+            claim: private_claim,
+            topics,
+            resume: msg.resume,
+        });
+
+        Ok(())
+    }
+
+    fn leave_game(&mut self, session_id: &str, game_id: i32, ctx: &mut Context<Self>) {
+        let game = match self.games.get_mut(&game_id) {
+            Some(game) => game,
+            None => return,
+        };
+        game.addr.do_send(Leave {
+            id: session_id.to_string(),
+        });
+        game.sessions -= 1;
+
+        if game.sessions == 0 {
+            ctx.run_later(GAME_IDLE_TIMEOUT, move |act, _| {
+                if let Some(game) = act.games.get(&game_id) {
+                    if game.sessions == 0 {
+                        game.addr.do_send(StopGame);
+                        act.games.remove(&game_id);
                     }
                 }
+            });
+        }
+    }
+
+    /// Sends a message to the game's sessions on every instance
+    fn broadcast(&mut self, target: Target, msg: MessageToClient) {
+        let span = msg.span.clone();
+        let _entered = span.enter();
+        self.messages_broadcast += 1;
+        match target {
+            Target::Session(_) => self.deliver(
+                msg.game_id,
+                Broadcast::Message(TargetedMessageToClient::new(target, msg)),
+            ),
+            _ => self
+                .backend
+                .publish(Broadcast::Message(TargetedMessageToClient::new(
+                    target, msg,
+                ))),
+        }
+    }
+
+    fn send_reactions(&mut self) {
+        for (game_id, burst) in self.reactions.drain().collect::<Vec<_>>() {
+            match serde_json::to_value(burst) {
+                Ok(value) => self.broadcast(
+                    Target::Game,
+                    MessageToClient::new(Topic::Reactions, game_id, value),
+                ),
+                Err(err) => error!("Error serializing reactions - {:?}", err),
+            }
+        }
+    }
+
+    /// Hands a broadcast to the game's actor, if anyone is connected to the game on this instance
+    fn deliver(&self, game_id: i32, msg: Broadcast) {
+        if let Some(game) = self.games.get(&game_id) {
+            game.addr.do_send(Deliver(msg));
+        }
+    }
+
+    /// Tells the game's sessions on this instance that a player came online or went offline, for
+    /// changes every instance works out for itself, like another instance stopping
+    fn send_presence(&self, game_id: i32, update: PresenceUpdate) {
+        match to_value(update) {
+            Ok(value) => self.deliver(
+                game_id,
+                Broadcast::Message(TargetedMessageToClient::new(
+                    Target::Game,
+                    MessageToClient::new(Topic::Presence, game_id, value),
+                )),
+            ),
+            Err(err) => error!("Error serializing presence - {:?}", err),
+        }
+    }
+
+    fn publish_presence_snapshot(&self) {
+        self.backend.publish(Broadcast::PresenceSnapshot {
+            instance: self.presence.instance().to_string(),
+            online: self.presence.local(),
+        });
+    }
+
+    /// An instance heard from for the first time is sent this one's players, so it catches up
+    fn heard_from(&mut self, instance: &str) {
+        if self.presence.seen(instance, Instant::now()) {
+            self.publish_presence_snapshot();
+        }
+    }
+
+    fn expire_presence(&mut self) {
+        let timeout = PRESENCE_SNAPSHOT_INTERVAL * PRESENCE_SNAPSHOTS_MISSED;
+        if let Some(cutoff) = Instant::now().checked_sub(timeout) {
+            for (game_id, update) in self.presence.expire(cutoff) {
+                self.send_presence(game_id, update);
             }
-        } else {
-            warn!("Could not find session by game: {}", *game_id);
         }
     }
 }
 
 impl Actor for Server {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.backend.start(ctx.address().recipient());
+        // lets the instances already running know about this one, so they send their players
+        self.publish_presence_snapshot();
+        ctx.run_interval(PRESENCE_SNAPSHOT_INTERVAL, |act, _| {
+            act.publish_presence_snapshot();
+            act.expire_presence();
+        });
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        for arbiter in &self.arbiters {
+            arbiter.stop();
+        }
+    }
 }
 
 pub struct Auth {
     pub id: String,
     pub token: String,
+    /// Where the client left off before it reconnected
+    pub resume: Option<ResumeFrom>,
 }
 
 impl ActixMessage for Auth {
-    type Result = Result<(), Error>;
+    type Result = Result<PrivateClaim, Error>;
 }
 
 impl Handler<Auth> for Server {
-    type Result = Result<(), Error>;
+    type Result = ResponseActFuture<Self, Result<PrivateClaim, Error>>;
 
     fn handle(&mut self, msg: Auth, _: &mut Context<Self>) -> Self::Result {
-        let private_claim = decode_jwt(&msg.token);
-        if private_claim.is_ok() {
-            if !self.sessions.contains_key(&msg.id) {
-                error!("Session not found: {}", msg.id);
-                return Ok(());
-            }
-            let current_session = self.sessions.get_mut(&msg.id).unwrap();
-            current_session.token = Some(msg.token.clone());
-            let private_claim = private_claim.unwrap();
-            if !self.game_to_sessions.contains_key(&private_claim.game_id) {
-                self.game_to_sessions
-                    .insert(private_claim.game_id, Vec::new());
-            }
-
-            let sessions_for_game = self
-                .game_to_sessions
-                .get_mut(&private_claim.game_id)
-                .unwrap();
+        let private_claim = match decode_jwt(&msg.token) {
+            Ok(private_claim) => private_claim,
+            Err(_) => return Box::pin(fut::ready(Err(Error::Unauthorized))),
+        };
+        let repository = self.repository.clone();
 
-            // already authenticated
-            if sessions_for_game.contains(&msg.id) {
-                return Ok(());
+        Box::pin(
+            async move {
+                verify_claim(repository.as_ref(), &private_claim)
+                    .await
+                    .map(|_| private_claim)
             }
-            sessions_for_game.push(msg.id.clone());
+            .into_actor(self)
+            .map(move |result: Result<PrivateClaim, Error>, act, ctx| {
+                let private_claim = result?;
+                act.join_game(msg, private_claim.clone(), ctx)?;
+                Ok(private_claim)
+            }),
+        )
+    }
+}
 
-            current_session.game_ids.push(private_claim.game_id);
+#[derive(ActixMessage, Clone)]
+#[rtype(result = "()")]
+pub struct Subscribe {
+    pub id: String,
+    pub topics: Vec<Topic>,
+}
 
-            let connection = get_conn(&self.pool)?;
+impl Handler<Subscribe> for Server {
+    type Result = ();
 
-            let users = User::find_all_by_game_id(&connection, private_claim.game_id)?;
-            if let Ok(value) = to_value(users) {
-                let msg = MessageToClient::new("/players", private_claim.game_id, value);
-                self.send_msg_to_game_sessions(&msg.game_id, to_string(&msg));
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) {
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            session.topics = Some(msg.topics.iter().cloned().collect());
+            if let Some(game) = session.game_id.and_then(|game_id| self.games.get(&game_id)) {
+                game.addr.do_send(msg);
             }
         }
-
-        Ok(())
     }
 }
 
 #[derive(ActixMessage)]
 #[rtype(result = "()")]
 pub struct Connect {
-    pub addr: Recipient<Message>,
+    pub addr: SessionRecipient,
     pub id: String,
 }
 
@@ -150,7 +445,14 @@ impl Handler<Connect> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) {
-        self.sessions.insert(msg.id.clone(), Session::new(msg.addr));
+        self.sessions.insert(
+            msg.id,
+            Session {
+                addr: msg.addr,
+                game_id: None,
+                topics: None,
+            },
+        );
     }
 }
 
@@ -163,8 +465,138 @@ pub struct Disconnect {
 impl Handler<Disconnect> for Server {
     type Result = ();
 
-    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        self.sessions.remove(&msg.id);
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) {
+        if let Some(Session {
+            game_id: Some(game_id),
+            ..
+        }) = self.sessions.remove(&msg.id)
+        {
+            self.leave_game(&msg.id, game_id, ctx);
+        }
+    }
+}
+
+/// A reaction from one of the game's sessions, already throttled by the session
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct React {
+    pub game_id: i32,
+    pub emote: Emote,
+}
+
+impl Handler<React> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: React, ctx: &mut Context<Self>) {
+        // the first reaction since the last burst schedules the next one
+        if self.reactions.is_empty() {
+            ctx.run_later(REACTION_BURST_INTERVAL, |act, _| act.send_reactions());
+        }
+
+        *self
+            .reactions
+            .entry(msg.game_id)
+            .or_default()
+            .counts
+            .entry(msg.emote)
+            .or_insert(0) += 1;
+    }
+}
+
+/// Closes a kicked player's sessions, on every instance
+#[derive(ActixMessage)]
+#[rtype(result = "()")]
+pub struct Kick {
+    pub game_id: i32,
+    pub user_id: i32,
+}
+
+impl Handler<Kick> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Kick, _: &mut Context<Self>) {
+        self.backend.publish(Broadcast::Kick {
+            game_id: msg.game_id,
+            user_id: msg.user_id,
+        });
+    }
+}
+
+/// Sent by a game's actor when a player's first session in the game on this instance opens, or
+/// their last one closes. Replies with the update for the game's sessions if that changed whether
+/// the player is online on any instance, which the game's actor then sends to all of them.
+#[derive(ActixMessage)]
+#[rtype(result = "Option<PresenceUpdate>")]
+pub struct SetPresence {
+    pub game_id: i32,
+    pub user_id: i32,
+    pub online: bool,
+}
+
+impl Handler<SetPresence> for Server {
+    type Result = MessageResult<SetPresence>;
+
+    fn handle(&mut self, msg: SetPresence, _: &mut Context<Self>) -> Self::Result {
+        let instance = self.presence.instance().to_string();
+        let update = self
+            .presence
+            .set(&instance, msg.game_id, msg.user_id, msg.online);
+
+        self.backend.publish(Broadcast::Presence {
+            instance,
+            game_id: msg.game_id,
+            user_id: msg.user_id,
+            online: msg.online,
+        });
+
+        MessageResult(update)
+    }
+}
+
+/// Ids of the players in a game with at least one open session, on any instance
+#[derive(ActixMessage)]
+#[rtype(result = "HashSet<i32>")]
+pub struct GetOnlinePlayers {
+    pub game_id: i32,
+}
+
+impl Handler<GetOnlinePlayers> for Server {
+    type Result = MessageResult<GetOnlinePlayers>;
+
+    fn handle(&mut self, msg: GetOnlinePlayers, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.presence.online_user_ids(msg.game_id))
+    }
+}
+
+/// What the server is doing, for metrics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerStats {
+    /// Open sessions, whether or not they've joined a game
+    pub sessions: usize,
+    /// Games with sessions joined to them on this instance. Not broken down by game, as there's
+    /// no limit to how many there are.
+    pub games: usize,
+    /// Sessions joined to a game
+    pub game_sessions: usize,
+    /// Messages sent to games since the server started, counted once however many sessions get
+    /// them
+    pub messages_broadcast: u64,
+}
+
+#[derive(ActixMessage)]
+#[rtype(result = "ServerStats")]
+pub struct GetStats;
+
+impl Handler<GetStats> for Server {
+    type Result = MessageResult<GetStats>;
+
+    fn handle(&mut self, _: GetStats, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(ServerStats {
+            sessions: self.sessions.len(),
+            games: self.games.values().filter(|game| game.sessions > 0).count(),
+            game_sessions: self.games.values().map(|game| game.sessions).sum(),
+            messages_broadcast: self.messages_broadcast,
+        })
     }
 }
 
@@ -172,7 +604,67 @@ impl Handler<MessageToClient> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: MessageToClient, _: &mut Context<Self>) -> Self::Result {
-        self.send_msg_to_game_sessions(&msg.game_id, to_string(&msg));
+        self.broadcast(Target::Game, msg);
+    }
+}
+
+impl Handler<TargetedMessageToClient> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: TargetedMessageToClient, _: &mut Context<Self>) -> Self::Result {
+        self.broadcast(msg.target, msg.message);
+    }
+}
+
+impl Handler<TargetedMessagesToClient> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: TargetedMessagesToClient, _: &mut Context<Self>) -> Self::Result {
+        let span = msg
+            .messages
+            .first()
+            .map(|msg| msg.message.span.clone())
+            .unwrap_or_else(Span::none);
+        let _entered = span.enter();
+        self.messages_broadcast += 1;
+        self.backend.publish(Broadcast::Messages(msg));
+    }
+}
+
+impl Handler<Deliver> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Deliver, _: &mut Context<Self>) -> Self::Result {
+        match msg.0 {
+            Broadcast::Message(msg) => {
+                self.deliver(msg.message.game_id, Broadcast::Message(msg));
+            }
+            Broadcast::Messages(msg) => {
+                self.deliver(msg.game_id, Broadcast::Messages(msg));
+            }
+            Broadcast::Kick { game_id, user_id } => {
+                self.deliver(game_id, Broadcast::Kick { game_id, user_id });
+            }
+            // this instance's own presence was recorded when it was published
+            Broadcast::Presence { instance, .. } | Broadcast::PresenceSnapshot { instance, .. }
+                if instance == self.presence.instance() => {}
+            Broadcast::Presence {
+                instance,
+                game_id,
+                user_id,
+                online,
+            } => {
+                self.heard_from(&instance);
+                // the instance the player's session is on tells the game's sessions
+                self.presence.set(&instance, game_id, user_id, online);
+            }
+            Broadcast::PresenceSnapshot { instance, online } => {
+                self.heard_from(&instance);
+                for (game_id, update) in self.presence.replace(&instance, &online) {
+                    self.send_presence(game_id, update);
+                }
+            }
+        }
     }
 }
 
@@ -180,22 +672,30 @@ impl Handler<MessageToClient> for Server {
 mod tests {
     use actix_web_actors::ws;
     use awc::Client;
-    use diesel::RunQueryDsl;
+    use diesel::ExpressionMethods;
+    use diesel_async::RunQueryDsl;
     use futures::{SinkExt, StreamExt};
     use serde_json;
 
     use auth::{PrivateClaim, Role};
     use db::{
         get_conn,
-        models::{Game, NewUser, User, UserDetails},
+        models::{
+            Game, NewGameQuestion, NewRound, NewUser, NewUserQuestion, Question, User, UserDetails,
+        },
         new_pool,
-        schema::{games, users},
+        schema::{game_questions, games, questions, rounds, user_questions, users},
     };
 
-    use crate::tests::helpers::tests::{get_auth_token, get_test_server, get_websocket_frame_data};
+    use crate::handlers::{PlayerDetails, RoundStatusRepsonse};
+    use crate::tests::helpers::tests::{
+        get_auth_token, get_test_server, get_websocket_frame_data, insert_each, read_until_path,
+    };
+    use crate::websocket::PresenceUpdate;
+    use crate::websocket::Topic;
 
     #[derive(Insertable)]
-    #[table_name = "games"]
+    #[diesel(table_name = games)]
     struct NewGame {
         slug: String,
     }
@@ -203,13 +703,14 @@ mod tests {
     #[actix_rt::test]
     async fn test_ws_auth_broadcast_no_users() {
         let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+        let mut conn = get_conn(&pool).await.unwrap();
 
         let game: Game = diesel::insert_into(games::table)
             .values(NewGame {
                 slug: "abc123".to_string(),
             })
-            .get_result(&conn)
+            .get_result(&mut conn)
+            .await
             .unwrap();
 
         let srv = get_test_server();
@@ -239,7 +740,7 @@ mod tests {
         let data = get_websocket_frame_data(msg.unwrap().unwrap());
         if data.is_some() {
             let msg = data.unwrap();
-            assert_eq!(msg.path, "/players");
+            assert_eq!(msg.path, Topic::Players);
             assert_eq!(msg.game_id, game.id);
             let players = msg.data.as_array().unwrap();
             assert_eq!(players.len(), 0);
@@ -250,19 +751,23 @@ mod tests {
         drop(stream);
 
         srv.stop().await;
-        diesel::delete(games::table).execute(&conn).unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
     }
 
     #[actix_rt::test]
     async fn test_ws_auth_broadcasts_users() {
         let pool = new_pool();
-        let conn = get_conn(&pool).unwrap();
+        let mut conn = get_conn(&pool).await.unwrap();
 
         let game: Game = diesel::insert_into(games::table)
             .values(NewGame {
                 slug: "abc123".to_string(),
             })
-            .get_result(&conn)
+            .get_result(&mut conn)
+            .await
             .unwrap();
 
         let srv = get_test_server();
@@ -275,7 +780,8 @@ mod tests {
                 game_id: game.id,
                 user_name: "agmcleod".to_string(),
             })
-            .get_result(&conn)
+            .get_result(&mut conn)
+            .await
             .unwrap();
 
         // player joins
@@ -300,7 +806,7 @@ mod tests {
         let data = get_websocket_frame_data(msg.unwrap().unwrap());
         if data.is_some() {
             let msg = data.unwrap();
-            assert_eq!(msg.path, "/players");
+            assert_eq!(msg.path, Topic::Players);
             assert_eq!(msg.game_id, game.id);
             let players = msg.data.as_array().unwrap();
             assert_eq!(players.len(), 1);
@@ -315,7 +821,654 @@ mod tests {
         drop(stream);
 
         srv.stop().await;
-        diesel::delete(users::table).execute(&conn).unwrap();
-        diesel::delete(games::table).execute(&conn).unwrap();
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_auth_spectator_receives_users() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: game.id,
+                user_name: "agmcleod".to_string(),
+            })
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+
+        let client = Client::default();
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap();
+
+        let token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Spectator,
+        ));
+
+        ws_conn
+            .1
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", token).into(),
+            ))
+            .await
+            .unwrap();
+
+        let mut stream = ws_conn.1.take(1);
+
+        let msg = stream.next().await;
+        let data = get_websocket_frame_data(msg.unwrap().unwrap());
+        if let Some(msg) = data {
+            assert_eq!(msg.path, Topic::Players);
+            assert_eq!(msg.game_id, game.id);
+            // the spectator isn't listed as a player
+            let players = msg.data.as_array().unwrap();
+            assert_eq!(players.len(), 1);
+        } else {
+            panic!("Message was not a string");
+        }
+
+        drop(stream);
+
+        srv.stop().await;
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_round_status_personalized_per_player() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let question: Question = diesel::insert_into(questions::table)
+            .values(questions::dsl::body.eq("Who will win?"))
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        diesel::insert_into(game_questions::table)
+            .values(NewGameQuestion {
+                game_id: game.id,
+                question_id: question.id,
+            })
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let round: db::models::Round = diesel::insert_into(rounds::table)
+            .values(NewRound {
+                player_one: "maru".to_string(),
+                player_two: "serral".to_string(),
+                game_id: game.id,
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let picked: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: game.id,
+                user_name: "picked".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+        let not_picked: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: game.id,
+                user_name: "not_picked".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        diesel::insert_into(user_questions::table)
+            .values(NewUserQuestion {
+                user_id: picked.id,
+                question_id: question.id,
+                round_id: round.id,
+                answer: "maru".to_string(),
+            })
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        let tokens = vec![
+            owner_token.clone(),
+            get_auth_token(PrivateClaim::new(
+                picked.id,
+                picked.user_name.clone(),
+                game.id,
+                Role::Player,
+            )),
+            get_auth_token(PrivateClaim::new(
+                not_picked.id,
+                not_picked.user_name.clone(),
+                game.id,
+                Role::Player,
+            )),
+        ];
+
+        let mut connections = Vec::new();
+        for token in &tokens {
+            let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+            ws_conn
+                .send(ws::Message::Text(
+                    format!("/auth {{\"token\":\"{}\"}}", token).into(),
+                ))
+                .await
+                .unwrap();
+            read_until_path(&mut ws_conn, Topic::Players).await;
+            connections.push(ws_conn);
+        }
+
+        let res = srv
+            .post("/api/rounds/lock")
+            .append_header(("Authorization", owner_token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        let mut picks_chosen = Vec::new();
+        for ws_conn in connections.iter_mut() {
+            let msg = read_until_path(ws_conn, Topic::RoundStatus).await;
+            let round_status: RoundStatusRepsonse = serde_json::from_value(msg.data).unwrap();
+            assert!(round_status.locked);
+            picks_chosen.push(round_status.picks_chosen);
+        }
+
+        // owner, player with picks, player without picks
+        assert_eq!(picks_chosen, vec![false, true, false]);
+
+        drop(connections);
+
+        srv.stop().await;
+        diesel::delete(user_questions::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(rounds::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(game_questions::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(questions::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_presence() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let user: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: game.id,
+                user_name: "agmcleod".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        let mut owner_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        owner_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut owner_ws, Topic::Players).await;
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let mut player_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        player_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", player_token).into(),
+            ))
+            .await
+            .unwrap();
+
+        let msg = read_until_path(&mut owner_ws, Topic::Players).await;
+        let players: Vec<PlayerDetails> = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(players.len(), 1);
+        assert!(players[0].online);
+
+        let msg = read_until_path(&mut owner_ws, Topic::Presence).await;
+        let presence: PresenceUpdate = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(
+            presence,
+            PresenceUpdate {
+                user_id: user.id,
+                online: true
+            }
+        );
+
+        let get_players = || async {
+            let mut res = client
+                .get(srv.url(&format!("/api/games/{}/players", game.id)))
+                .insert_header(("Authorization", owner_token.clone()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status().as_u16(), 200);
+            res.json::<Vec<PlayerDetails>>().await.unwrap()
+        };
+
+        let players = get_players().await;
+        assert_eq!(players.len(), 1);
+        assert!(players[0].online);
+
+        // closing the connection, rather than a heartbeat timeout, takes the player offline
+        player_ws.close().await.unwrap();
+        drop(player_ws);
+
+        let msg = read_until_path(&mut owner_ws, Topic::Presence).await;
+        let presence: PresenceUpdate = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(
+            presence,
+            PresenceUpdate {
+                user_id: user.id,
+                online: false
+            }
+        );
+
+        let players = get_players().await;
+        assert!(!players[0].online);
+
+        drop(owner_ws);
+        srv.stop().await;
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_reconnect_replays_missed_messages() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let users: Vec<User> = insert_each!(
+            &mut conn,
+            users::table,
+            [
+                NewUser {
+                    game_id: game.id,
+                    user_name: "agmcleod".to_string(),
+                },
+                NewUser {
+                    game_id: game.id,
+                    user_name: "agmcleod2".to_string(),
+                },
+            ]
+        );
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let owner_token = get_auth_token(PrivateClaim::new(
+            game.id,
+            "abc123".to_string(),
+            game.id,
+            Role::Owner,
+        ));
+        let mut owner_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        owner_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut owner_ws, Topic::Players).await;
+
+        let tokens: Vec<String> = users
+            .iter()
+            .map(|user| {
+                get_auth_token(PrivateClaim::new(
+                    user.id,
+                    user.user_name.clone(),
+                    game.id,
+                    Role::Player,
+                ))
+            })
+            .collect();
+
+        let mut player_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        player_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", tokens[0]).into(),
+            ))
+            .await
+            .unwrap();
+        let last_seen = read_until_path(&mut player_ws, Topic::Presence).await;
+        player_ws.close().await.unwrap();
+        drop(player_ws);
+        read_until_path(&mut owner_ws, Topic::Presence).await;
+
+        // the second player joins while the first is gone
+        let mut other_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        other_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", tokens[1]).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut other_ws, Topic::Presence).await;
+
+        let mut player_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        player_ws
+            .send(ws::Message::Text(
+                format!(
+                    "/auth {{\"token\":\"{}\",\"last_seq\":{},\"epoch\":\"{}\"}}",
+                    tokens[0], last_seen.seq, last_seen.epoch
+                )
+                .into(),
+            ))
+            .await
+            .unwrap();
+
+        // missed: itself going offline, then the /players and /presence from the second player
+        let mut missed = Vec::new();
+        for _ in 0..3 {
+            let frame = player_ws.next().await.unwrap().unwrap();
+            missed.push(get_websocket_frame_data(frame).unwrap());
+        }
+        assert_eq!(
+            missed.iter().map(|msg| msg.path).collect::<Vec<Topic>>(),
+            vec![Topic::Presence, Topic::Players, Topic::Presence]
+        );
+        assert_eq!(
+            missed.iter().map(|msg| msg.seq).collect::<Vec<u64>>(),
+            vec![last_seen.seq + 1, last_seen.seq + 2, last_seen.seq + 3]
+        );
+        let presence: PresenceUpdate = serde_json::from_value(missed[0].data.clone()).unwrap();
+        assert_eq!(presence.user_id, users[0].id);
+        assert!(!presence.online);
+
+        // then the messages from joining again pick up where the replay left off
+        let msg = read_until_path(&mut player_ws, Topic::Players).await;
+        assert_eq!(msg.seq, last_seen.seq + 4);
+
+        drop(owner_ws);
+        drop(other_ws);
+        drop(player_ws);
+        srv.stop().await;
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_reconnect_sends_snapshot_when_replay_is_unavailable() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let game: Game = diesel::insert_into(games::table)
+            .values(NewGame {
+                slug: "abc123".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let user: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: game.id,
+                user_name: "agmcleod".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        diesel::insert_into(rounds::table)
+            .values(NewRound {
+                player_one: "maru".to_string(),
+                player_two: "zest".to_string(),
+                game_id: game.id,
+            })
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            game.id,
+            Role::Player,
+        ));
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        // the seq is from before the game's actor restarted, so even though this one has counted
+        // that far, it isn't the same stream
+        ws_conn
+            .send(ws::Message::Text(
+                format!(
+                    "/auth {{\"token\":\"{}\",\"last_seq\":0,\"epoch\":\"stale\"}}",
+                    token
+                )
+                .into(),
+            ))
+            .await
+            .unwrap();
+
+        read_until_path(&mut ws_conn, Topic::GameStatus).await;
+        let msg = read_until_path(&mut ws_conn, Topic::RoundStatus).await;
+        let round_status: RoundStatusRepsonse = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(round_status.player_names, vec!["maru", "zest"]);
+        assert!(!round_status.picks_chosen);
+
+        drop(ws_conn);
+        srv.stop().await;
+        diesel::delete(rounds::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ws_switching_games_leaves_previous_game() {
+        let pool = new_pool();
+        let mut conn = get_conn(&pool).await.unwrap();
+
+        let games: Vec<Game> = insert_each!(
+            &mut conn,
+            games::table,
+            [
+                NewGame {
+                    slug: "abc123".to_string(),
+                },
+                NewGame {
+                    slug: "def456".to_string(),
+                },
+            ]
+        );
+
+        let user: User = diesel::insert_into(users::table)
+            .values(NewUser {
+                game_id: games[0].id,
+                user_name: "agmcleod".to_string(),
+            })
+            .get_result(&mut conn)
+            .await
+            .unwrap();
+
+        let srv = get_test_server();
+        let client = Client::default();
+
+        let owner_token = get_auth_token(PrivateClaim::new(
+            games[0].id,
+            "abc123".to_string(),
+            games[0].id,
+            Role::Owner,
+        ));
+        let mut owner_ws = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        owner_ws
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", owner_token).into(),
+            ))
+            .await
+            .unwrap();
+        read_until_path(&mut owner_ws, Topic::Players).await;
+
+        let player_token = get_auth_token(PrivateClaim::new(
+            user.id,
+            user.user_name.clone(),
+            games[0].id,
+            Role::Player,
+        ));
+        let mut ws_conn = client.ws(srv.url("/ws/")).connect().await.unwrap().1;
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", player_token).into(),
+            ))
+            .await
+            .unwrap();
+        let msg = read_until_path(&mut owner_ws, Topic::Presence).await;
+        let presence: PresenceUpdate = serde_json::from_value(msg.data).unwrap();
+        assert!(presence.online);
+
+        // the same connection is used to watch another game
+        let spectator_token = get_auth_token(PrivateClaim::new(
+            games[1].id,
+            "def456".to_string(),
+            games[1].id,
+            Role::Spectator,
+        ));
+        ws_conn
+            .send(ws::Message::Text(
+                format!("/auth {{\"token\":\"{}\"}}", spectator_token).into(),
+            ))
+            .await
+            .unwrap();
+
+        let msg = read_until_path(&mut owner_ws, Topic::Presence).await;
+        let presence: PresenceUpdate = serde_json::from_value(msg.data).unwrap();
+        assert_eq!(presence.user_id, user.id);
+        assert!(!presence.online);
+
+        // skips past the players sent for the first game
+        loop {
+            let msg = read_until_path(&mut ws_conn, Topic::Players).await;
+            if msg.game_id == games[1].id {
+                break;
+            }
+        }
+
+        drop(owner_ws);
+        drop(ws_conn);
+        srv.stop().await;
+        diesel::delete(users::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        diesel::delete(games::table)
+            .execute(&mut conn)
+            .await
+            .unwrap();
     }
 }